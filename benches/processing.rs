@@ -0,0 +1,103 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::env;
+use std::fs;
+
+use transaction_manager::processing_config::*;
+use transaction_manager::transaction_manager::*;
+
+/// Build a csv input of the given number of deposits and withdrawals spread evenly across the given number of clients,
+/// with a dispute and resolve of every tenth deposit. Each client alternates between a deposit and a withdrawal,
+/// so every withdrawal has the funds to succeed.
+fn synthetic_input(transactions: u32, clients: u32) -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for id in 0..transactions {
+        let client_id = id % clients;
+        if (id / clients).is_multiple_of(2) {
+            input.push_str(&format!("deposit,{},{},10.0\n", client_id, id));
+            if id % 10 == 0 {
+                input.push_str(&format!("dispute,{},{},\n", client_id, id));
+                input.push_str(&format!("resolve,{},{},\n", client_id, id));
+            }
+        } else {
+            input.push_str(&format!("withdrawal,{},{},1.0\n", client_id, id));
+        }
+    }
+    input
+}
+
+/// Process synthetic inputs with a growing number of clients, up to the 65,536 that u16 client ids allow,
+/// to measure how the client store scales.
+///
+/// The client store stays a BTreeMap. Processing 200,000 rows took about 15 s, 233 ms, and 211 ms for 100, 10,000,
+/// and 65,536 clients. Applying the same 200,000 client lookups and one ordered pass over the accounts took 1.0, 5.7, and 7.0 ms
+/// with a BTreeMap, against 1.2, 2.7, and 5.0 ms with a HashMap whose keys are sorted for output, so a HashMap would save at most
+/// about 1% of a run while every report and the account output depend on ascending client id order. The time is instead spent
+/// copying each client, with its transaction log, for every transaction applied to it, which is why fewer, busier clients are slower.
+fn bench_process_transactions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_transactions_from_reader");
+    for clients in [100, 10_000, 65_536] {
+        let input = synthetic_input(200_000, clients);
+        group.bench_with_input(BenchmarkId::from_parameter(clients), &input, |b, input| {
+            b.iter(|| {
+                let mut output = Vec::new();
+                process_transactions_from_reader(
+                    &mut output,
+                    black_box(input.as_bytes()),
+                    &ProcessingConfig::default(),
+                )
+                .unwrap();
+                output
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Process the same input file serially and with a growing number of worker threads, to compare parallel processing
+/// against the serial path it must match.
+///
+/// On a single CPU, processing 200,000 rows for 10,000 clients took about 216 ms serially, against 231, 251, and 272 ms
+/// with 1, 2, and 4 workers: with nothing to run in parallel, each worker only adds routing and merging overhead.
+/// Any speedup depends on having spare cores for the workers while the reading thread parses rows.
+fn bench_serial_vs_parallel(c: &mut Criterion) {
+    let input_path = env::temp_dir().join("transaction-manager-bench-input.csv");
+    fs::write(&input_path, synthetic_input(200_000, 10_000)).unwrap();
+    let input_path = input_path.to_str().unwrap();
+    let config = ProcessingConfig::default();
+    let mut group = c.benchmark_group("serial_vs_parallel");
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            process_transactions_with_config(&mut output, black_box(input_path), &config).unwrap();
+            output
+        })
+    });
+    for workers in [1, 2, 4] {
+        group.bench_with_input(
+            BenchmarkId::new("parallel", workers),
+            &workers,
+            |b, workers| {
+                b.iter(|| {
+                    let mut output = Vec::new();
+                    process_transactions_parallel(
+                        &mut output,
+                        black_box(input_path),
+                        *workers,
+                        &config,
+                    )
+                    .unwrap();
+                    output
+                })
+            },
+        );
+    }
+    group.finish();
+    fs::remove_file(input_path).unwrap();
+}
+
+criterion_group!(
+    benches,
+    bench_process_transactions,
+    bench_serial_vs_parallel
+);
+criterion_main!(benches);
@@ -1,12 +1,21 @@
-use serde::{Serialize, Serializer};
-use std::collections::BTreeMap;
-use std::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize, Serializer};
 
+use crate::processing_config::*;
 use crate::transaction::*;
 use crate::transaction_error::*;
 
 /// Represents a client account with id, amounts, status, and previous transactions.
-#[derive(Clone, Debug, Serialize)]
+/// Accounts can be read back from the account csv output, in which case they have no currency or previous transactions.
+/// A client can be split into its AccountState and its transaction log, so that output never needs the log.
+/// The balance fields are public, but the available, held, total, and is_locked accessors are preferred,
+/// since they will keep working if the way balances are stored changes.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Client {
     /// Unique client ID
     #[serde(rename = "client")]
@@ -27,12 +36,216 @@ pub struct Client {
     /// Locked is true if a chargeback has been issued.
     pub locked: bool,
 
+    /// The currency of the account, taken from the first deposit that names one.
+    #[serde(skip)]
+    pub currency: Option<String>,
+
     /// A log of prevous transactions, grouped by transaction ID.
-    #[serde(skip_serializing)]
-    pub transactions: BTreeMap<u32, Vec<Transaction>>,
+    #[serde(skip)]
+    pub transactions: TransactionLog,
+
+    /// Cumulative amount withdrawn, not counting withdrawals that are disputed or have been charged back.
+    #[serde(skip)]
+    pub withdrawn_total: f64,
+
+    /// The number of later transactions applied to the account since the oldest open dispute of each group of related transactions
+    /// was raised, by group ID, which is the disputed transaction ID.
+    #[serde(skip)]
+    pub dispute_ages: BTreeMap<u32, usize>,
+
+    /// The number of disputes currently open on the account. A transaction disputed more than once counts once per open dispute.
+    #[serde(skip)]
+    pub open_dispute_count: usize,
+
+    /// The transaction ID of the chargeback that most recently locked the account. It is kept after an unlock, for compliance reporting.
+    #[serde(skip)]
+    pub locked_by: Option<u32>,
+
+    /// The ID of the first withdrawal, dispute, or resolve that took available funds below zero, if any has.
+    #[serde(skip)]
+    pub first_overdraft_tx: Option<u32>,
+
+    /// Frozen is true if a freeze transaction has blocked all activity on the account. It is separate from locked,
+    /// which only a chargeback sets, and is not part of the account csv output.
+    #[serde(skip)]
+    pub frozen: bool,
+
+    /// The group of related transactions each dispute with a ref_tx is logged in, by the dispute's own id,
+    /// so a resolve or chargeback naming a dispute finds its group without scanning the log. See dispute_groups.
+    #[serde(skip)]
+    pub(crate) dispute_groups: BTreeMap<u32, u32>,
+}
+
+/// The balances and status of a client account, without its currency or transaction log.
+/// This is everything the account csv output contains, so accounts can be written once their logs have been dropped.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AccountState {
+    /// Unique client ID
+    #[serde(rename = "client")]
+    pub id: u16,
+
+    /// Funds available for withdrawal.
+    #[serde(serialize_with = "four_decimal_serializer")]
+    pub available: f64,
+
+    /// Funds held in dispute.
+    #[serde(serialize_with = "four_decimal_serializer")]
+    pub held: f64,
+
+    /// Total funds in account.
+    #[serde(serialize_with = "four_decimal_serializer")]
+    pub total: f64,
+
+    /// Locked is true if a chargeback has been issued.
+    pub locked: bool,
+}
+
+impl AccountState {
+    /// The account with each balance rounded to four decimal places using the given rounding mode, as every account output writes it.
+    /// A negative zero, from a tiny negative balance rounding away, becomes zero.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::processing_config::*;
+    /// let account_state = AccountState {
+    ///     id: 1,
+    ///     available: 1.23459,
+    ///     held: -0.00001,
+    ///     total: 1.23458,
+    ///     locked: false,
+    /// };
+    /// let rounded = account_state.rounded(&RoundingMode::Truncate);
+    /// assert_eq!((rounded.available, rounded.total), (1.2345, 1.2345));
+    /// assert!(rounded.held.is_sign_positive());
+    /// ```
+    pub fn rounded(&self, rounding_mode: &RoundingMode) -> AccountState {
+        let round = |balance: f64| {
+            let rounded = rounding_mode.round(balance);
+            if rounded == 0.0 {
+                0.0
+            } else {
+                rounded
+            }
+        };
+        AccountState {
+            available: round(self.available),
+            held: round(self.held),
+            total: round(self.total),
+            ..self.clone()
+        }
+    }
+}
+
+/// A log of a client's previous transactions, grouped by transaction ID.
+pub type TransactionLog = BTreeMap<u32, Vec<Transaction>>;
+
+/// The net financial outcome of a client's disputes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisputeOutcome {
+    /// Funds returned to the client's available balance by resolves.
+    pub resolved_amount: f64,
+
+    /// Funds removed from the client's account by chargebacks.
+    pub charged_back_amount: f64,
+}
+
+/// Why funds are held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HoldReason {
+    /// An open dispute of a deposit.
+    Dispute,
+}
+
+/// Funds moved from available to held, derived from the transaction log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hold {
+    /// The amount held.
+    pub amount: f64,
+
+    /// Why the funds are held.
+    pub reason: HoldReason,
+
+    /// The transaction whose funds are held.
+    pub transaction_id: u32,
+
+    /// The transaction that put the hold in place, which is the held transaction's own ID unless the dispute gave a ref_tx.
+    pub originating_id: u32,
+}
+
+/// A summary of a client's lifetime activity and final balances, produced when the account is closed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosingStatement {
+    /// Unique client ID
+    pub client_id: u16,
+
+    /// Sum of every deposit applied to the account.
+    pub lifetime_deposits: f64,
+
+    /// Sum of every withdrawal applied to the account.
+    pub lifetime_withdrawals: f64,
+
+    /// Number of disputes raised against the account's transactions.
+    pub dispute_count: usize,
+
+    /// How the account's disputes were settled.
+    pub dispute_outcome: DisputeOutcome,
+
+    /// Final funds available for withdrawal.
+    pub available: f64,
+
+    /// Final funds held in dispute.
+    pub held: f64,
+
+    /// Final total funds.
+    pub total: f64,
+
+    /// Whether the account was locked by a chargeback.
+    pub locked: bool,
+}
+
+/// A client account together with its full transaction log, for audit output.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientAudit<'a> {
+    /// Unique client ID
+    #[serde(rename = "client")]
+    pub id: u16,
+
+    /// Funds available for withdrawal.
+    #[serde(serialize_with = "four_decimal_serializer")]
+    pub available: f64,
+
+    /// Funds held in dispute.
+    #[serde(serialize_with = "four_decimal_serializer")]
+    pub held: f64,
+
+    /// Total funds in account.
+    #[serde(serialize_with = "four_decimal_serializer")]
+    pub total: f64,
+
+    /// Locked is true if a chargeback has been issued.
+    pub locked: bool,
+
+    /// The currency of the account, if any deposit named one.
+    pub currency: Option<&'a str>,
+
+    /// Every transaction applied to the account, grouped by transaction ID in ascending order.
+    pub transactions: &'a TransactionLog,
+
+    /// The transaction ID of the chargeback that most recently locked the account. Omitted if no chargeback has locked it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_by: Option<u32>,
+
+    /// The ID of the first transaction that took available funds below zero. Omitted if available funds never went negative.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_overdraft_tx: Option<u32>,
+
+    /// Frozen is true if a freeze transaction has blocked all activity on the account. Omitted if the account is not frozen.
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    pub frozen: bool,
 }
 
 /// Create a new client with default settings, then apply their first transaction.
+/// A withdrawal as the first transaction produces a FirstTransactionNotDeposit error, while a dispute, resolve,
+/// or chargeback produces an AccountNotFound error because it references a transaction on an account that does not exist.
 /// ```
 /// use transaction_manager::transaction::*;
 /// use transaction_manager::client::*;
@@ -40,35 +253,74 @@ pub struct Client {
 ///     transaction_type: TransactionType::Deposit,
 ///     client_id: 0,
 ///     id: 0,
-///     amount: Some(1.2)
+///     amount: Some(1.2),
+///     ..Default::default()
 /// }).unwrap();
 /// assert_eq!(client.id, 0);
 /// assert_eq!(client.available, 1.2);
 /// assert_eq!(client.held, 0.0);
 /// assert_eq!(client.total, 1.2);
-/// assert_eq!(client.locked, false);
+/// assert!(!client.locked);
 /// assert_eq!(client.transactions[&0][0].amount, Some(1.2));
 /// ```
 pub fn initialize_client(transaction: Transaction) -> Result<Client, TransactionError> {
-    let client = Client {
-        id: transaction.client_id,
-        available: 0.0,
-        held: 0.0,
-        total: 0.0,
-        locked: false,
-        transactions: BTreeMap::new(),
-    };
-    if transaction.transaction_type != TransactionType::Deposit {
+    initialize_client_with_config(transaction, &ProcessingConfig::default())
+}
+
+/// Create a new client with default settings, then apply their first transaction according to the given config.
+pub fn initialize_client_with_config(
+    transaction: Transaction,
+    config: &ProcessingConfig,
+) -> Result<Client, TransactionError> {
+    let client = Client::new(transaction.client_id);
+    let error_type = match transaction.transaction_type {
+        TransactionType::Deposit | TransactionType::OpenBalance => None,
+        TransactionType::Withdrawal => Some(TransactionErrorTypes::FirstTransactionNotDeposit),
+        TransactionType::Dispute
+        | TransactionType::Resolve
+        | TransactionType::Chargeback
+        | TransactionType::Unlock
+        | TransactionType::Freeze
+        | TransactionType::Unfreeze => Some(TransactionErrorTypes::AccountNotFound),
+    };
+    if let Some(error_type) = error_type {
         return Err(TransactionError {
-            error_type: TransactionErrorTypes::FirstTransactionNotDeposit,
-            transaction: transaction,
-            client: client,
+            error_type,
+            transaction: Box::new(transaction),
+            client: client.state(),
         });
     }
-    Ok(client.apply_transaction(transaction)?)
+    client.apply_transaction_with_config(transaction, config)
 }
 
 impl Client {
+    /// Create a new, unlocked client with the given id, no funds, and no previous transactions.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let client = Client::new(3);
+    /// assert_eq!(client.id, 3);
+    /// assert_eq!(client.total, 0.0);
+    /// assert!(client.transactions.is_empty());
+    /// ```
+    pub fn new(id: u16) -> Client {
+        Client {
+            id,
+            available: 0.0,
+            held: 0.0,
+            total: 0.0,
+            locked: false,
+            currency: None,
+            transactions: BTreeMap::new(),
+            withdrawn_total: 0.0,
+            dispute_ages: BTreeMap::new(),
+            open_dispute_count: 0,
+            locked_by: None,
+            first_overdraft_tx: None,
+            frozen: false,
+            dispute_groups: BTreeMap::new(),
+        }
+    }
+
     /// Try to apply the given tranasaction to the client, and if successful return the updated client.
     /// May produce a TransactionError if the transaction breaks any rules.
     /// ```
@@ -78,336 +330,3974 @@ impl Client {
     ///     transaction_type: TransactionType::Deposit,
     ///     client_id: 0,
     ///     id: 0,
-    ///     amount: Some(1.2)
+    ///     amount: Some(1.2),
+    ///     ..Default::default()
     /// }).unwrap();
     ///
     /// let client = client.apply_transaction(Transaction {
-    /// transaction_type: TransactionType::Deposit,
-    /// client_id: 0,
-    /// id: 1,
-    /// amount: Some(1.3)}).unwrap();
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 1,
+    ///     amount: Some(1.3),
+    ///     ..Default::default()
+    /// }).unwrap();
     /// assert_eq!(client.id, 0);
     /// assert_eq!(client.available, 2.5);
     /// assert_eq!(client.held, 0.0);
     /// assert_eq!(client.total, 2.5);
-    /// assert_eq!(client.locked, false);
+    /// assert!(!client.locked);
     /// assert_eq!(client.transactions[&1][0].amount, Some(1.3));
     /// ```
-    pub fn apply_transaction(
+    pub fn apply_transaction(self, transaction: Transaction) -> Result<Client, TransactionError> {
+        self.apply_transaction_with_config(transaction, &ProcessingConfig::default())
+    }
+
+    /// Check whether the given transaction would be applied successfully with the default config, without changing the client.
+    /// Returns the type of error applying it would produce, if any.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// use transaction_manager::transaction_error::*;
+    /// let client = Client::new(0);
+    /// let withdrawal = Transaction {
+    ///     transaction_type: TransactionType::Withdrawal,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.0),
+    ///     ..Default::default()
+    /// };
+    /// assert!(matches!(client.can_apply(&withdrawal), Err(TransactionErrorTypes::InsufficientFunds)));
+    /// assert_eq!(client, Client::new(0));
+    /// ```
+    pub fn can_apply(&self, transaction: &Transaction) -> Result<(), TransactionErrorTypes> {
+        self.can_apply_with_config(transaction, &ProcessingConfig::default())
+    }
+
+    /// Check whether the given transaction would be applied successfully according to the given config, without changing the client.
+    /// Returns the type of error applying it would produce, if any.
+    /// The check runs the same validation that apply_transaction_with_config runs before it changes anything, so the two
+    /// can never disagree and the client is never copied. With the invariant-checks feature, an InvariantViolation can only
+    /// be found by applying the transaction, so it is never returned here.
+    pub fn can_apply_with_config(
+        &self,
+        transaction: &Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<(), TransactionErrorTypes> {
+        let amount = normalized_amount(transaction, config);
+        self.check_status(transaction, config)?;
+        match transaction.transaction_type {
+            TransactionType::Deposit => self.check_deposit(transaction, config).map(|_| ()),
+            TransactionType::Withdrawal => self.check_withdrawal(transaction, config).map(|_| ()),
+            TransactionType::Dispute => self.check_dispute(transaction, amount, config).map(|_| ()),
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                self.check_settlement(transaction, amount).map(|_| ())
+            }
+            TransactionType::Unlock | TransactionType::Freeze | TransactionType::Unfreeze => {
+                check_no_amount(amount)
+            }
+            TransactionType::OpenBalance => self.check_open_balance(transaction).map(|_| ()),
+        }
+    }
+
+    /// Try to apply the given transaction to the client according to the given config, and if successful return the updated client.
+    /// May produce a TransactionError if the transaction breaks any rules.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction::*;
+    /// let config = ProcessingConfig {
+    ///     zero_amount_as_none: true,
+    ///     ..Default::default()
+    /// };
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.2),
+    ///     ..Default::default()
+    /// }).unwrap();
+    ///
+    /// let client = client.apply_transaction_with_config(Transaction {
+    ///     transaction_type: TransactionType::Dispute,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(0.0),
+    ///     ..Default::default()
+    /// }, &config).unwrap();
+    /// assert_eq!(client.available, 0.0);
+    /// assert_eq!(client.held, 1.2);
+    /// assert_eq!(client.transactions[&0][1].amount, None);
+    /// ```
+    pub fn apply_transaction_with_config(
         mut self,
-        transaction: Transaction,
+        mut transaction: Transaction,
+        config: &ProcessingConfig,
     ) -> Result<Client, TransactionError> {
-        if self.locked {
-            return Err(TransactionError {
-                error_type: TransactionErrorTypes::AccountLocked,
-                transaction: transaction,
-                client: self,
-            });
+        transaction.amount = normalized_amount(&transaction, config);
+        if let Err(error_type) = self.check_status(&transaction, config) {
+            return Err(self.rejection(error_type, transaction));
         }
+        #[cfg(feature = "invariant-checks")]
+        let applied_transaction = transaction.clone();
+        // A dispute starts its group's clock only if no earlier dispute of the group is still open, and is not aged by its own row.
+        let raised_group = match transaction.transaction_type {
+            TransactionType::Dispute => Some(self.reference_group(&transaction))
+                .filter(|group_id| !self.dispute_ages.contains_key(group_id)),
+            _ => None,
+        };
         self = match transaction.transaction_type {
-            TransactionType::Deposit => self.apply_deposit(transaction)?,
-            TransactionType::Withdrawal => self.apply_withdrawal(transaction)?,
-            TransactionType::Dispute => self.apply_dispute(transaction)?,
-            TransactionType::Resolve => self.apply_resolve(transaction)?,
-            TransactionType::Chargeback => self.apply_chargeback(transaction)?,
+            TransactionType::Deposit => self.apply_deposit(transaction, config)?,
+            TransactionType::Withdrawal => self.apply_withdrawal(transaction, config)?,
+            TransactionType::Dispute => self.apply_dispute(transaction, config)?,
+            TransactionType::Resolve => self.apply_resolve(transaction, config)?,
+            TransactionType::Chargeback => self.apply_chargeback(transaction, config)?,
+            TransactionType::Unlock => self.apply_unlock(transaction)?,
+            TransactionType::Freeze | TransactionType::Unfreeze => {
+                self.apply_freeze(transaction)?
+            }
+            TransactionType::OpenBalance => self.apply_open_balance(transaction, config)?,
         };
+        self = self.sweep_disputes(raised_group, config);
+        #[cfg(feature = "invariant-checks")]
+        if !self.check_invariant() {
+            return Err(self.rejection(
+                TransactionErrorTypes::InvariantViolation,
+                applied_transaction,
+            ));
+        }
         Ok(self)
     }
 
-    /// If the given amount is Some(positive number), add it to available and total funds.
-    fn apply_deposit(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
-        if let Some(amount) = transaction.amount {
-            if amount <= 0.0 {
-                return Err(TransactionError {
-                    error_type: TransactionErrorTypes::NonPositiveAmount,
-                    transaction: transaction,
-                    client: self,
-                });
+    /// Build the error rejecting the given transaction, capturing the account as it is now.
+    fn rejection(
+        &self,
+        error_type: TransactionErrorTypes,
+        transaction: Transaction,
+    ) -> TransactionError {
+        TransactionError {
+            error_type,
+            transaction: Box::new(transaction),
+            client: self.state(),
+        }
+    }
+
+    /// Check that the account takes the given transaction at all: its type must be enabled, the account must not be frozen
+    /// unless it is a freeze or unfreeze, nor locked unless it is a freeze, unfreeze, unlock, or a type the config allows
+    /// on locked accounts, and any currency it names must match the account's.
+    fn check_status(
+        &self,
+        transaction: &Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<(), TransactionErrorTypes> {
+        if !config.enabled_types.contains(&transaction.transaction_type) {
+            return Err(TransactionErrorTypes::TransactionTypeDisabled);
+        }
+        let is_freeze = transaction.transaction_type == TransactionType::Freeze
+            || transaction.transaction_type == TransactionType::Unfreeze;
+        if self.frozen && !is_freeze {
+            return Err(TransactionErrorTypes::AccountFrozen);
+        }
+        if self.locked
+            && !is_freeze
+            && transaction.transaction_type != TransactionType::Unlock
+            && !config.locked_allows.contains(&transaction.transaction_type)
+        {
+            return Err(TransactionErrorTypes::AccountLocked);
+        }
+        if let (Some(client_currency), Some(currency)) = (&self.currency, &transaction.currency) {
+            if client_currency != currency {
+                return Err(TransactionErrorTypes::CurrencyMismatch);
             }
-            self.available = round_to_four_decimals(self.available + amount);
-            self.total = round_to_four_decimals(self.total + amount);
-        } else {
-            return Err(TransactionError {
-                error_type: TransactionErrorTypes::MissingRequiredAmount,
-                transaction: transaction,
-                client: self,
-            });
+        }
+        Ok(())
+    }
+
+    /// If the given amount is Some(positive, finite number), add it to available and total funds.
+    /// If the config treats negative deposits as reversals, a negative amount is subtracted instead, as long as available and
+    /// total funds stay within the overdraft limit, otherwise an InsufficientFunds error is produced. Bounds apply to the reversed amount.
+    /// The first deposit that names a currency sets the currency of the account.
+    fn apply_deposit(
+        mut self,
+        transaction: Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let amount = match self.check_deposit(&transaction, config) {
+            Ok(amount) => amount,
+            Err(error_type) => return Err(self.rejection(error_type, transaction)),
+        };
+        self.available = config.rounding_mode.round(self.available + amount);
+        self.total = config.rounding_mode.round(self.total + amount);
+        self.record_overdraft(transaction.id);
+        if self.currency.is_none() {
+            self.currency = transaction.currency.clone();
         }
         self.log_transaction(transaction);
         Ok(self)
     }
 
-    /// If the given amount is Some(positive number) and there are enough available funds, subtract it from available and total funds.
-    fn apply_withdrawal(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
-        if let Some(amount) = transaction.amount {
-            if amount <= 0.0 {
-                return Err(TransactionError {
-                    error_type: TransactionErrorTypes::NonPositiveAmount,
-                    transaction: transaction,
-                    client: self,
-                });
-            }
-            if amount > self.available {
-                return Err(TransactionError {
-                    error_type: TransactionErrorTypes::InsufficientFunds,
-                    transaction: transaction,
-                    client: self,
-                });
+    /// Check a deposit against the rules apply_deposit enforces, and return the amount to add.
+    fn check_deposit(
+        &self,
+        transaction: &Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<f64, TransactionErrorTypes> {
+        let amount = transaction
+            .amount
+            .ok_or(TransactionErrorTypes::MissingRequiredAmount)?;
+        if !amount.is_finite() {
+            return Err(TransactionErrorTypes::NonFiniteAmount);
+        }
+        let reversal = amount < 0.0 && config.treat_negative_deposit_as_reversal;
+        if amount <= 0.0 && !reversal {
+            return Err(TransactionErrorTypes::NonPositiveAmount);
+        }
+        if is_out_of_bounds(&transaction.transaction_type, libm::fabs(amount), config)
+            || libm::fabs(self.available + amount) > MAX_SAFE_AMOUNT
+            || libm::fabs(self.total + amount) > MAX_SAFE_AMOUNT
+        {
+            return Err(TransactionErrorTypes::AmountOutOfBounds(amount));
+        }
+        if reversal
+            && (self.available + amount < -config.overdraft_limit
+                || self.total + amount < -config.overdraft_limit)
+        {
+            return Err(TransactionErrorTypes::InsufficientFunds);
+        }
+        Ok(amount)
+    }
+
+    /// If the client has no logged transactions or funds yet, set available and total funds to the given amount,
+    /// which must be Some(zero or positive, finite number) no larger than MAX_SAFE_AMOUNT. The config's minimum deposit and
+    /// maximum amount do not apply, since an opening balance is not a new transaction. Otherwise produce an OpenBalanceNotFirst error.
+    /// The opening balance is logged, so it has a transaction ID like any deposit, but it can never be disputed.
+    fn apply_open_balance(
+        mut self,
+        transaction: Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let amount = match self.check_open_balance(&transaction) {
+            Ok(amount) => amount,
+            Err(error_type) => return Err(self.rejection(error_type, transaction)),
+        };
+        self.available = config.rounding_mode.round(amount);
+        self.total = config.rounding_mode.round(amount);
+        if self.currency.is_none() {
+            self.currency = transaction.currency.clone();
+        }
+        debug!("client {} opened with balance {}", self.id, self.total);
+        self.log_transaction(transaction);
+        Ok(self)
+    }
+
+    /// Check an opening balance against the rules apply_open_balance enforces, and return the balance to open with.
+    fn check_open_balance(&self, transaction: &Transaction) -> Result<f64, TransactionErrorTypes> {
+        if !self.transactions.is_empty() || self.total != 0.0 || self.held != 0.0 {
+            return Err(TransactionErrorTypes::OpenBalanceNotFirst);
+        }
+        match transaction.amount {
+            None => Err(TransactionErrorTypes::MissingRequiredAmount),
+            Some(amount) if !amount.is_finite() => Err(TransactionErrorTypes::NonFiniteAmount),
+            Some(amount) if amount < 0.0 => Err(TransactionErrorTypes::NonPositiveAmount),
+            Some(amount) if amount > MAX_SAFE_AMOUNT => {
+                Err(TransactionErrorTypes::AmountOutOfBounds(amount))
             }
-            self.available = round_to_four_decimals(self.available - amount);
-            self.total = round_to_four_decimals(self.total - amount);
-        } else {
-            return Err(TransactionError {
-                error_type: TransactionErrorTypes::MissingRequiredAmount,
-                transaction: transaction,
-                client: self,
-            });
+            Some(amount) => Ok(amount),
         }
+    }
+
+    /// If the given amount is Some(positive, finite number) and there are enough available funds, subtract it from available and total funds.
+    /// Available funds may go negative by up to the configured overdraft limit.
+    /// If the config caps total withdrawals, a withdrawal that would push the client's withdrawn total over the cap produces a WithdrawalLimitExceeded error.
+    fn apply_withdrawal(
+        mut self,
+        transaction: Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let (amount, withdrawn_total) = match self.check_withdrawal(&transaction, config) {
+            Ok(checked) => checked,
+            Err(error_type) => return Err(self.rejection(error_type, transaction)),
+        };
+        self.available = config.rounding_mode.round(self.available - amount);
+        self.total = config.rounding_mode.round(self.total - amount);
+        self.withdrawn_total = withdrawn_total;
+        self.record_overdraft(transaction.id);
         self.log_transaction(transaction);
         Ok(self)
     }
 
-    /// If the given transaction ID exists in the log, move the amount specified in that deposit from available to held.
+    /// Check a withdrawal against the rules apply_withdrawal enforces, and return the amount to subtract with the client's new withdrawn total.
+    fn check_withdrawal(
+        &self,
+        transaction: &Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<(f64, f64), TransactionErrorTypes> {
+        let amount = transaction
+            .amount
+            .ok_or(TransactionErrorTypes::MissingRequiredAmount)?;
+        if !amount.is_finite() {
+            return Err(TransactionErrorTypes::NonFiniteAmount);
+        }
+        if amount <= 0.0 {
+            return Err(TransactionErrorTypes::NonPositiveAmount);
+        }
+        if is_out_of_bounds(&transaction.transaction_type, amount, config) {
+            return Err(TransactionErrorTypes::AmountOutOfBounds(amount));
+        }
+        if self.available - amount < -config.overdraft_limit {
+            return Err(TransactionErrorTypes::InsufficientFunds);
+        }
+        let withdrawn_total = config.rounding_mode.round(self.withdrawn_total + amount);
+        if config
+            .max_total_withdrawal
+            .is_some_and(|max_total_withdrawal| withdrawn_total > max_total_withdrawal)
+        {
+            return Err(TransactionErrorTypes::WithdrawalLimitExceeded);
+        }
+        Ok((amount, withdrawn_total))
+    }
+
+    /// If the given transaction ID exists in the log, dispute it.
+    /// A disputed deposit moves the deposited amount from available to held.
+    /// A disputed withdrawal has already left the account, so the withdrawn amount is credited back to available and total while the dispute is pending,
+    /// and no longer counts towards the client's withdrawn total.
+    /// If the config limits held funds to total funds, a deposit dispute that would make held exceed total produces an InsufficientFunds error.
+    /// A transaction may be disputed again while earlier disputes of it are open, and each dispute is settled separately.
+    /// A dispute without an amount disputes whatever part of the referenced amount is not already disputed or charged back.
+    /// If the config allows partial disputes, a dispute with an amount disputes only that portion of the referenced transaction.
+    /// A dispute amount that is not positive or exceeds the undisputed part of the referenced amount produces an InvalidDisputeAmount error.
     /// If the referenced transaction ID does not exist, ignore and log the the transaction.
-    fn apply_dispute(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
-        if let Some(_) = transaction.amount {
-            return Err(TransactionError {
-                error_type: TransactionErrorTypes::HasMeaninglessAmount,
-                transaction: transaction,
-                client: self,
-            });
-        }
-        if let Some(related_transactions) = self.transactions.get(&transaction.id) {
-            let amount = related_transactions[0].amount.unwrap();
-            self.available = round_to_four_decimals(self.available - amount);
-            self.held = round_to_four_decimals(self.held + amount);
+    /// If the ID is only logged for disputes of unknown transactions, with no deposit or withdrawal, produce an InvalidIdReferenced error.
+    /// If the config does not list the referenced transaction's type as disputable, or it is an opening balance, produce a NotDisputable error.
+    /// If the config caps open disputes per client and the account already has that many open, produce a TooManyOpenDisputes error.
+    fn apply_dispute(
+        mut self,
+        transaction: Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let (group_id, disputed) =
+            match self.check_dispute(&transaction, transaction.amount, config) {
+                Ok(checked) => checked,
+                Err(error_type) => return Err(self.rejection(error_type, transaction)),
+            };
+        if let Some((referenced_type, amount)) = disputed {
+            if referenced_type == TransactionType::Withdrawal {
+                self.available = config.rounding_mode.round(self.available + amount);
+                self.total = config.rounding_mode.round(self.total + amount);
+                self.withdrawn_total = config.rounding_mode.round(self.withdrawn_total - amount);
+            } else {
+                self.available = config.rounding_mode.round(self.available - amount);
+                self.held = config.rounding_mode.round(self.held + amount);
+                self.record_overdraft(transaction.id);
+            }
+            self.dispute_ages.entry(group_id).or_insert(0);
+            self.open_dispute_count += 1;
+            debug!("client {} disputed tx {}", self.id, group_id);
+        } else {
+            debug!(
+                "ignoring dispute for unknown tx {} on client {}",
+                group_id, self.id
+            );
         }
-        self.log_transaction(transaction);
+        self.log_related_transaction(group_id, transaction);
         Ok(self)
     }
 
-    /// If the given transaction ID exists in the log and a dispute was the last transaction, move the amount specified in that deposit from held to available.
+    /// Check a dispute with the given amount against the rules apply_dispute enforces. Returns the group the dispute is logged in,
+    /// with the type of the referenced transaction and the amount to dispute, or None if the referenced transaction does not exist.
+    fn check_dispute(
+        &self,
+        transaction: &Transaction,
+        amount: Option<f64>,
+        config: &ProcessingConfig,
+    ) -> Result<(u32, Option<(TransactionType, f64)>), TransactionErrorTypes> {
+        if amount.is_some() && !config.allow_partial_disputes {
+            return Err(TransactionErrorTypes::HasMeaninglessAmount);
+        }
+        let group_id = self.reference_group(transaction);
+        let related_transactions = match self.transactions.get(&group_id) {
+            Some(related_transactions) => related_transactions,
+            None => return Ok((group_id, None)),
+        };
+        let (referenced_type, referenced_amount) = referenced_transaction(related_transactions)?;
+        if referenced_type == TransactionType::OpenBalance
+            || !config.disputable_types.contains(&referenced_type)
+        {
+            return Err(TransactionErrorTypes::NotDisputable);
+        }
+        let undisputed_amount =
+            replay_disputes(related_transactions, referenced_amount).undisputed_amount;
+        let amount = amount.unwrap_or(undisputed_amount);
+        if !amount.is_finite() || amount <= 0.0 || amount > undisputed_amount {
+            return Err(TransactionErrorTypes::InvalidDisputeAmount);
+        }
+        if config
+            .max_open_disputes
+            .is_some_and(|max_open_disputes| self.open_dispute_count >= max_open_disputes)
+        {
+            return Err(TransactionErrorTypes::TooManyOpenDisputes);
+        }
+        if referenced_type != TransactionType::Withdrawal
+            && config.limit_held_to_total
+            && config.rounding_mode.round(self.held + amount) > self.total
+        {
+            return Err(TransactionErrorTypes::InsufficientFunds);
+        }
+        Ok((group_id, Some((referenced_type, amount))))
+    }
+
+    /// If the given transaction ID exists in the log and has an open dispute, resolve the oldest open dispute in favor of the original transaction,
+    /// or the dispute its ref_tx names by its own id.
+    /// A resolved deposit dispute moves exactly the disputed amount from held back to available, even if some of
+    /// that deposit was withdrawn before the dispute. If held funds are somehow smaller than that amount, an InsufficientFunds error is produced.
+    /// A resolved withdrawal dispute removes the pending credit from available and total, so the withdrawal stands.
     /// If the referenced transaction ID does not exist or does not reference a dispute, ignore and log the the transaction.
-    fn apply_resolve(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
-        if let Some(_) = transaction.amount {
-            return Err(TransactionError {
-                error_type: TransactionErrorTypes::HasMeaninglessAmount,
-                transaction: transaction,
-                client: self,
-            });
-        }
-        if let Some(related_transactions) = self.transactions.get(&transaction.id) {
-            if related_transactions[related_transactions.len() - 1].transaction_type
-                == TransactionType::Dispute
-            {
-                let amount = related_transactions[0].amount.unwrap();
-                self.held = round_to_four_decimals(self.held - amount);
-                self.available = round_to_four_decimals(self.available + amount);
+    fn apply_resolve(
+        mut self,
+        transaction: Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let (group_id, settlement) = match self.check_settlement(&transaction, transaction.amount) {
+            Ok(checked) => checked,
+            Err(error_type) => return Err(self.rejection(error_type, transaction)),
+        };
+        if let Some(settlement) = settlement {
+            let amount = settlement.amount;
+            if settlement.referenced_type == TransactionType::Withdrawal {
+                self.available = config.rounding_mode.round(self.available - amount);
+                self.total = config.rounding_mode.round(self.total - amount);
+                self.withdrawn_total = config.rounding_mode.round(self.withdrawn_total + amount);
+                self.record_overdraft(transaction.id);
+            } else {
+                self.held = config.rounding_mode.round(self.held - amount);
+                self.available = config.rounding_mode.round(self.available + amount);
             }
+            if settlement.still_open == 0 {
+                self.dispute_ages.remove(&group_id);
+            }
+            self.open_dispute_count = self.open_dispute_count.saturating_sub(1);
+            debug!("client {} resolved dispute of tx {}", self.id, group_id);
+        } else if self.transactions.contains_key(&group_id) {
+            debug!(
+                "ignoring resolve for tx {} on client {} because it is not disputed",
+                group_id, self.id
+            );
+        } else {
+            debug!(
+                "ignoring resolve for unknown tx {} on client {}",
+                group_id, self.id
+            );
         }
-        self.log_transaction(transaction);
+        self.log_related_transaction(group_id, transaction);
+        self.compact_log(group_id, config);
         Ok(self)
     }
 
-    /// If the given transaction ID exists in the log and a dispute was the last transaction, subrtract the amount specified in that deposit from held and total, then lock the account.
+    /// If the given transaction ID exists in the log and has an open dispute, reverse the disputed amount of the oldest open dispute, or of the dispute its ref_tx names by its own id, and lock the account.
+    /// A deposit chargeback subtracts the disputed amount from held and total.
+    /// A withdrawal chargeback makes the pending credit from the dispute permanent, leaving balances unchanged.
     /// If the referenced transaction ID does not exist or does not reference a dispute, ignore and log the the transaction
-    fn apply_chargeback(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
-        if let Some(_) = transaction.amount {
-            return Err(TransactionError {
-                error_type: TransactionErrorTypes::HasMeaninglessAmount,
-                transaction: transaction,
-                client: self,
-            });
-        }
-        if let Some(related_transactions) = self.transactions.get(&transaction.id) {
-            if related_transactions[related_transactions.len() - 1].transaction_type
-                == TransactionType::Dispute
-            {
-                let amount = related_transactions[0].amount.unwrap();
-                self.held = round_to_four_decimals(self.held - amount);
-                self.total = round_to_four_decimals(self.total - amount);
-                self.locked = true;
+    fn apply_chargeback(
+        mut self,
+        transaction: Transaction,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let (group_id, settlement) = match self.check_settlement(&transaction, transaction.amount) {
+            Ok(checked) => checked,
+            Err(error_type) => return Err(self.rejection(error_type, transaction)),
+        };
+        if let Some(settlement) = settlement {
+            if settlement.referenced_type != TransactionType::Withdrawal {
+                self.held = config.rounding_mode.round(self.held - settlement.amount);
+                self.total = config.rounding_mode.round(self.total - settlement.amount);
             }
+            self.locked = true;
+            self.locked_by = Some(transaction.id);
+            if settlement.still_open == 0 {
+                self.dispute_ages.remove(&group_id);
+            }
+            self.open_dispute_count = self.open_dispute_count.saturating_sub(1);
+            warn!(
+                "account {} locked by chargeback of tx {}",
+                self.id, group_id
+            );
+        } else if self.transactions.contains_key(&group_id) {
+            debug!(
+                "ignoring chargeback for tx {} on client {} because it is not disputed",
+                group_id, self.id
+            );
+        } else {
+            debug!(
+                "ignoring chargeback for unknown tx {} on client {}",
+                group_id, self.id
+            );
         }
-        self.log_transaction(transaction);
+        self.log_related_transaction(group_id, transaction);
+        self.compact_log(group_id, config);
         Ok(self)
     }
 
-    /// Log the transaction alongside any related transactions.
-    fn log_transaction(&mut self, transaction: Transaction) {
-        if let Some(related_transactions) = self.transactions.get_mut(&transaction.id) {
-            related_transactions.push(transaction);
+    /// Check a resolve or chargeback with the given amount against the rules apply_resolve and apply_chargeback enforce.
+    /// Returns the group the settlement is logged in, with the dispute it settles, or None if there is no open dispute to settle.
+    fn check_settlement(
+        &self,
+        transaction: &Transaction,
+        amount: Option<f64>,
+    ) -> Result<(u32, Option<Settlement>), TransactionErrorTypes> {
+        check_no_amount(amount)?;
+        let group_id = self.reference_group(transaction);
+        let related_transactions = match self.transactions.get(&group_id) {
+            Some(related_transactions) => related_transactions,
+            None => return Ok((group_id, None)),
+        };
+        let (referenced_type, referenced_amount) = referenced_transaction(related_transactions)?;
+        let (amount, still_open) =
+            match settled_dispute(related_transactions, referenced_amount, transaction) {
+                Some(settled) => settled,
+                None => return Ok((group_id, None)),
+            };
+        if transaction.transaction_type == TransactionType::Resolve
+            && referenced_type != TransactionType::Withdrawal
+            && amount > self.held
+        {
+            return Err(TransactionErrorTypes::InsufficientFunds);
+        }
+        Ok((
+            group_id,
+            Some(Settlement {
+                referenced_type,
+                amount,
+                still_open,
+            }),
+        ))
+    }
+
+    /// Unlock the account so that transactions can be applied to it again, typically after a chargeback has been manually reviewed.
+    /// Unlocks are not added to the transaction log, since their transaction ID does not reference another transaction,
+    /// but every unlock of a locked account is reported through the log crate at warn level as an audit trail.
+    fn apply_unlock(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
+        if let Err(error_type) = check_no_amount(transaction.amount) {
+            return Err(self.rejection(error_type, transaction));
+        }
+        if self.locked {
+            self.locked = false;
+            warn!("account {} unlocked by tx {}", self.id, transaction.id);
         } else {
-            self.transactions.insert(transaction.id, vec![transaction]);
+            debug!(
+                "ignoring unlock tx {} on client {} because it is not locked",
+                transaction.id, self.id
+            );
         }
+        Ok(self)
     }
-}
 
-/// Rounds any f64 to four decimal places
-fn round_to_four_decimals(n: f64) -> f64 {
-    (n * 10000.0).round() / 10000.0
-}
+    /// Freeze or unfreeze the account, without moving funds or changing whether it is locked by a chargeback.
+    /// A frozen account rejects every transaction but a freeze or unfreeze, whatever the config allows on locked accounts,
+    /// and a locked account can still be frozen and unfrozen. Like unlocks, freezes are not added to the transaction log,
+    /// and every change to the frozen status is reported through the log crate at warn level as an audit trail.
+    fn apply_freeze(mut self, transaction: Transaction) -> Result<Client, TransactionError> {
+        if let Err(error_type) = check_no_amount(transaction.amount) {
+            return Err(self.rejection(error_type, transaction));
+        }
+        let frozen = transaction.transaction_type == TransactionType::Freeze;
+        if self.frozen != frozen {
+            self.frozen = frozen;
+            warn!(
+                "account {} {} by tx {}",
+                self.id,
+                if frozen { "frozen" } else { "unfrozen" },
+                transaction.id
+            );
+        } else {
+            debug!(
+                "ignoring {:?} tx {} on client {} because it is already {}",
+                transaction.transaction_type,
+                transaction.id,
+                self.id,
+                if frozen { "frozen" } else { "unfrozen" }
+            );
+        }
+        Ok(self)
+    }
 
-/// When serializing funds, attempt to round to four decimal places.
-fn four_decimal_serializer<S>(n: &f64, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    s.serialize_f64(round_to_four_decimals(*n))
-}
+    /// Returns true if total funds equal available plus held funds, compared at the four decimal places balances are kept to.
+    /// When the invariant-checks feature is enabled, this is checked after every transaction is applied.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let mut client = Client::new(0);
+    /// assert!(client.check_invariant());
+    /// client.total = 1.0;
+    /// assert!(!client.check_invariant());
+    /// ```
+    pub fn check_invariant(&self) -> bool {
+        let to_units = |n: f64| libm::round(n * 10000.0) as i64;
+        to_units(self.available) + to_units(self.held) == to_units(self.total)
+    }
 
-#[test]
-fn test_deposit() {
-    let client = initialize_client(Transaction {
-        transaction_type: TransactionType::Deposit,
-        client_id: 0,
-        id: 0,
-        amount: Some(1.2),
-    })
-    .unwrap();
-    let client = client
-        .apply_deposit(Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: 0,
-            id: 1,
-            amount: Some(1.3),
-        })
+    /// Rebuild the client from scratch by replaying its transaction log with the default config, to check the incremental balances
+    /// against a from-scratch computation. See rebuild_from_log_with_config for how the log is replayed.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.2),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(client.rebuild_from_log().unwrap(), client);
+    /// ```
+    pub fn rebuild_from_log(&self) -> Result<Client, TransactionError> {
+        self.rebuild_from_log_with_config(&ProcessingConfig::default())
+    }
+
+    /// Rebuild the client from scratch by replaying its transaction log according to the given config,
+    /// which should be the config the client's transactions were originally applied with.
+    /// The log is grouped by transaction ID, so the original order across groups is not recorded. Transactions are replayed
+    /// in timestamp order if every logged transaction has a timestamp, otherwise group by group in ascending ID order,
+    /// and in the order they were logged within each group. A dispute, resolve, or chargeback is logged under the ID of
+    /// the deposit or withdrawal it references, so in ID order it is replayed before any later-numbered deposit or withdrawal,
+    /// even if it was applied after them. Final balances do not depend on that order, but a withdrawal that only had enough
+    /// available funds because a dispute came after it can be rejected by the replay, and that error is returned.
+    /// Unlocks and freezes are not logged, so any transaction may be replayed on a locked account,
+    /// and the rebuilt client keeps this client's lock and frozen status.
+    /// Opening balances from a seed file are not logged either, so a seeded client cannot be rebuilt to its live balances,
+    /// but an open_balance transaction is logged, and is replayed before everything else since it must come first.
+    pub fn rebuild_from_log_with_config(
+        &self,
+        config: &ProcessingConfig,
+    ) -> Result<Client, TransactionError> {
+        let replay_config = ProcessingConfig {
+            locked_allows: config.enabled_types.clone(),
+            ..config.clone()
+        };
+        let logged_transactions = self.transactions.values().flatten().collect();
+        let mut replayed = replay_order(logged_transactions, |transaction| *transaction);
+        replayed.sort_by_key(|transaction| {
+            transaction.transaction_type != TransactionType::OpenBalance
+        });
+        let mut rebuilt = Client::new(self.id);
+        for transaction in replayed {
+            rebuilt = rebuilt.apply_transaction_with_config(transaction.clone(), &replay_config)?;
+        }
+        rebuilt.locked = self.locked;
+        rebuilt.frozen = self.frozen;
+        Ok(rebuilt)
+    }
+
+    /// Find the transactions that still have an open dispute, using the transaction log, with the total amount each has in dispute.
+    /// Returned in ascending transaction ID order.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// assert!(Client::new(0).open_disputes().is_empty());
+    /// ```
+    pub fn open_disputes(&self) -> Vec<(u32, f64)> {
+        let mut open_disputes = Vec::new();
+        for (id, related_transactions) in &self.transactions {
+            let referenced_amount = match referenced_transaction(related_transactions) {
+                Ok((_, amount)) => amount,
+                Err(_) => continue,
+            };
+            let open = replay_disputes(related_transactions, referenced_amount).open;
+            if !open.is_empty() {
+                open_disputes.push((
+                    *id,
+                    round_to_four_decimals(open.iter().map(|dispute| dispute.amount).sum()),
+                ));
+            }
+        }
+        open_disputes
+    }
+
+    /// Pair each chargeback that settled a dispute with the dispute it settled, using the transaction log.
+    /// A chargeback settles the dispute named by its ref_tx, or otherwise the oldest open dispute, matching how it is applied,
+    /// so a chargeback that found no open dispute is not listed. Returned as (dispute, chargeback) in ascending transaction ID order.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// assert!(Client::new(0).settled_chargebacks().is_empty());
+    /// ```
+    pub fn settled_chargebacks(&self) -> Vec<(&Transaction, &Transaction)> {
+        let mut settled_chargebacks = Vec::new();
+        for related_transactions in self.transactions.values() {
+            let referenced_amount = match referenced_transaction(related_transactions) {
+                Ok((_, amount)) => amount,
+                Err(_) => continue,
+            };
+            let settled = replay_disputes(related_transactions, referenced_amount).settled;
+            for (transaction, settled) in related_transactions.iter().zip(settled) {
+                if let (TransactionType::Chargeback, Some(dispute_position)) =
+                    (&transaction.transaction_type, settled)
+                {
+                    settled_chargebacks
+                        .push((&related_transactions[dispute_position], transaction));
+                }
+            }
+        }
+        settled_chargebacks
+    }
+
+    /// List the funds currently held, using the transaction log, one entry per open dispute of a deposit.
+    /// Disputed withdrawals are not listed, since their disputes credit the available balance instead of holding funds.
+    /// Returned in ascending transaction ID order, oldest dispute first, and their amounts add up to the held balance.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// assert!(Client::new(0).holds().is_empty());
+    /// ```
+    pub fn holds(&self) -> Vec<Hold> {
+        let mut holds = Vec::new();
+        for (id, related_transactions) in &self.transactions {
+            let referenced_amount = match referenced_transaction(related_transactions) {
+                Ok((TransactionType::Deposit, amount)) => amount,
+                _ => continue,
+            };
+            let open = replay_disputes(related_transactions, referenced_amount).open;
+            holds.extend(open.into_iter().map(|dispute| Hold {
+                amount: dispute.amount,
+                reason: HoldReason::Dispute,
+                transaction_id: *id,
+                originating_id: dispute.own_id.unwrap_or(*id),
+            }));
+        }
+        holds
+    }
+
+    /// Compute how much disputed money was resolved back to the client versus removed by chargebacks, using the transaction log.
+    /// Only resolves and chargebacks that followed a dispute of the same transaction are counted, matching how they are applied.
+    /// Disputed withdrawals are not counted, since their resolves and chargebacks move money the other way.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let client = Client::new(0);
+    /// assert_eq!(client.dispute_outcome(), DisputeOutcome::default());
+    /// ```
+    pub fn dispute_outcome(&self) -> DisputeOutcome {
+        let mut outcome = DisputeOutcome::default();
+        for related_transactions in self.transactions.values() {
+            let referenced_amount = match referenced_transaction(related_transactions) {
+                Ok((TransactionType::Deposit, amount)) => amount,
+                _ => continue,
+            };
+            let dispute_amounts = replay_disputes(related_transactions, referenced_amount).amounts;
+            for (transaction, dispute_amount) in related_transactions.iter().zip(dispute_amounts) {
+                match (&transaction.transaction_type, dispute_amount) {
+                    (TransactionType::Resolve, Some(amount)) => {
+                        outcome.resolved_amount =
+                            round_to_four_decimals(outcome.resolved_amount + amount)
+                    }
+                    (TransactionType::Chargeback, Some(amount)) => {
+                        outcome.charged_back_amount =
+                            round_to_four_decimals(outcome.charged_back_amount + amount)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Summarize the account's lifetime deposits, withdrawals, and disputes alongside its final balances, using the transaction log.
+    /// There is no dedicated close-account transaction, so the statement reflects the account as it stands when generated;
+    /// a fully closed account is one whose final balances are all zero.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let statement = Client::new(0).closing_statement();
+    /// assert_eq!(statement.lifetime_deposits, 0.0);
+    /// assert_eq!(statement.total, 0.0);
+    /// ```
+    pub fn closing_statement(&self) -> ClosingStatement {
+        let mut lifetime_deposits = 0.0;
+        let mut lifetime_withdrawals = 0.0;
+        let mut dispute_count = 0;
+        for transaction in self.transactions.values().flatten() {
+            match transaction.transaction_type {
+                TransactionType::Deposit => {
+                    lifetime_deposits = round_to_four_decimals(
+                        lifetime_deposits + transaction.amount.unwrap_or(0.0),
+                    )
+                }
+                TransactionType::Withdrawal => {
+                    lifetime_withdrawals = round_to_four_decimals(
+                        lifetime_withdrawals + transaction.amount.unwrap_or(0.0),
+                    )
+                }
+                TransactionType::Dispute => dispute_count += 1,
+                _ => {}
+            }
+        }
+        ClosingStatement {
+            client_id: self.id,
+            lifetime_deposits,
+            lifetime_withdrawals,
+            dispute_count,
+            dispute_outcome: self.dispute_outcome(),
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+
+    /// Funds available for withdrawal.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.5),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(client.available(), 1.5);
+    /// ```
+    pub fn available(&self) -> f64 {
+        self.available
+    }
+
+    /// Funds held in dispute.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.5),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(client.held(), 0.0);
+    /// ```
+    pub fn held(&self) -> f64 {
+        self.held
+    }
+
+    /// Total funds in the account.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.5),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(client.total(), 1.5);
+    /// ```
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Whether the account has been locked by a chargeback.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.5),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert!(!client.is_locked());
+    /// ```
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether the account has been frozen by a freeze transaction. See apply_freeze.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// assert!(!Client::new(0).is_frozen());
+    /// ```
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Copy the balances and status of the account, without its currency or transaction log.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let mut client = Client::new(2);
+    /// client.available = 1.5;
+    /// client.total = 1.5;
+    /// let state = client.state();
+    /// assert_eq!(state.id, 2);
+    /// assert_eq!(state.available, 1.5);
+    /// assert_eq!(state.locked, false);
+    /// ```
+    pub fn state(&self) -> AccountState {
+        AccountState {
+            id: self.id,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+
+    /// Split the account into its balances and status and its transaction log, so each can be kept or dropped separately.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let (state, transactions) = Client::new(2).into_parts();
+    /// assert_eq!(state.id, 2);
+    /// assert!(transactions.is_empty());
+    /// ```
+    pub fn into_parts(self) -> (AccountState, TransactionLog) {
+        let state = self.state();
+        (state, self.transactions)
+    }
+
+    /// Borrow the account and its full transaction log as an audit record, which serializes the log that the account csv omits.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// let client = Client::new(0);
+    /// assert_eq!(client.audit_record().id, 0);
+    /// assert!(client.audit_record().transactions.is_empty());
+    /// ```
+    pub fn audit_record(&self) -> ClientAudit<'_> {
+        ClientAudit {
+            id: self.id,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            currency: self.currency.as_deref(),
+            transactions: &self.transactions,
+            locked_by: self.locked_by,
+            first_overdraft_tx: self.first_overdraft_tx,
+            frozen: self.frozen,
+        }
+    }
+
+    /// Compute the average total balance over the period from `from` up to `to`, weighted by how long each balance was held.
+    /// Each transaction takes effect at its timestamp. Logged transactions without a timestamp are ignored.
+    /// If the period is empty, the total balance at `from` is returned.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.2),
+    ///     timestamp: Some(10),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(client.time_weighted_average_balance(0, 20), 0.6);
+    /// ```
+    pub fn time_weighted_average_balance(&self, from: u64, to: u64) -> f64 {
+        let mut changes: Vec<(u64, f64)> = self
+            .total_changes()
+            .into_iter()
+            .filter_map(|(transaction, change)| {
+                transaction.timestamp.map(|timestamp| (timestamp, change))
+            })
+            .collect();
+        changes.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let mut balance = 0.0;
+        let mut weighted_sum = 0.0;
+        let mut last_time = from;
+        for (timestamp, change) in changes {
+            if timestamp > from {
+                if to <= from {
+                    break;
+                }
+                let until = timestamp.min(to);
+                weighted_sum += balance * (until - last_time) as f64;
+                last_time = until;
+                if timestamp >= to {
+                    break;
+                }
+            }
+            balance += change;
+        }
+        if to <= from {
+            return round_to_four_decimals(balance);
+        }
+        weighted_sum += balance * (to - last_time) as f64;
+        round_to_four_decimals(weighted_sum / (to - from) as f64)
+    }
+
+    /// Replay the client's log and find the first transaction that drove available, held, or total funds below zero.
+    /// The log is replayed in timestamp order if every logged transaction has a timestamp, otherwise in transaction ID order.
+    /// Returns the transaction ID and the name of the balance that went negative ("available", "held", or "total"),
+    /// or None if every balance stayed at or above zero.
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let client = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.2),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(client.first_negative_point(), None);
+    /// ```
+    pub fn first_negative_point(&self) -> Option<(u32, String)> {
+        let mut available = 0.0;
+        let mut held = 0.0;
+        for (transaction, (available_change, held_change)) in
+            replay_order(self.balance_changes(), |(transaction, _)| *transaction)
+        {
+            available = round_to_four_decimals(available + available_change);
+            held = round_to_four_decimals(held + held_change);
+            let total = round_to_four_decimals(available + held);
+            for (balance, name) in [(available, "available"), (held, "held"), (total, "total")] {
+                if balance < 0.0 {
+                    return Some((transaction.id, name.to_string()));
+                }
+            }
+        }
+        None
+    }
+
+    /// Pair each logged transaction with the change it made to the client's total funds, in transaction ID order.
+    pub(crate) fn total_changes(&self) -> Vec<(&Transaction, f64)> {
+        self.balance_changes()
+            .into_iter()
+            .map(|(transaction, (available_change, held_change))| {
+                (transaction, available_change + held_change)
+            })
+            .collect()
+    }
+
+    /// Pair each logged transaction with the changes it made to the client's available and held funds, in transaction ID order.
+    fn balance_changes(&self) -> Vec<(&Transaction, (f64, f64))> {
+        let mut changes = Vec::new();
+        for related_transactions in self.transactions.values() {
+            let (referenced_type, referenced_amount) = referenced_transaction(related_transactions)
+                .unwrap_or((TransactionType::Deposit, 0.0));
+            let referenced_withdrawal = referenced_type == TransactionType::Withdrawal;
+            let dispute_amounts = replay_disputes(related_transactions, referenced_amount).amounts;
+            for (transaction, dispute_amount) in related_transactions.iter().zip(dispute_amounts) {
+                let change = match (&transaction.transaction_type, dispute_amount) {
+                    (TransactionType::Deposit | TransactionType::OpenBalance, _) => {
+                        (transaction.amount.unwrap_or(0.0), 0.0)
+                    }
+                    (TransactionType::Withdrawal, _) => (-transaction.amount.unwrap_or(0.0), 0.0),
+                    (TransactionType::Dispute, Some(amount)) if referenced_withdrawal => {
+                        (amount, 0.0)
+                    }
+                    (TransactionType::Dispute, Some(amount)) => (-amount, amount),
+                    (TransactionType::Resolve, Some(amount)) if referenced_withdrawal => {
+                        (-amount, 0.0)
+                    }
+                    (TransactionType::Resolve, Some(amount)) => (amount, -amount),
+                    (TransactionType::Chargeback, Some(amount)) if !referenced_withdrawal => {
+                        (0.0, -amount)
+                    }
+                    _ => (0.0, 0.0),
+                };
+                changes.push((transaction, change));
+            }
+        }
+        changes
+    }
+
+    /// Age every open dispute by the transaction just applied, except the group of related transactions whose first open dispute
+    /// that transaction raised, then automatically resolve any dispute that has reached the config's auto-resolve threshold.
+    /// Ages are kept per group, so the group's oldest open dispute sets the clock: a later dispute of the same group shares its age,
+    /// and once the oldest is auto-resolved the next one is resolved after the following transaction.
+    /// A locked or frozen account is not swept, so its disputes stay open until it is unlocked or unfrozen and another transaction is applied.
+    /// If an automatic resolve fails, the dispute stays open and is retried after the next transaction.
+    fn sweep_disputes(mut self, raised_group: Option<u32>, config: &ProcessingConfig) -> Client {
+        let auto_resolve_after = match config.auto_resolve_disputes_after {
+            Some(auto_resolve_after) if !self.locked && !self.frozen => auto_resolve_after,
+            _ => return self,
+        };
+        let mut expired_disputes = Vec::new();
+        for (id, age) in self.dispute_ages.iter_mut() {
+            if raised_group != Some(*id) {
+                *age += 1;
+            }
+            if *age >= auto_resolve_after {
+                expired_disputes.push(*id);
+            }
+        }
+        for id in expired_disputes {
+            let resolve = Transaction {
+                transaction_type: TransactionType::Resolve,
+                client_id: self.id,
+                id,
+                amount: None,
+                timestamp: None,
+                currency: None,
+                ref_tx: None,
+                metadata: Default::default(),
+            };
+            // The resolve consumes the client it is applied to, so it is applied to a copy that is only kept if it succeeds.
+            self = match self.clone().apply_resolve(resolve, config) {
+                Ok(client) => {
+                    warn!("client {} auto-resolved dispute of tx {}", client.id, id);
+                    client
+                }
+                Err(error) => {
+                    warn!(
+                        "client {} failed to auto-resolve dispute of tx {}: {}",
+                        error.client.id,
+                        id,
+                        error.error_type.code()
+                    );
+                    self
+                }
+            };
+        }
+        self
+    }
+
+    /// Record the given transaction as the first overdraft if it left available funds negative and none has been recorded.
+    fn record_overdraft(&mut self, transaction_id: u32) {
+        if self.available < 0.0 && self.first_overdraft_tx.is_none() {
+            self.first_overdraft_tx = Some(transaction_id);
+        }
+    }
+
+    /// Log the transaction alongside any related transactions.
+    fn log_transaction(&mut self, transaction: Transaction) {
+        self.log_related_transaction(transaction.id, transaction);
+    }
+
+    /// Log the transaction in the group of related transactions with the given id.
+    fn log_related_transaction(&mut self, group_id: u32, transaction: Transaction) {
+        if transaction.transaction_type == TransactionType::Dispute && transaction.ref_tx.is_some()
+        {
+            self.dispute_groups
+                .entry(transaction.id)
+                .and_modify(|dispute_group| *dispute_group = group_id.min(*dispute_group))
+                .or_insert(group_id);
+        }
+        if let Some(related_transactions) = self.transactions.get_mut(&group_id) {
+            related_transactions.push(transaction);
+        } else {
+            self.transactions.insert(group_id, vec![transaction]);
+        }
+    }
+
+    /// If the config compacts transaction logs, drop the entries of the given group that no balance or future dispute depends on.
+    fn compact_log(&mut self, group_id: u32, config: &ProcessingConfig) {
+        if !config.compact_transaction_log {
+            return;
+        }
+        if let Some(related_transactions) = self.transactions.remove(&group_id) {
+            let is_named_dispute = |transaction: &Transaction| {
+                transaction.transaction_type == TransactionType::Dispute
+                    && transaction.ref_tx.is_some()
+            };
+            let dispute_ids: Vec<u32> = related_transactions
+                .iter()
+                .filter(|transaction| is_named_dispute(transaction))
+                .map(|transaction| transaction.id)
+                .collect();
+            let compacted = compact_related_transactions(related_transactions);
+            for dispute_id in dispute_ids {
+                let kept = compacted.iter().any(|transaction| {
+                    is_named_dispute(transaction) && transaction.id == dispute_id
+                });
+                if !kept && self.dispute_groups.get(&dispute_id) == Some(&group_id) {
+                    self.dispute_groups.remove(&dispute_id);
+                }
+            }
+            self.transactions.insert(group_id, compacted);
+        }
+    }
+
+    /// Find the id of the group of related transactions that a dispute, resolve, or chargeback belongs to.
+    /// Without a ref_tx this is its own id. A ref_tx naming a logged deposit or withdrawal, or any ref_tx on a dispute,
+    /// is the group id itself. A resolve or chargeback may instead name a dispute by its own id, which finds that dispute's group.
+    fn reference_group(&self, transaction: &Transaction) -> u32 {
+        let ref_tx = match transaction.ref_tx {
+            Some(ref_tx) => ref_tx,
+            None => return transaction.id,
+        };
+        if transaction.transaction_type == TransactionType::Dispute
+            || self.transactions.contains_key(&ref_tx)
+        {
+            return ref_tx;
+        }
+        self.dispute_groups.get(&ref_tx).copied().unwrap_or(ref_tx)
+    }
+}
+
+/// The disputes of a group of related transactions, replayed in log order.
+/// The ledger replays each group with this too, so ledger lines always settle disputes the same way the accounts do.
+pub(crate) struct DisputeReplay {
+    /// The amount each transaction in the group disputed or settled, or None if it had no effect on any dispute.
+    amounts: Vec<Option<f64>>,
+    /// The disputes that are still open, oldest first.
+    open: VecDeque<OpenDispute>,
+    /// For each transaction in the group, the position in the group of the dispute it settled, if it is a resolve or chargeback that settled one.
+    settled: Vec<Option<usize>>,
+    /// The own ids of the disputes in the group that gave a ref_tx, which a resolve or chargeback may name as its ref_tx.
+    dispute_ids: Vec<u32>,
+    /// The part of the referenced amount that may still be disputed: not disputed yet, or disputed and resolved.
+    undisputed_amount: f64,
+    /// Whether the referenced deposit or withdrawal has been replayed, so later disputes reference a known transaction.
+    referenced_seen: bool,
+}
+
+/// A dispute that has not been resolved or charged back, found by replaying its group of related transactions.
+struct OpenDispute {
+    /// The position of the dispute in its group of related transactions.
+    position: usize,
+    /// The dispute's own id, if it gave a ref_tx.
+    own_id: Option<u32>,
+    /// The amount held by the dispute.
+    amount: f64,
+}
+
+/// The open dispute that a resolve or chargeback settles.
+struct Settlement {
+    /// The type of the disputed transaction.
+    referenced_type: TransactionType,
+    /// The amount held by the dispute.
+    amount: f64,
+    /// How many disputes of the same transaction are still open once this one is settled.
+    still_open: usize,
+}
+
+/// The amount a transaction is applied with. If the config reads zero amounts as none, a zero amount on any transaction
+/// but a deposit, withdrawal, or opening balance is read as no amount.
+fn normalized_amount(transaction: &Transaction, config: &ProcessingConfig) -> Option<f64> {
+    let takes_amount = matches!(
+        transaction.transaction_type,
+        TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::OpenBalance
+    );
+    if config.zero_amount_as_none && !takes_amount && transaction.amount == Some(0.0) {
+        None
+    } else {
+        transaction.amount
+    }
+}
+
+/// Produce a HasMeaninglessAmount error type if a transaction that takes no amount was given one.
+fn check_no_amount(amount: Option<f64>) -> Result<(), TransactionErrorTypes> {
+    match amount {
+        Some(_) => Err(TransactionErrorTypes::HasMeaninglessAmount),
+        None => Ok(()),
+    }
+}
+
+/// Replay the disputes of a group of related transactions that references a deposit or withdrawal of the given amount.
+/// See DisputeReplay::replay.
+fn replay_disputes(related_transactions: &[Transaction], referenced_amount: f64) -> DisputeReplay {
+    let mut replay = DisputeReplay::new(referenced_amount);
+    for transaction in related_transactions {
+        replay.replay(transaction);
+    }
+    replay
+}
+
+impl DisputeReplay {
+    /// Start replaying a group of related transactions that references a deposit or withdrawal of the given amount.
+    pub(crate) fn new(referenced_amount: f64) -> DisputeReplay {
+        DisputeReplay {
+            amounts: Vec::new(),
+            open: VecDeque::new(),
+            settled: Vec::new(),
+            dispute_ids: Vec::new(),
+            undisputed_amount: referenced_amount,
+            referenced_seen: false,
+        }
+    }
+
+    /// Replay the next transaction of the group, returning the amount it disputed or settled, if any.
+    /// A dispute holds its own amount if it gave one, otherwise whatever part of the referenced amount is not already disputed or charged back.
+    /// Each resolve or chargeback settles the open dispute its ref_tx names by its own id, or otherwise the oldest open dispute,
+    /// and does nothing if that dispute is not open.
+    /// Disputes replayed before the referenced deposit or withdrawal referenced an unknown transaction, so they are ignored.
+    pub(crate) fn replay(&mut self, transaction: &Transaction) -> Option<f64> {
+        let position = self.amounts.len();
+        let mut settled = None;
+        let amount = match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                self.referenced_seen = true;
+                None
+            }
+            TransactionType::Dispute if self.referenced_seen => {
+                let amount = transaction.amount.unwrap_or(self.undisputed_amount);
+                self.undisputed_amount = round_to_four_decimals(self.undisputed_amount - amount);
+                let own_id = transaction.ref_tx.map(|_| transaction.id);
+                self.dispute_ids.extend(own_id);
+                self.open.push_back(OpenDispute {
+                    position,
+                    own_id,
+                    amount,
+                });
+                Some(amount)
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                settled = settle_open_dispute(self, transaction.ref_tx);
+                let amount = settled.as_ref().map(|dispute| dispute.amount);
+                if let (TransactionType::Resolve, Some(amount)) =
+                    (&transaction.transaction_type, amount)
+                {
+                    self.undisputed_amount =
+                        round_to_four_decimals(self.undisputed_amount + amount);
+                }
+                amount
+            }
+            _ => None,
+        };
+        self.amounts.push(amount);
+        self.settled.push(settled.map(|dispute| dispute.position));
+        amount
+    }
+}
+
+/// Remove and return the open dispute that a resolve or chargeback with the given ref_tx settles.
+/// A ref_tx naming a dispute by its own id settles that dispute, or nothing if it is no longer open.
+/// Any other resolve or chargeback settles the oldest open dispute.
+fn settle_open_dispute(replay: &mut DisputeReplay, ref_tx: Option<u32>) -> Option<OpenDispute> {
+    match ref_tx {
+        Some(dispute_id) if replay.dispute_ids.contains(&dispute_id) => {
+            let index = replay
+                .open
+                .iter()
+                .position(|dispute| dispute.own_id == Some(dispute_id))?;
+            replay.open.remove(index)
+        }
+        _ => replay.open.pop_front(),
+    }
+}
+
+/// Find the amount of the open dispute that the given resolve or chargeback would settle in a group of related transactions,
+/// along with how many disputes would still be open after it, or None if it settles nothing.
+fn settled_dispute(
+    related_transactions: &[Transaction],
+    referenced_amount: f64,
+    settlement: &Transaction,
+) -> Option<(f64, usize)> {
+    let mut replay = replay_disputes(related_transactions, referenced_amount);
+    let dispute = settle_open_dispute(&mut replay, settlement.ref_tx)?;
+    Some((dispute.amount, replay.open.len()))
+}
+
+/// Index the group of related transactions each dispute with a ref_tx is logged in by the dispute's own id,
+/// keeping the lowest group id if the same id was used more than once.
+#[cfg(feature = "std")]
+pub(crate) fn dispute_groups(transactions: &TransactionLog) -> BTreeMap<u32, u32> {
+    let mut dispute_groups = BTreeMap::new();
+    for (group_id, related_transactions) in transactions {
+        for transaction in related_transactions {
+            if transaction.transaction_type == TransactionType::Dispute
+                && transaction.ref_tx.is_some()
+            {
+                dispute_groups.entry(transaction.id).or_insert(*group_id);
+            }
+        }
+    }
+    dispute_groups
+}
+
+/// Keep only the entries of a group of related transactions that balances and future disputes depend on: every deposit,
+/// withdrawal, and opening balance, every dispute that is still open, and every charged back dispute with its chargeback.
+/// Resolved disputes and their resolves, settlements that settled nothing, and disputes logged before the referenced
+/// transaction are dropped. A kept dispute is given the amount it holds unless replaying the kept entries already gives it
+/// that amount, so the compacted group replays to the same open disputes and undisputed amount.
+/// A group with no deposit or withdrawal to reference is returned unchanged.
+fn compact_related_transactions(related_transactions: Vec<Transaction>) -> Vec<Transaction> {
+    let referenced_amount = match referenced_transaction(&related_transactions) {
+        Ok((_, amount)) => amount,
+        Err(_) => return related_transactions,
+    };
+    let replay = replay_disputes(&related_transactions, referenced_amount);
+    let charged_back: Vec<usize> = related_transactions
+        .iter()
+        .zip(&replay.settled)
+        .filter(|(transaction, _)| transaction.transaction_type == TransactionType::Chargeback)
+        .filter_map(|(_, settled)| *settled)
+        .collect();
+    let mut undisputed_amount = referenced_amount;
+    related_transactions
+        .into_iter()
+        .enumerate()
+        .filter_map(|(position, mut transaction)| {
+            let keep = match transaction.transaction_type {
+                TransactionType::Dispute => {
+                    replay
+                        .open
+                        .iter()
+                        .any(|dispute| dispute.position == position)
+                        || charged_back.contains(&position)
+                }
+                TransactionType::Resolve => false,
+                TransactionType::Chargeback => replay.settled[position].is_some(),
+                _ => true,
+            };
+            if !keep {
+                return None;
+            }
+            if let (TransactionType::Dispute, Some(amount)) =
+                (&transaction.transaction_type, replay.amounts[position])
+            {
+                if transaction.amount.is_some() || amount != undisputed_amount {
+                    transaction.amount = Some(amount);
+                }
+                undisputed_amount = round_to_four_decimals(undisputed_amount - amount);
+            }
+            Some(transaction)
+        })
+        .collect()
+}
+
+/// Find the deposit or withdrawal that a group of related transactions references, and return its type and amount.
+/// An opening balance is found the same way, so that disputing it produces a NotDisputable error.
+/// This is the first logged deposit or withdrawal, which need not be the first entry, since a dispute of an unknown transaction
+/// is logged under an id that a later deposit or withdrawal may reuse.
+/// Produces an InvalidIdReferenced error type if no deposit or withdrawal has been logged under the id,
+/// or an Unspecified error type if the group is unexpectedly empty.
+fn referenced_transaction(
+    related_transactions: &[Transaction],
+) -> Result<(TransactionType, f64), TransactionErrorTypes> {
+    if related_transactions.is_empty() {
+        return Err(TransactionErrorTypes::Unspecified);
+    }
+    related_transactions
+        .iter()
+        .find_map(
+            |transaction| match (&transaction.transaction_type, transaction.amount) {
+                (
+                    TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::OpenBalance,
+                    Some(amount),
+                ) => Some((transaction.transaction_type.clone(), amount)),
+                _ => None,
+            },
+        )
+        .ok_or(TransactionErrorTypes::InvalidIdReferenced)
+}
+
+/// The largest amount or balance that can be held to four decimal places exactly.
+/// Funds are scaled by 10000 when rounded, and above this magnitude the scaled value passes 2^53,
+/// beyond which an f64 can no longer represent every integer, so balances would silently lose precision.
+pub const MAX_SAFE_AMOUNT: f64 = 900_719_925_474.099_1;
+
+/// Returns true if the given deposit or withdrawal amount is below the config's minimum deposit or above its maximum amount,
+/// or above MAX_SAFE_AMOUNT whatever the config. Amounts exactly at either bound are allowed.
+fn is_out_of_bounds(
+    transaction_type: &TransactionType,
+    amount: f64,
+    config: &ProcessingConfig,
+) -> bool {
+    let below_minimum = *transaction_type == TransactionType::Deposit
+        && config
+            .min_deposit
+            .is_some_and(|min_deposit| amount < min_deposit);
+    let above_maximum = amount > MAX_SAFE_AMOUNT
+        || config
+            .max_amount
+            .is_some_and(|max_amount| amount > max_amount);
+    below_minimum || above_maximum
+}
+
+/// Order items holding a client's logged transactions, each found with the given function, by timestamp
+/// if every one of them has a timestamp, otherwise leave them in ID order.
+pub(crate) fn replay_order<T>(
+    mut items: Vec<T>,
+    transaction: impl Fn(&T) -> &Transaction,
+) -> Vec<T> {
+    if items
+        .iter()
+        .all(|item| transaction(item).timestamp.is_some())
+    {
+        items.sort_by_key(|item| transaction(item).timestamp);
+    }
+    items
+}
+
+/// Rounds any f64 to four decimal places, rounding halves away from zero.
+pub(crate) fn round_to_four_decimals(n: f64) -> f64 {
+    RoundingMode::HalfUp.round(n)
+}
+
+/// When serializing funds, attempt to round to four decimal places.
+/// Balances are rounded with the configured rounding mode as transactions are applied, and the account and audit output
+/// round them with it again before serializing, so re-rounding the four decimal value here leaves it unchanged
+/// and the configured mode is preserved in the output.
+/// A negative zero, from a tiny negative artifact of float arithmetic rounding away, is written as 0.0 rather than -0.0.
+fn four_decimal_serializer<S>(n: &f64, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let rounded = round_to_four_decimals(*n);
+    s.serialize_f64(if rounded == 0.0 { 0.0 } else { rounded })
+}
+
+#[test]
+fn test_deposit() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let client = client
+        .apply_deposit(
+            Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 0,
+                id: 1,
+                amount: Some(1.3),
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    assert_eq!(client.available, 2.5);
+    assert_eq!(client.held, 0.0);
+    assert_eq!(client.total, 2.5);
+    assert!(!client.locked);
+}
+
+#[test]
+fn test_withdrawal() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let client = client
+        .apply_withdrawal(
+            Transaction {
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 0,
+                id: 1,
+                amount: Some(1.1),
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    assert_eq!(client.available, 0.1);
+    assert_eq!(client.held, 0.0);
+    assert_eq!(client.total, 0.1);
+    assert!(!client.locked);
+}
+
+#[test]
+fn test_withdrawal_overdraft_limit() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.0),
+        ..Default::default()
+    })
+    .unwrap();
+    let config = ProcessingConfig {
+        overdraft_limit: 0.5,
+        ..Default::default()
+    };
+    let withdrawal = |amount: f64| Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 0,
+        id: 1,
+        amount: Some(amount),
+        ..Default::default()
+    };
+
+    let below = client
+        .clone()
+        .apply_withdrawal(withdrawal(1.4), &config)
+        .unwrap();
+    assert_eq!(below.available, -0.4);
+    assert_eq!(below.total, -0.4);
+
+    let at = client
+        .clone()
+        .apply_withdrawal(withdrawal(1.5), &config)
+        .unwrap();
+    assert_eq!(at.available, -0.5);
+    assert_eq!(at.total, -0.5);
+
+    let above = client
+        .clone()
+        .apply_withdrawal(withdrawal(1.6), &config)
+        .unwrap_err();
+    assert!(matches!(
+        above.error_type,
+        TransactionErrorTypes::InsufficientFunds
+    ));
+    assert_eq!(above.client.available, 1.0);
+
+    let without_limit = client
+        .apply_withdrawal(withdrawal(1.4), &ProcessingConfig::default())
+        .unwrap_err();
+    assert!(matches!(
+        without_limit.error_type,
+        TransactionErrorTypes::InsufficientFunds
+    ));
+}
+
+#[test]
+fn test_dispute() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let client = client
+        .apply_dispute(
+            Transaction {
+                transaction_type: TransactionType::Dispute,
+                client_id: 0,
+                id: 0,
+                amount: None,
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    assert_eq!(client.available, 0.0);
+    assert_eq!(client.held, 1.2);
+    assert_eq!(client.total, 1.2);
+    assert!(!client.locked);
+}
+
+#[test]
+fn test_resolve() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let client = client
+        .apply_dispute(
+            Transaction {
+                transaction_type: TransactionType::Dispute,
+                client_id: 0,
+                id: 0,
+                amount: None,
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    let client = client
+        .apply_resolve(
+            Transaction {
+                transaction_type: TransactionType::Resolve,
+                client_id: 0,
+                id: 0,
+                amount: None,
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    assert_eq!(client.available, 1.2);
+    assert_eq!(client.held, 0.0);
+    assert_eq!(client.total, 1.2);
+    assert!(!client.locked);
+}
+
+#[test]
+fn test_chargeback() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let client = client
+        .apply_dispute(
+            Transaction {
+                transaction_type: TransactionType::Dispute,
+                client_id: 0,
+                id: 0,
+                amount: None,
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    let client = client
+        .apply_chargeback(
+            Transaction {
+                transaction_type: TransactionType::Chargeback,
+                client_id: 0,
+                id: 0,
+                amount: None,
+                ..Default::default()
+            },
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+    assert_eq!(client.available, 0.0);
+    assert_eq!(client.held, 0.0);
+    assert_eq!(client.total, 0.0);
+    assert!(client.locked);
+}
+
+#[test]
+fn test_zero_amount_as_none() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let dispute = Transaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 0,
+        id: 0,
+        amount: Some(0.0),
+        ..Default::default()
+    };
+    let error = client
+        .clone()
+        .apply_transaction(dispute.clone())
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::HasMeaninglessAmount
+    ));
+    let config = ProcessingConfig {
+        zero_amount_as_none: true,
+        ..Default::default()
+    };
+    let client = client
+        .apply_transaction_with_config(dispute, &config)
+        .unwrap();
+    assert_eq!(client.available, 0.0);
+    assert_eq!(client.held, 1.2);
+    assert_eq!(client.total, 1.2);
+    assert!(!client.locked);
+}
+
+#[test]
+fn test_dispute_outcome() {
+    let mut client = Client::new(0);
+    let transactions = [
+        (TransactionType::Deposit, 0, Some(1.2)),
+        (TransactionType::Deposit, 1, Some(3.0)),
+        (TransactionType::Deposit, 2, Some(0.5)),
+        (TransactionType::Dispute, 0, None),
+        (TransactionType::Resolve, 0, None),
+        (TransactionType::Resolve, 2, None),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Chargeback, 1, None),
+    ];
+    for (transaction_type, id, amount) in transactions {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 0,
+                id,
+                amount,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let outcome = client.dispute_outcome();
+    assert_eq!(outcome.resolved_amount, 1.2);
+    assert_eq!(outcome.charged_back_amount, 3.0);
+}
+
+#[test]
+fn test_time_weighted_average_balance() {
+    let mut client = Client::new(0);
+    let transactions = [
+        (TransactionType::Deposit, 0, Some(10.0), 0),
+        (TransactionType::Withdrawal, 1, Some(4.0), 5),
+        (TransactionType::Deposit, 2, Some(2.0), 8),
+    ];
+    for (transaction_type, id, amount, timestamp) in transactions {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 0,
+                id,
+                amount,
+                timestamp: Some(timestamp),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    assert_eq!(client.time_weighted_average_balance(0, 10), 8.4);
+    assert_eq!(client.time_weighted_average_balance(5, 10), 6.8);
+    assert_eq!(client.time_weighted_average_balance(9, 9), 8.0);
+}
+
+#[test]
+fn test_initialize_client_with_reference_transaction() {
+    for transaction_type in [
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+    ] {
+        let error = initialize_client(Transaction {
+            transaction_type,
+            client_id: 0,
+            id: 0,
+            amount: None,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(matches!(
+            error.error_type,
+            TransactionErrorTypes::AccountNotFound
+        ));
+        assert!(error.to_string().contains("account that does not exist"));
+    }
+    let error = initialize_client(Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.0),
+        ..Default::default()
+    })
+    .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::FirstTransactionNotDeposit
+    ));
+}
+
+#[test]
+fn test_dispute_matrix() {
+    // (disputed id, follow up type, (available, held, total) after dispute, (available, held, total) after follow up)
+    let cases = [
+        (
+            0,
+            TransactionType::Resolve,
+            (-4.0, 10.0, 6.0),
+            (6.0, 0.0, 6.0),
+        ),
+        (
+            0,
+            TransactionType::Chargeback,
+            (-4.0, 10.0, 6.0),
+            (-4.0, 0.0, -4.0),
+        ),
+        (
+            1,
+            TransactionType::Resolve,
+            (10.0, 0.0, 10.0),
+            (6.0, 0.0, 6.0),
+        ),
+        (
+            1,
+            TransactionType::Chargeback,
+            (10.0, 0.0, 10.0),
+            (10.0, 0.0, 10.0),
+        ),
+    ];
+    for (id, follow_up_type, after_dispute, after_follow_up) in cases {
+        let locks = follow_up_type == TransactionType::Chargeback;
+        let client = initialize_client(Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 0,
+            id: 0,
+            amount: Some(10.0),
+            ..Default::default()
+        })
+        .unwrap()
+        .apply_transaction(Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            client_id: 0,
+            id: 1,
+            amount: Some(4.0),
+            ..Default::default()
+        })
+        .unwrap();
+        let client = client
+            .apply_dispute(
+                Transaction {
+                    transaction_type: TransactionType::Dispute,
+                    client_id: 0,
+                    id,
+                    amount: None,
+                    ..Default::default()
+                },
+                &ProcessingConfig::default(),
+            )
+            .unwrap();
+        assert_eq!((client.available, client.held, client.total), after_dispute);
+        let client = client
+            .apply_transaction(Transaction {
+                transaction_type: follow_up_type,
+                client_id: 0,
+                id,
+                amount: None,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            (client.available, client.held, client.total),
+            after_follow_up
+        );
+        assert_eq!(client.locked, locks);
+    }
+}
+
+#[test]
+fn test_concurrent_disputes() {
+    let mut client = Client::new(0);
+    for (id, amount) in [(0, 1.5), (1, 2.25), (2, 3.0), (3, 4.0)] {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 0,
+                id,
+                amount: Some(amount),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let config = ProcessingConfig {
+        limit_held_to_total: true,
+        ..Default::default()
+    };
+    for id in 0..3 {
+        client = client
+            .apply_dispute(
+                Transaction {
+                    transaction_type: TransactionType::Dispute,
+                    client_id: 0,
+                    id,
+                    amount: None,
+                    ..Default::default()
+                },
+                &config,
+            )
+            .unwrap();
+        assert!(client.held <= client.total);
+    }
+    assert_eq!(client.held, 6.75);
+    assert_eq!(client.available, 4.0);
+    assert_eq!(client.total, 10.75);
+}
+
+#[test]
+fn test_dispute_limited_to_total() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(10.0),
+        ..Default::default()
+    })
+    .unwrap()
+    .apply_transaction(Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 0,
+        id: 1,
+        amount: Some(8.0),
+        ..Default::default()
+    })
+    .unwrap();
+    let dispute = Transaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 0,
+        id: 0,
+        amount: None,
+        ..Default::default()
+    };
+    let config = ProcessingConfig {
+        limit_held_to_total: true,
+        ..Default::default()
+    };
+    let error = client
+        .clone()
+        .apply_dispute(dispute.clone(), &config)
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::InsufficientFunds
+    ));
+    assert_eq!(error.client.held, 0.0);
+
+    let client = client
+        .apply_dispute(dispute, &ProcessingConfig::default())
+        .unwrap();
+    assert_eq!(client.available, -8.0);
+    assert_eq!(client.held, 10.0);
+    assert_eq!(client.total, 2.0);
+}
+
+#[test]
+fn test_closing_statement() {
+    let mut client = Client::new(0);
+    let transactions = [
+        (TransactionType::Deposit, 0, Some(5.0)),
+        (TransactionType::Deposit, 1, Some(2.5)),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Resolve, 1, None),
+        (TransactionType::Withdrawal, 2, Some(3.0)),
+        (TransactionType::Withdrawal, 3, Some(4.5)),
+    ];
+    for (transaction_type, id, amount) in transactions {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 0,
+                id,
+                amount,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let statement = client.closing_statement();
+    assert_eq!(statement.client_id, 0);
+    assert_eq!(statement.lifetime_deposits, 7.5);
+    assert_eq!(statement.lifetime_withdrawals, 7.5);
+    assert_eq!(statement.dispute_count, 1);
+    assert_eq!(statement.dispute_outcome.resolved_amount, 2.5);
+    assert_eq!(statement.dispute_outcome.charged_back_amount, 0.0);
+    assert_eq!(statement.available, 0.0);
+    assert_eq!(statement.held, 0.0);
+    assert_eq!(statement.total, 0.0);
+    assert!(!statement.locked);
+}
+
+/// Collects log messages so tests can assert that they fire.
+#[cfg(test)]
+struct TestLogger {
+    messages: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl log::Log for TestLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+static TEST_LOGGER: TestLogger = TestLogger {
+    messages: std::sync::Mutex::new(Vec::new()),
+};
+
+#[test]
+fn test_resolve_partially_withdrawn_dispute() {
+    let mut client = Client::new(0);
+    let transactions = [
+        (TransactionType::Deposit, 0, Some(10.0)),
+        (TransactionType::Deposit, 1, Some(1.0)),
+        (TransactionType::Withdrawal, 2, Some(8.0)),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Dispute, 0, None),
+    ];
+    for (transaction_type, id, amount) in transactions {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 0,
+                id,
+                amount,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    assert_eq!(client.available, -8.0);
+    assert_eq!(client.held, 11.0);
+    assert_eq!(client.total, 3.0);
+
+    let resolve = Transaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 0,
+        id: 0,
+        amount: None,
+        ..Default::default()
+    };
+    let resolved = client
+        .clone()
+        .apply_resolve(resolve.clone(), &ProcessingConfig::default())
+        .unwrap();
+    assert_eq!(resolved.available, 2.0);
+    assert_eq!(resolved.held, 1.0);
+    assert_eq!(resolved.total, 3.0);
+
+    client.held = 5.0;
+    let error = client
+        .apply_resolve(resolve, &ProcessingConfig::default())
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::InsufficientFunds
+    ));
+    assert_eq!(error.client.held, 5.0);
+}
+
+#[test]
+fn test_currency() {
+    let deposit = |id: u32, currency: Option<&str>| Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id,
+        amount: Some(1.0),
+        currency: currency.map(String::from),
+        ..Default::default()
+    };
+    let client = initialize_client(deposit(0, Some("USD"))).unwrap();
+    assert_eq!(client.currency.as_deref(), Some("USD"));
+    let client = client
+        .apply_transaction(deposit(1, Some("USD")))
+        .unwrap()
+        .apply_transaction(deposit(2, None))
+        .unwrap();
+    assert_eq!(client.total, 3.0);
+
+    let error = client
+        .apply_transaction(deposit(3, Some("EUR")))
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::CurrencyMismatch
+    ));
+    assert_eq!(error.client.total, 3.0);
+
+    let client = initialize_client(deposit(0, None))
+        .unwrap()
+        .apply_transaction(deposit(1, Some("EUR")))
+        .unwrap();
+    assert_eq!(client.currency.as_deref(), Some("EUR"));
+}
+
+#[test]
+fn test_dispute_logging() {
+    // Other tests may log concurrently, so only check that the expected messages are present.
+    let _ = log::set_logger(&TEST_LOGGER);
+    log::set_max_level(log::LevelFilter::Debug);
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 7,
+        id: 1,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let reference_types = [
+        (TransactionType::Dispute, 42),
+        (TransactionType::Resolve, 1),
+        (TransactionType::Dispute, 1),
+        (TransactionType::Chargeback, 1),
+    ];
+    let mut client = client;
+    for (transaction_type, id) in reference_types {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 7,
+                id,
+                amount: None,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let messages = TEST_LOGGER.messages.lock().unwrap();
+    for expected in [
+        "DEBUG ignoring dispute for unknown tx 42 on client 7",
+        "DEBUG ignoring resolve for tx 1 on client 7 because it is not disputed",
+        "DEBUG client 7 disputed tx 1",
+        "WARN account 7 locked by chargeback of tx 1",
+    ] {
+        assert!(messages.iter().any(|message| message == expected));
+    }
+}
+
+#[test]
+fn test_log_transaction() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let transaction_one = Transaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 0,
+        id: 0,
+        amount: None,
+        ..Default::default()
+    };
+    let transaction_two = transaction_one.clone();
+    let client = client
+        .apply_dispute(transaction_one, &ProcessingConfig::default())
+        .unwrap();
+    assert_eq!(client.transactions[&0][1], transaction_two);
+}
+
+#[test]
+fn test_audit_record_json() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 3,
+        id: 2,
+        amount: Some(2.0),
+        ..Default::default()
+    })
+    .unwrap()
+    .apply_transaction(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 3,
+        id: 1,
+        amount: Some(1.5),
+        ..Default::default()
+    })
+    .unwrap()
+    .apply_transaction(Transaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 3,
+        id: 2,
+        amount: None,
+        ..Default::default()
+    })
+    .unwrap();
+    let json = serde_json::to_string(&client.audit_record()).unwrap();
+    assert!(
+        json.starts_with(r#"{"client":3,"available":1.5,"held":2.0,"total":3.5,"locked":false"#)
+    );
+    let first_deposit = json
+        .find(r#"{"type":"deposit","client":3,"tx":1,"amount":1.5"#)
+        .unwrap();
+    let second_deposit = json
+        .find(r#"{"type":"deposit","client":3,"tx":2,"amount":2.0"#)
+        .unwrap();
+    let dispute = json
+        .find(r#"{"type":"dispute","client":3,"tx":2,"amount":null"#)
+        .unwrap();
+    assert!(first_deposit < second_deposit);
+    assert!(second_deposit < dispute);
+
+    let csv_row = {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&client).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    };
+    assert!(!csv_row.contains("dispute"));
+}
+
+#[test]
+fn test_first_negative_point() {
+    let deposit = Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 5,
+        id: 1,
+        amount: Some(2.0),
+        ..Default::default()
+    };
+    let withdrawal = Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 5,
+        id: 2,
+        amount: Some(1.5),
+        ..Default::default()
+    };
+    let dispute = Transaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 5,
+        id: 1,
+        amount: None,
+        ..Default::default()
+    };
+    let client = initialize_client(deposit.clone())
+        .unwrap()
+        .apply_transaction(withdrawal.clone())
+        .unwrap();
+    assert_eq!(client.first_negative_point(), None);
+
+    // Timestamps make the replay follow the order the transactions were applied, rather than transaction ID order.
+    let client = initialize_client(Transaction {
+        timestamp: Some(1),
+        ..deposit
+    })
+    .unwrap()
+    .apply_transaction(Transaction {
+        timestamp: Some(2),
+        ..withdrawal.clone()
+    })
+    .unwrap()
+    .apply_transaction(Transaction {
+        timestamp: Some(3),
+        ..dispute
+    })
+    .unwrap();
+    assert_eq!(client.available, -1.5);
+    assert_eq!(
+        client.first_negative_point(),
+        Some((1, "available".to_string()))
+    );
+
+    let config = ProcessingConfig {
+        overdraft_limit: 5.0,
+        ..Default::default()
+    };
+    let client = Client::new(5)
+        .apply_transaction_with_config(
+            Transaction {
+                id: 3,
+                amount: Some(1.0),
+                transaction_type: TransactionType::Deposit,
+                ..withdrawal.clone()
+            },
+            &config,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            Transaction {
+                id: 4,
+                amount: Some(3.0),
+                ..withdrawal
+            },
+            &config,
+        )
+        .unwrap();
+    assert_eq!(
+        client.first_negative_point(),
+        Some((4, "available".to_string()))
+    );
+}
+
+#[test]
+fn test_rounding_mode_applies_to_balances() {
+    let deposit = Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(0.00005),
+        ..Default::default()
+    };
+    let half_up = initialize_client(deposit.clone()).unwrap();
+    assert_eq!(half_up.available, 0.0001);
+    let half_even = initialize_client_with_config(
+        deposit.clone(),
+        &ProcessingConfig {
+            rounding_mode: RoundingMode::HalfEven,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(half_even.available, 0.0);
+    let truncate = initialize_client_with_config(
+        Transaction {
+            amount: Some(0.00019),
+            ..deposit
+        },
+        &ProcessingConfig {
+            rounding_mode: RoundingMode::Truncate,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(truncate.total, 0.0001);
+}
+
+#[test]
+fn test_non_finite_amounts_rejected() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 0,
+        id: 0,
+        amount: Some(1.2),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut amounts: Vec<f64> = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader("nan\ninf\n-inf\n".as_bytes())
+        .deserialize()
+        .map(|amount| amount.unwrap())
+        .collect();
+    amounts.extend([f64::NAN, f64::INFINITY]);
+    for amount in amounts {
+        for (id, transaction_type) in [
+            (1, TransactionType::Deposit),
+            (2, TransactionType::Withdrawal),
+        ] {
+            let error = client
+                .clone()
+                .apply_transaction(Transaction {
+                    transaction_type,
+                    client_id: 0,
+                    id,
+                    amount: Some(amount),
+                    ..Default::default()
+                })
+                .unwrap_err();
+            assert_eq!(error.code(), "NON_FINITE_AMOUNT");
+            assert_eq!(error.client.available, 1.2);
+            assert_eq!(error.client.total, 1.2);
+        }
+    }
+}
+
+#[test]
+fn test_amount_bounds() {
+    let config = ProcessingConfig {
+        min_deposit: Some(1.0),
+        max_amount: Some(100.0),
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount: Some(amount),
+        ..Default::default()
+    };
+    let client =
+        initialize_client_with_config(transaction(TransactionType::Deposit, 0, 1.0), &config)
+            .unwrap();
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Deposit, 1, 100.0), &config)
+        .unwrap();
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Withdrawal, 2, 100.0), &config)
+        .unwrap();
+    assert_eq!(client.total, 1.0);
+
+    for (transaction_type, amount) in [
+        (TransactionType::Deposit, 0.9999),
+        (TransactionType::Deposit, 100.0001),
+        (TransactionType::Withdrawal, 100.0001),
+    ] {
+        let error = client
+            .clone()
+            .apply_transaction_with_config(transaction(transaction_type, 3, amount), &config)
+            .unwrap_err();
+        assert!(matches!(
+            error.error_type,
+            TransactionErrorTypes::AmountOutOfBounds(value) if value == amount
+        ));
+        assert_eq!(error.client.total, 1.0);
+    }
+
+    // The minimum only applies to deposits, and no bounds apply by default.
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Withdrawal, 3, 0.5), &config)
+        .unwrap();
+    assert!(client
+        .apply_transaction(transaction(TransactionType::Deposit, 4, 1000.0))
+        .is_ok());
+}
+
+#[test]
+fn test_unlock() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(2.0)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Deposit, 1, Some(1.0)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Dispute, 1, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Chargeback, 1, None))
+        .unwrap();
+    assert!(client.locked);
+
+    let error = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Deposit, 2, Some(1.0)))
+        .unwrap_err();
+    assert_eq!(error.code(), "ACCOUNT_LOCKED");
+
+    let error = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Unlock, 3, Some(1.0)))
+        .unwrap_err();
+    assert_eq!(error.code(), "HAS_MEANINGLESS_AMOUNT");
+    assert!(error.client.locked);
+
+    let client = client
+        .apply_transaction(transaction(TransactionType::Unlock, 3, None))
+        .unwrap();
+    assert!(!client.locked);
+    assert!(!client.transactions.contains_key(&3));
+    let client = client
+        .apply_transaction(transaction(TransactionType::Deposit, 2, Some(1.0)))
+        .unwrap();
+    assert_eq!(client.available, 3.0);
+    assert_eq!(client.total, 3.0);
+
+    let error = initialize_client(transaction(TransactionType::Unlock, 0, None)).unwrap_err();
+    assert_eq!(error.code(), "ACCOUNT_NOT_FOUND");
+}
+
+#[test]
+fn test_check_invariant_detects_inconsistent_client() {
+    let mut client = Client::new(0);
+    client.available = 1.0;
+    client.held = 0.5;
+    client.total = 1.5;
+    assert!(client.check_invariant());
+    client.held = 0.4999;
+    assert!(!client.check_invariant());
+}
+
+#[cfg(feature = "invariant-checks")]
+#[test]
+fn test_invariant_violation_error() {
+    let mut client = Client::new(0);
+    client.total = 1.0;
+    let error = client
+        .apply_transaction(Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 0,
+            id: 0,
+            amount: Some(1.0),
+            ..Default::default()
+        })
+        .unwrap_err();
+    assert_eq!(error.code(), "INVARIANT_VIOLATION");
+    assert_eq!(error.transaction.id, 0);
+}
+
+#[test]
+fn test_invariant_holds_after_random_transactions() {
+    // A small linear congruential generator keeps the sequences random but reproducible.
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = |bound: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % bound
+    };
+    for _ in 0..200 {
+        let mut client = Client::new(0);
+        let mut next_id: u32 = 0;
+        // Only reference transactions that were applied, since disputes of unknown IDs are outside this test.
+        let mut applied_ids: Vec<u32> = Vec::new();
+        for _ in 0..30 {
+            let choice = if applied_ids.is_empty() { 0 } else { next(6) };
+            let (transaction_type, id, amount) = match choice {
+                0..=2 => {
+                    next_id += 1;
+                    let amount = Some((next(100_000) + 1) as f64 / 100.0);
+                    if choice == 2 {
+                        (TransactionType::Withdrawal, next_id, amount)
+                    } else {
+                        (TransactionType::Deposit, next_id, amount)
+                    }
+                }
+                choice => {
+                    let id = applied_ids[next(applied_ids.len() as u64) as usize];
+                    let transaction_type = match choice {
+                        3 => TransactionType::Dispute,
+                        4 => TransactionType::Resolve,
+                        _ => TransactionType::Chargeback,
+                    };
+                    (transaction_type, id, None)
+                }
+            };
+            let references_new_id = amount.is_some();
+            let transaction = Transaction {
+                transaction_type,
+                client_id: 0,
+                id,
+                amount,
+                ..Default::default()
+            };
+            client = match client.clone().apply_transaction(transaction) {
+                Ok(client) => {
+                    if references_new_id {
+                        applied_ids.push(id);
+                    }
+                    client
+                }
+                Err(_) => client,
+            };
+            assert!(client.check_invariant(), "{:#?}", client);
+        }
+    }
+}
+
+#[test]
+fn test_partial_disputes() {
+    let config = ProcessingConfig {
+        allow_partial_disputes: true,
+        ..Default::default()
+    };
+    let transaction = |transaction_type, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id: 0,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, Some(10.0))).unwrap();
+
+    let error = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Dispute, Some(4.0)))
+        .unwrap_err();
+    assert_eq!(error.code(), "HAS_MEANINGLESS_AMOUNT");
+
+    let error = client
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, Some(10.5)), &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "INVALID_DISPUTE_AMOUNT");
+    assert_eq!(error.client.held, 0.0);
+
+    let error = client
+        .clone()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, Some(f64::NAN)),
+            &config,
+        )
+        .unwrap_err();
+    assert_eq!(error.code(), "INVALID_DISPUTE_AMOUNT");
+    assert_eq!(error.client.held, 0.0);
+    assert_eq!(error.client.available, 10.0);
+
+    let partial = client
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, Some(4.0)), &config)
+        .unwrap();
+    assert_eq!(partial.available, 6.0);
+    assert_eq!(partial.held, 4.0);
+    assert_eq!(partial.total, 10.0);
+    let resolved = partial
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Resolve, None), &config)
+        .unwrap();
+    assert_eq!(resolved.available, 10.0);
+    assert_eq!(resolved.held, 0.0);
+    let charged_back = partial
+        .apply_transaction_with_config(transaction(TransactionType::Chargeback, None), &config)
+        .unwrap();
+    assert_eq!(charged_back.available, 6.0);
+    assert_eq!(charged_back.held, 0.0);
+    assert_eq!(charged_back.total, 6.0);
+    assert_eq!(charged_back.dispute_outcome().charged_back_amount, 4.0);
+    assert!(charged_back.check_invariant());
+
+    let full = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, None), &config)
+        .unwrap();
+    assert_eq!(full.available, 0.0);
+    assert_eq!(full.held, 10.0);
+}
+
+#[test]
+fn test_account_state_matches_client_csv() {
+    let client = initialize_client(Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 7,
+        id: 0,
+        amount: Some(1.23456),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+    let mut client_writer = csv::Writer::from_writer(Vec::new());
+    client_writer.serialize(&client).unwrap();
+    let (state, transactions) = client.into_parts();
+    assert_eq!(transactions[&0].len(), 1);
+    let mut state_writer = csv::Writer::from_writer(Vec::new());
+    state_writer.serialize(&state).unwrap();
+    assert_eq!(
+        state_writer.into_inner().unwrap(),
+        client_writer.into_inner().unwrap()
+    );
+}
+
+#[test]
+fn test_max_total_withdrawal() {
+    let config = ProcessingConfig {
+        max_total_withdrawal: Some(5.0),
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(20.0))).unwrap();
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Withdrawal, 1, Some(3.0)),
+            &config,
+        )
+        .unwrap();
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Withdrawal, 2, Some(2.0)),
+            &config,
+        )
+        .unwrap();
+    assert_eq!(client.withdrawn_total, 5.0);
+    let error = client
+        .clone()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Withdrawal, 3, Some(0.5)),
+            &config,
+        )
+        .unwrap_err();
+    assert_eq!(error.code(), "WITHDRAWAL_LIMIT_EXCEEDED");
+    assert_eq!(error.client.available, 15.0);
+
+    let disputed = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, None), &config)
+        .unwrap();
+    assert_eq!(disputed.withdrawn_total, 2.0);
+    let resolved = disputed
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Resolve, 1, None), &config)
+        .unwrap();
+    assert_eq!(resolved.withdrawn_total, 5.0);
+    let charged_back = disputed
+        .apply_transaction_with_config(transaction(TransactionType::Chargeback, 1, None), &config)
+        .unwrap();
+    assert_eq!(charged_back.withdrawn_total, 2.0);
+
+    let client = resolved
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 2, None), &config)
+        .unwrap();
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Withdrawal, 3, Some(2.0)),
+            &config,
+        )
+        .unwrap();
+    assert_eq!(client.withdrawn_total, 5.0);
+}
+
+#[test]
+fn test_dispute_of_colliding_id() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(1.0))).unwrap();
+    // A dispute of an unknown transaction is logged, so its id now has a log entry without an amount.
+    let client = client
+        .apply_transaction(transaction(TransactionType::Dispute, 5, None))
+        .unwrap();
+    for transaction_type in [
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+    ] {
+        let error = client
+            .clone()
+            .apply_transaction(transaction(transaction_type, 5, None))
+            .unwrap_err();
+        assert_eq!(error.code(), "INVALID_ID_REFERENCED");
+        assert_eq!(error.client.available, 1.0);
+        assert_eq!(error.client.held, 0.0);
+    }
+
+    // Once a deposit reuses the id, disputes reference it rather than the earlier logged dispute.
+    let client = client
+        .apply_transaction(transaction(TransactionType::Deposit, 5, Some(2.0)))
+        .unwrap();
+    let client = client
+        .apply_transaction(transaction(TransactionType::Dispute, 5, None))
+        .unwrap();
+    assert_eq!(client.available, 1.0);
+    assert_eq!(client.held, 2.0);
+    assert!(client.check_invariant());
+    let client = client
+        .apply_transaction(transaction(TransactionType::Chargeback, 5, None))
+        .unwrap();
+    assert_eq!(client.held, 0.0);
+    assert_eq!(client.total, 1.0);
+    assert_eq!(client.dispute_outcome().charged_back_amount, 2.0);
+    assert!(client.check_invariant());
+}
+
+#[test]
+fn test_dispute_of_empty_log_is_unspecified_error() {
+    let mut client = Client::new(0);
+    client.transactions.insert(5, Vec::new());
+    let error = client
+        .apply_transaction(Transaction {
+            transaction_type: TransactionType::Dispute,
+            client_id: 0,
+            id: 5,
+            amount: None,
+            ..Default::default()
+        })
+        .unwrap_err();
+    assert_eq!(error.code(), "UNSPECIFIED");
+}
+
+#[test]
+fn test_auto_resolve_disputes() {
+    let config = ProcessingConfig {
+        auto_resolve_disputes_after: Some(2),
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(5.0))).unwrap();
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 0, None), &config)
+        .unwrap();
+    assert_eq!(client.held, 5.0);
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Deposit, 1, Some(1.0)), &config)
+        .unwrap();
+    assert_eq!(client.held, 5.0);
+    assert_eq!(client.dispute_ages[&0], 1);
+
+    // A timely resolve pre-empts the automatic one.
+    let resolved = client
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Resolve, 0, None), &config)
+        .unwrap();
+    assert_eq!(resolved.available, 6.0);
+    assert!(resolved.dispute_ages.is_empty());
+    assert_eq!(resolved.transactions[&0].len(), 3);
+
+    // Otherwise the second later transaction resolves the dispute automatically.
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Deposit, 2, Some(1.0)), &config)
+        .unwrap();
+    assert_eq!(client.available, 7.0);
+    assert_eq!(client.held, 0.0);
+    assert_eq!(client.total, 7.0);
+    assert!(client.dispute_ages.is_empty());
+    assert_eq!(
+        client.transactions[&0][2].transaction_type,
+        TransactionType::Resolve
+    );
+    assert_eq!(client.dispute_outcome().resolved_amount, 5.0);
+
+    // Without the policy, disputes stay open.
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(5.0))).unwrap();
+    let mut client = client
+        .apply_transaction(transaction(TransactionType::Dispute, 0, None))
+        .unwrap();
+    for id in 1..5 {
+        client = client
+            .apply_transaction(transaction(TransactionType::Deposit, id, Some(1.0)))
+            .unwrap();
+    }
+    assert_eq!(client.held, 5.0);
+}
+
+#[test]
+fn test_auto_resolve_ref_tx_disputes() {
+    let config = ProcessingConfig {
+        auto_resolve_disputes_after: Some(1),
+        allow_partial_disputes: true,
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount, ref_tx| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ref_tx,
+        ..Default::default()
+    };
+    let client =
+        initialize_client(transaction(TransactionType::Deposit, 0, Some(5.0), None)).unwrap();
+
+    // A dispute with its own id is aged from the next transaction, not by the row that raised it.
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 10, Some(2.0), Some(0)),
+            &config,
+        )
+        .unwrap();
+    assert_eq!(client.held, 2.0);
+    assert_eq!(client.dispute_ages[&0], 0);
+
+    // A second dispute of the same transaction shares the group's clock, which the first dispute started,
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 11, Some(3.0), Some(0)),
+            &config,
+        )
+        .unwrap();
+    // It ages the first dispute, which is auto-resolved, leaving only the second one's funds held.
+    assert_eq!(client.held, 3.0);
+    assert_eq!(
+        client.transactions[&0][3].transaction_type,
+        TransactionType::Resolve
+    );
+
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Deposit, 1, Some(1.0), None),
+            &config,
+        )
         .unwrap();
-    assert_eq!(client.available, 2.5);
     assert_eq!(client.held, 0.0);
-    assert_eq!(client.total, 2.5);
-    assert_eq!(client.locked, false);
+    assert_eq!(client.available, 6.0);
+    assert!(client.dispute_ages.is_empty());
 }
 
 #[test]
-fn test_withdrawal() {
-    let client = initialize_client(Transaction {
-        transaction_type: TransactionType::Deposit,
+fn test_disputable_types() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
         client_id: 0,
-        id: 0,
-        amount: Some(1.2),
-    })
-    .unwrap();
-    let client = client
-        .apply_withdrawal(Transaction {
-            transaction_type: TransactionType::Withdrawal,
-            client_id: 0,
-            id: 1,
-            amount: Some(1.1),
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(5.0)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Withdrawal, 1, Some(2.0)))
+        .unwrap();
+    let dispute_both = |client: &Client, config: &ProcessingConfig| {
+        [0, 1].map(|id| {
+            client
+                .clone()
+                .apply_transaction_with_config(
+                    transaction(TransactionType::Dispute, id, None),
+                    config,
+                )
+                .map_err(|error| error.code())
+                .map(|client| client.available)
         })
+    };
+
+    let config = ProcessingConfig::default();
+    assert_eq!(dispute_both(&client, &config), [Ok(-2.0), Ok(5.0)]);
+
+    let config = ProcessingConfig {
+        disputable_types: [TransactionType::Deposit].into_iter().collect(),
+        ..Default::default()
+    };
+    assert_eq!(
+        dispute_both(&client, &config),
+        [Ok(-2.0), Err("NOT_DISPUTABLE")]
+    );
+
+    let config = ProcessingConfig {
+        disputable_types: Default::default(),
+        ..Default::default()
+    };
+    assert_eq!(
+        dispute_both(&client, &config),
+        [Err("NOT_DISPUTABLE"), Err("NOT_DISPUTABLE")]
+    );
+
+    // Disputes of unknown transactions are still ignored.
+    let unknown = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 9, None), &config)
         .unwrap();
-    assert_eq!(client.available, 0.1);
-    assert_eq!(client.held, 0.0);
-    assert_eq!(client.total, 0.1);
-    assert_eq!(client.locked, false);
+    assert_eq!(unknown.available, 3.0);
 }
 
 #[test]
-fn test_dispute() {
-    let client = initialize_client(Transaction {
-        transaction_type: TransactionType::Deposit,
+fn test_interleaved_disputes_of_one_transaction() {
+    let config = ProcessingConfig {
+        allow_partial_disputes: true,
+        ..Default::default()
+    };
+    let transaction = |transaction_type, amount| Transaction {
+        transaction_type,
         client_id: 0,
         id: 0,
-        amount: Some(1.2),
-    })
-    .unwrap();
+        amount,
+        ..Default::default()
+    };
+    let mut client = initialize_client(transaction(TransactionType::Deposit, Some(10.0))).unwrap();
+    // (transaction type, amount, (available, held, total) afterwards)
+    let steps = [
+        (TransactionType::Dispute, Some(3.0), (7.0, 3.0, 10.0)),
+        (TransactionType::Dispute, Some(4.0), (3.0, 7.0, 10.0)),
+        // Resolves and chargebacks settle the oldest open dispute first.
+        (TransactionType::Resolve, None, (6.0, 4.0, 10.0)),
+        // A dispute without an amount disputes whatever is not already disputed.
+        (TransactionType::Dispute, None, (0.0, 10.0, 10.0)),
+        (TransactionType::Resolve, None, (4.0, 6.0, 10.0)),
+        (TransactionType::Chargeback, None, (4.0, 0.0, 4.0)),
+    ];
+    for (transaction_type, amount, balances) in steps {
+        client = client
+            .apply_transaction_with_config(transaction(transaction_type, amount), &config)
+            .unwrap();
+        assert_eq!((client.available, client.held, client.total), balances);
+        assert!(client.check_invariant());
+    }
+    assert!(client.locked);
+    assert_eq!(
+        client.dispute_outcome(),
+        DisputeOutcome {
+            resolved_amount: 7.0,
+            charged_back_amount: 6.0,
+        }
+    );
+    assert_eq!(client.first_negative_point(), None);
+
+    // Nothing is left to dispute once every part of the transaction is disputed.
+    let client = initialize_client(transaction(TransactionType::Deposit, Some(10.0)))
+        .unwrap()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, Some(6.0)), &config)
+        .unwrap();
+    for amount in [Some(4.5), Some(0.0)] {
+        let error = client
+            .clone()
+            .apply_transaction_with_config(transaction(TransactionType::Dispute, amount), &config)
+            .unwrap_err();
+        assert_eq!(error.code(), "INVALID_DISPUTE_AMOUNT");
+    }
     let client = client
-        .apply_dispute(Transaction {
-            transaction_type: TransactionType::Dispute,
-            client_id: 0,
-            id: 0,
-            amount: None,
-        })
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, Some(4.0)), &config)
         .unwrap();
-    assert_eq!(client.available, 0.0);
-    assert_eq!(client.held, 1.2);
-    assert_eq!(client.total, 1.2);
-    assert_eq!(client.locked, false);
+    assert_eq!(client.held, 10.0);
+    let error = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, None), &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "INVALID_DISPUTE_AMOUNT");
 }
 
 #[test]
-fn test_resolve() {
-    let client = initialize_client(Transaction {
+fn test_locked_by_and_first_overdraft_tx() {
+    let config = ProcessingConfig {
+        overdraft_limit: 5.0,
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut client =
+        initialize_client(transaction(TransactionType::Deposit, 1, Some(1.0))).unwrap();
+    for (transaction_type, id, amount) in [
+        (TransactionType::Withdrawal, 2, Some(0.5)),
+        (TransactionType::Withdrawal, 3, Some(2.0)),
+        (TransactionType::Deposit, 4, Some(10.0)),
+        (TransactionType::Withdrawal, 5, Some(12.0)),
+    ] {
+        client = client
+            .apply_transaction_with_config(transaction(transaction_type, id, amount), &config)
+            .unwrap();
+    }
+    assert_eq!(client.first_overdraft_tx, Some(3));
+    assert_eq!(client.locked_by, None);
+
+    // A deposit dispute after most of the deposit was withdrawn overdraws the account, and its chargeback locks it.
+    let mut client =
+        initialize_client(transaction(TransactionType::Deposit, 1, Some(5.0))).unwrap();
+    for (transaction_type, id) in [
+        (TransactionType::Withdrawal, 2),
+        (TransactionType::Dispute, 1),
+        (TransactionType::Chargeback, 1),
+        (TransactionType::Unlock, 6),
+    ] {
+        let amount = match transaction_type {
+            TransactionType::Withdrawal => Some(4.0),
+            _ => None,
+        };
+        client = client
+            .apply_transaction_with_config(transaction(transaction_type, id, amount), &config)
+            .unwrap();
+    }
+    assert_eq!(client.first_overdraft_tx, Some(1));
+    assert_eq!(client.locked_by, Some(1));
+    assert!(!client.locked);
+    let audit = serde_json::to_value(client.audit_record()).unwrap();
+    assert_eq!(audit["locked_by"], 1);
+    assert_eq!(audit["first_overdraft_tx"], 1);
+    let audit = serde_json::to_value(Client::new(0).audit_record()).unwrap();
+    assert!(audit.get("locked_by").is_none());
+}
+
+#[test]
+fn test_locked_allows() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut client =
+        initialize_client(transaction(TransactionType::Deposit, 1, Some(5.0))).unwrap();
+    for (transaction_type, id) in [
+        (TransactionType::Dispute, 1),
+        (TransactionType::Chargeback, 1),
+    ] {
+        client = client
+            .apply_transaction(transaction(transaction_type, id, None))
+            .unwrap();
+    }
+    assert!(client.locked);
+    let deposit = transaction(TransactionType::Deposit, 2, Some(3.0));
+    let withdrawal = transaction(TransactionType::Withdrawal, 3, Some(1.0));
+
+    // By default, nothing but an unlock is allowed on a locked account.
+    let error = client
+        .clone()
+        .apply_transaction(deposit.clone())
+        .unwrap_err();
+    assert_eq!(error.code(), "ACCOUNT_LOCKED");
+
+    let config = ProcessingConfig {
+        locked_allows: [TransactionType::Deposit].into_iter().collect(),
+        ..Default::default()
+    };
+    let client = client
+        .apply_transaction_with_config(deposit, &config)
+        .unwrap();
+    assert_eq!((client.available, client.total), (3.0, 3.0));
+    assert!(client.locked);
+    let error = client
+        .apply_transaction_with_config(withdrawal, &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "ACCOUNT_LOCKED");
+    assert_eq!(error.client.available, 3.0);
+}
+
+#[test]
+fn test_enabled_types() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut config = ProcessingConfig::default();
+    config.enabled_types.remove(&TransactionType::Chargeback);
+    let client =
+        initialize_client_with_config(transaction(TransactionType::Deposit, 1, Some(5.0)), &config)
+            .unwrap()
+            .apply_transaction_with_config(
+                transaction(TransactionType::Deposit, 2, Some(1.0)),
+                &config,
+            )
+            .unwrap()
+            .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, None), &config)
+            .unwrap();
+    let error = client
+        .apply_transaction_with_config(transaction(TransactionType::Chargeback, 1, None), &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "TRANSACTION_TYPE_DISABLED");
+    assert!(!error.client.locked);
+    assert_eq!((error.client.available, error.client.held), (1.0, 5.0));
+}
+
+#[test]
+fn test_negative_deposit_as_reversal() {
+    let transaction = |id, amount| Transaction {
         transaction_type: TransactionType::Deposit,
         client_id: 0,
-        id: 0,
-        amount: Some(1.2),
-    })
-    .unwrap();
+        id,
+        amount: Some(amount),
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(1, 5.0)).unwrap();
+    let error = client
+        .clone()
+        .apply_transaction(transaction(2, -2.0))
+        .unwrap_err();
+    assert_eq!(error.code(), "NON_POSITIVE_AMOUNT");
+
+    let config = ProcessingConfig {
+        treat_negative_deposit_as_reversal: true,
+        ..Default::default()
+    };
     let client = client
-        .apply_dispute(Transaction {
-            transaction_type: TransactionType::Dispute,
-            client_id: 0,
-            id: 0,
-            amount: None,
-        })
+        .apply_transaction_with_config(transaction(2, -2.0), &config)
         .unwrap();
+    assert_eq!(
+        (client.available, client.held, client.total),
+        (3.0, 0.0, 3.0)
+    );
+    let error = client
+        .clone()
+        .apply_transaction_with_config(transaction(3, -3.5), &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "INSUFFICIENT_FUNDS");
+    assert_eq!(error.client.total, 3.0);
+    let error = client
+        .clone()
+        .apply_transaction_with_config(transaction(3, 0.0), &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "NON_POSITIVE_AMOUNT");
+
+    // With negative held funds, e.g. from a seed file, a reversal can keep available above the floor but take total below it.
+    let client = Client {
+        available: 3.0,
+        held: -2.0,
+        total: 1.0,
+        ..client
+    };
+    let error = client
+        .apply_transaction_with_config(transaction(3, -2.0), &config)
+        .unwrap_err();
+    assert_eq!(error.code(), "INSUFFICIENT_FUNDS");
+    assert_eq!((error.client.available, error.client.total), (3.0, 1.0));
+}
+
+#[test]
+fn test_rebuild_from_log() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut client = Client::new(0);
+    for (transaction_type, id, amount) in [
+        (TransactionType::Deposit, 1, Some(5.0)),
+        (TransactionType::Deposit, 2, Some(3.0)),
+        (TransactionType::Withdrawal, 3, Some(1.0)),
+        (TransactionType::Dispute, 2, None),
+        (TransactionType::Resolve, 2, None),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Chargeback, 1, None),
+    ] {
+        client = client
+            .apply_transaction(transaction(transaction_type, id, amount))
+            .unwrap();
+    }
+    // The disputes of tx 1 and 2 are replayed before the withdrawal of tx 3, but the balances come out the same.
+    let rebuilt = client.rebuild_from_log().unwrap();
+    assert_eq!(
+        (rebuilt.available, rebuilt.held, rebuilt.total),
+        (2.0, 0.0, 2.0)
+    );
+    assert_eq!(rebuilt, client);
+
+    // Drift in the live balances shows up as a difference from the rebuilt client.
+    let mut drifted = client.clone();
+    drifted.held = 0.5;
+    assert_ne!(drifted.rebuild_from_log().unwrap(), drifted);
+}
+
+#[test]
+fn test_max_safe_amount() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount: Some(amount),
+        ..Default::default()
+    };
+    let client =
+        initialize_client(transaction(TransactionType::Deposit, 0, MAX_SAFE_AMOUNT)).unwrap();
+    assert_eq!(client.total, MAX_SAFE_AMOUNT);
+
+    // Above the limit, amounts are rejected whatever the configured maximum.
+    for amount in [MAX_SAFE_AMOUNT + 0.001, 1e12] {
+        let error =
+            initialize_client(transaction(TransactionType::Deposit, 0, amount)).unwrap_err();
+        assert!(matches!(
+            error.error_type,
+            TransactionErrorTypes::AmountOutOfBounds(value) if value == amount
+        ));
+        let error = client
+            .clone()
+            .apply_transaction(transaction(TransactionType::Withdrawal, 1, amount))
+            .unwrap_err();
+        assert!(matches!(
+            error.error_type,
+            TransactionErrorTypes::AmountOutOfBounds(_)
+        ));
+    }
+
+    // A deposit that would push the running balance past the limit is rejected, and leaves the client unchanged.
+    let error = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Deposit, 1, 1.0))
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::AmountOutOfBounds(value) if value == 1.0
+    ));
+    assert_eq!(error.client.total, MAX_SAFE_AMOUNT);
     let client = client
-        .apply_resolve(Transaction {
-            transaction_type: TransactionType::Resolve,
-            client_id: 0,
-            id: 0,
-            amount: None,
-        })
+        .apply_transaction(transaction(TransactionType::Withdrawal, 1, 1.0))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Deposit, 2, 1.0))
         .unwrap();
-    assert_eq!(client.available, 1.2);
-    assert_eq!(client.held, 0.0);
-    assert_eq!(client.total, 1.2);
-    assert_eq!(client.locked, false);
+    assert_eq!(client.total, MAX_SAFE_AMOUNT);
 }
 
 #[test]
-fn test_chargeback() {
-    let client = initialize_client(Transaction {
-        transaction_type: TransactionType::Deposit,
+fn test_freeze() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
         client_id: 0,
-        id: 0,
-        amount: Some(1.2),
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 0, Some(5.0)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Dispute, 0, None))
+        .unwrap();
+    let frozen = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Freeze, 1, None))
+        .unwrap();
+    assert!(frozen.is_frozen());
+    assert!(!frozen.is_locked());
+    assert_eq!(
+        (frozen.available, frozen.held, frozen.total),
+        (client.available, client.held, client.total)
+    );
+    assert_eq!(frozen.transactions, client.transactions);
+
+    // A frozen account blocks every other transaction, even those the config allows on locked accounts.
+    let config = ProcessingConfig {
+        locked_allows: [TransactionType::Deposit].into_iter().collect(),
+        ..Default::default()
+    };
+    for blocked in [
+        transaction(TransactionType::Deposit, 2, Some(1.0)),
+        transaction(TransactionType::Resolve, 0, None),
+        transaction(TransactionType::Unlock, 2, None),
+    ] {
+        let error = frozen
+            .clone()
+            .apply_transaction_with_config(blocked, &config)
+            .unwrap_err();
+        assert!(matches!(
+            error.error_type,
+            TransactionErrorTypes::AccountFrozen
+        ));
+        assert_eq!(error.client, frozen.state());
+    }
+
+    let client = frozen
+        .apply_transaction(transaction(TransactionType::Unfreeze, 1, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Deposit, 2, Some(1.0)))
+        .unwrap();
+    assert!(!client.is_frozen());
+    assert_eq!(client.total, 6.0);
+
+    // A chargeback lock and a freeze are independent, so unfreezing leaves the account locked.
+    let client = client
+        .apply_transaction(transaction(TransactionType::Chargeback, 0, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Freeze, 3, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Unfreeze, 3, None))
+        .unwrap();
+    assert!(client.is_locked());
+    assert!(!client.is_frozen());
+    assert!(client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Freeze, 4, Some(1.0)))
+        .is_err());
+    assert!(initialize_client(transaction(TransactionType::Freeze, 0, None)).is_err());
+}
+
+#[test]
+fn test_max_open_disputes() {
+    let config = ProcessingConfig {
+        max_open_disputes: Some(2),
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut client = Client::new(0);
+    for id in 0..4 {
+        client = client
+            .apply_transaction_with_config(
+                transaction(TransactionType::Deposit, id, Some(1.0)),
+                &config,
+            )
+            .unwrap();
+    }
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 0, None), &config)
+        .unwrap()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, None), &config)
+        .unwrap();
+    assert_eq!(client.open_dispute_count, 2);
+
+    // A third open dispute crosses the cap, and leaves the account unchanged.
+    let error = client
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 2, None), &config)
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::TooManyOpenDisputes
+    ));
+    assert_eq!(error.client, client.state());
+
+    // A resolve frees a slot, and so does a chargeback.
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Resolve, 0, None), &config)
+        .unwrap()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 2, None), &config)
+        .unwrap();
+    assert_eq!(client.open_dispute_count, 2);
+    assert_eq!(client.held, 2.0);
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Chargeback, 1, None), &config)
+        .unwrap();
+    assert_eq!(client.open_dispute_count, 1);
+
+    // There is no cap by default.
+    let mut client = Client::new(0);
+    for id in 0..4 {
+        client = client
+            .apply_transaction(transaction(TransactionType::Deposit, id, Some(1.0)))
+            .unwrap()
+            .apply_transaction(transaction(TransactionType::Dispute, id, None))
+            .unwrap();
+    }
+    assert_eq!(client.open_dispute_count, 4);
+}
+
+#[test]
+fn test_can_apply() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(Transaction {
+        currency: Some("USD".to_string()),
+        ..transaction(TransactionType::Deposit, 0, Some(2.0))
     })
     .unwrap();
+    let unchanged = client.clone();
+    assert!(client
+        .can_apply(&transaction(TransactionType::Withdrawal, 1, Some(2.0)))
+        .is_ok());
+    assert!(client
+        .can_apply(&transaction(TransactionType::Dispute, 0, None))
+        .is_ok());
+
+    let rejection = |client: &Client, transaction: Transaction| {
+        client.can_apply(&transaction).unwrap_err().code()
+    };
+    for (rejected, code) in [
+        (
+            transaction(TransactionType::Deposit, 1, Some(-1.0)),
+            "NON_POSITIVE_AMOUNT",
+        ),
+        (
+            transaction(TransactionType::Deposit, 1, Some(f64::NAN)),
+            "NON_FINITE_AMOUNT",
+        ),
+        (
+            transaction(TransactionType::Withdrawal, 1, None),
+            "MISSING_REQUIRED_AMOUNT",
+        ),
+        (
+            transaction(TransactionType::Dispute, 0, Some(1.0)),
+            "HAS_MEANINGLESS_AMOUNT",
+        ),
+        (
+            transaction(TransactionType::Withdrawal, 1, Some(2.5)),
+            "INSUFFICIENT_FUNDS",
+        ),
+        (
+            Transaction {
+                currency: Some("EUR".to_string()),
+                ..transaction(TransactionType::Deposit, 1, Some(1.0))
+            },
+            "CURRENCY_MISMATCH",
+        ),
+    ] {
+        assert_eq!(rejection(&client, rejected), code);
+    }
+
+    let locked = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Dispute, 0, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Chargeback, 0, None))
+        .unwrap();
+    assert_eq!(
+        rejection(&locked, transaction(TransactionType::Deposit, 1, Some(1.0))),
+        "ACCOUNT_LOCKED"
+    );
+    assert!(locked
+        .can_apply(&transaction(TransactionType::Unlock, 1, None))
+        .is_ok());
+    let frozen = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Freeze, 1, None))
+        .unwrap();
+    assert_eq!(
+        rejection(&frozen, transaction(TransactionType::Deposit, 1, Some(1.0))),
+        "ACCOUNT_FROZEN"
+    );
+
+    // The check reads amounts the way applying does.
+    let config = ProcessingConfig {
+        zero_amount_as_none: true,
+        ..Default::default()
+    };
+    assert!(client
+        .can_apply_with_config(
+            &transaction(TransactionType::Dispute, 0, Some(0.0)),
+            &config
+        )
+        .is_ok());
+    assert!(client
+        .can_apply_with_config(
+            &transaction(TransactionType::Resolve, 0, Some(0.0)),
+            &config
+        )
+        .is_ok());
+
+    // Checking never changes the client.
+    assert_eq!(client, unchanged);
+}
+
+#[test]
+fn test_error_client_state() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 3,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let client = initialize_client(transaction(TransactionType::Deposit, 1, Some(5.0)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Deposit, 2, Some(1.5)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Dispute, 1, None))
+        .unwrap();
+    let error = client
+        .clone()
+        .apply_transaction(transaction(TransactionType::Withdrawal, 3, Some(2.0)))
+        .unwrap_err();
+    assert_eq!(error.code(), "INSUFFICIENT_FUNDS");
+    assert_eq!(
+        error.client,
+        AccountState {
+            id: 3,
+            available: 1.5,
+            held: 5.0,
+            total: 6.5,
+            locked: false,
+        }
+    );
+    assert_eq!(error.client, client.state());
+
+    let error =
+        initialize_client(transaction(TransactionType::Withdrawal, 1, Some(1.0))).unwrap_err();
+    assert_eq!(error.client, Client::new(3).state());
+}
+
+#[test]
+fn test_ref_tx_targets_dispute() {
+    let config = ProcessingConfig {
+        allow_partial_disputes: true,
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount, ref_tx| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ref_tx,
+        ..Default::default()
+    };
+    let client = Client::new(0)
+        .apply_transaction_with_config(
+            transaction(TransactionType::Deposit, 1, Some(10.0), None),
+            &config,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 10, Some(3.0), Some(1)),
+            &config,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 11, Some(4.0), Some(1)),
+            &config,
+        )
+        .unwrap();
+    assert_eq!(client.held, 7.0);
+    assert_eq!(client.open_disputes(), vec![(1, 7.0)]);
+
+    // A resolve naming the newer dispute by its own id settles that dispute, not the oldest.
     let client = client
-        .apply_dispute(Transaction {
-            transaction_type: TransactionType::Dispute,
-            client_id: 0,
-            id: 0,
-            amount: None,
-        })
+        .apply_transaction_with_config(
+            transaction(TransactionType::Resolve, 12, None, Some(11)),
+            &config,
+        )
         .unwrap();
+    assert_eq!(client.held, 3.0);
+    assert_eq!(client.available, 7.0);
+    assert_eq!(client.open_disputes(), vec![(1, 3.0)]);
+
+    // Settling the same dispute again does nothing.
     let client = client
-        .apply_chargeback(Transaction {
-            transaction_type: TransactionType::Chargeback,
-            client_id: 0,
-            id: 0,
-            amount: None,
-        })
+        .apply_transaction_with_config(
+            transaction(TransactionType::Chargeback, 13, None, Some(11)),
+            &config,
+        )
         .unwrap();
-    assert_eq!(client.available, 0.0);
+    assert!(!client.is_locked());
+    assert_eq!(client.held, 3.0);
+
+    // A chargeback naming the remaining dispute reverses only its amount.
+    let client = client
+        .apply_transaction_with_config(
+            transaction(TransactionType::Chargeback, 14, None, Some(10)),
+            &config,
+        )
+        .unwrap();
+    assert!(client.is_locked());
     assert_eq!(client.held, 0.0);
-    assert_eq!(client.total, 0.0);
-    assert_eq!(client.locked, true);
+    assert_eq!(client.available, 7.0);
+    assert_eq!(client.total, 7.0);
+    assert!(client.open_disputes().is_empty());
+
+    // Once a compacted log drops a resolved dispute, naming it settles nothing rather than the oldest open dispute.
+    let compacting = ProcessingConfig {
+        compact_transaction_log: true,
+        ..config.clone()
+    };
+    let client = Client::new(0)
+        .apply_transaction_with_config(
+            transaction(TransactionType::Deposit, 1, Some(10.0), None),
+            &compacting,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 10, Some(3.0), Some(1)),
+            &compacting,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 11, Some(4.0), Some(1)),
+            &compacting,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Resolve, 12, None, Some(11)),
+            &compacting,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Resolve, 13, None, Some(11)),
+            &compacting,
+        )
+        .unwrap();
+    assert_eq!(client.held, 3.0);
+    assert_eq!(client.open_disputes(), vec![(1, 3.0)]);
+
+    // A ref_tx naming the original transaction settles its oldest open dispute.
+    let client = Client::new(0)
+        .apply_transaction_with_config(
+            transaction(TransactionType::Deposit, 1, Some(10.0), None),
+            &config,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 10, Some(3.0), Some(1)),
+            &config,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Dispute, 11, Some(4.0), Some(1)),
+            &config,
+        )
+        .unwrap()
+        .apply_transaction_with_config(
+            transaction(TransactionType::Resolve, 12, None, Some(1)),
+            &config,
+        )
+        .unwrap();
+    assert_eq!(client.held, 4.0);
+    assert_eq!(client.available, 6.0);
 }
 
 #[test]
-fn test_log_transaction() {
-    let client = initialize_client(Transaction {
-        transaction_type: TransactionType::Deposit,
+fn test_holds() {
+    let transaction = |transaction_type, id, amount, ref_tx| Transaction {
+        transaction_type,
         client_id: 0,
-        id: 0,
-        amount: Some(1.2),
-    })
-    .unwrap();
-    let transaction_one = Transaction {
-        transaction_type: TransactionType::Dispute,
+        id,
+        amount,
+        ref_tx,
+        ..Default::default()
+    };
+    let client = Client::new(0)
+        .apply_transaction(transaction(TransactionType::Deposit, 1, Some(5.0), None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Deposit, 2, Some(2.5), None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Withdrawal, 3, Some(1.0), None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Dispute, 1, None, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Dispute, 10, None, Some(2)))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Dispute, 3, None, None))
+        .unwrap();
+    // The disputed withdrawal holds nothing.
+    assert_eq!(
+        client.holds(),
+        vec![
+            Hold {
+                amount: 5.0,
+                reason: HoldReason::Dispute,
+                transaction_id: 1,
+                originating_id: 1,
+            },
+            Hold {
+                amount: 2.5,
+                reason: HoldReason::Dispute,
+                transaction_id: 2,
+                originating_id: 10,
+            },
+        ]
+    );
+    assert_eq!(client.held, 7.5);
+
+    let client = client
+        .apply_transaction(transaction(TransactionType::Resolve, 1, None, None))
+        .unwrap()
+        .apply_transaction(transaction(TransactionType::Resolve, 11, None, Some(10)))
+        .unwrap();
+    assert!(client.holds().is_empty());
+    assert_eq!(client.held, 0.0);
+}
+
+#[test]
+fn test_open_balance() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
         client_id: 0,
-        id: 0,
-        amount: None,
+        id,
+        amount,
+        ..Default::default()
     };
-    let transaction_two = transaction_one.clone();
-    let client = client.apply_dispute(transaction_one).unwrap();
-    assert_eq!(client.transactions[&0][1], transaction_two);
+    let client =
+        initialize_client(transaction(TransactionType::OpenBalance, 7, Some(12.5))).unwrap();
+    assert_eq!(
+        (client.available, client.held, client.total),
+        (12.5, 0.0, 12.5)
+    );
+    assert_eq!(client.transactions[&7][0].amount, Some(12.5));
+
+    // An opening balance is never disputable, even if the config lists it.
+    let config = ProcessingConfig {
+        disputable_types: [TransactionType::Deposit, TransactionType::OpenBalance]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let error = client
+        .clone()
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 7, None), &config)
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::NotDisputable
+    ));
+    assert_eq!(error.client, client.state());
+
+    // Later transactions apply as usual, and the account rebuilds even though the opening balance has the higher ID.
+    let client = client
+        .apply_transaction(transaction(TransactionType::Withdrawal, 3, Some(2.5)))
+        .unwrap();
+    assert_eq!(client.available, 10.0);
+    assert_eq!(client.rebuild_from_log().unwrap(), client);
+
+    // An opening balance must come first.
+    let error = client
+        .apply_transaction(transaction(TransactionType::OpenBalance, 8, Some(1.0)))
+        .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::OpenBalanceNotFirst
+    ));
+    assert!(matches!(
+        initialize_client(transaction(TransactionType::OpenBalance, 1, None))
+            .unwrap_err()
+            .error_type,
+        TransactionErrorTypes::MissingRequiredAmount
+    ));
+    assert!(matches!(
+        initialize_client(transaction(TransactionType::OpenBalance, 1, Some(-1.0)))
+            .unwrap_err()
+            .error_type,
+        TransactionErrorTypes::NonPositiveAmount
+    ));
+}
+
+#[test]
+fn test_compact_transaction_log() {
+    let config = ProcessingConfig {
+        compact_transaction_log: true,
+        ..Default::default()
+    };
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 0,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut client = initialize_client_with_config(
+        transaction(TransactionType::Deposit, 1, Some(10.0)),
+        &config,
+    )
+    .unwrap();
+    for _ in 0..100 {
+        client = client
+            .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, None), &config)
+            .unwrap()
+            .apply_transaction_with_config(transaction(TransactionType::Resolve, 1, None), &config)
+            .unwrap();
+        // A resolve with no open dispute settles nothing, so it is dropped as well.
+        client = client
+            .apply_transaction_with_config(transaction(TransactionType::Resolve, 1, None), &config)
+            .unwrap();
+        assert_eq!(client.transactions[&1].len(), 1);
+    }
+    assert_eq!((client.available, client.held), (10.0, 0.0));
+
+    // Disputes still work on the compacted log, and a chargeback keeps its dispute.
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, None), &config)
+        .unwrap();
+    assert_eq!((client.available, client.held), (0.0, 10.0));
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Chargeback, 1, None), &config)
+        .unwrap();
+    assert!(client.is_locked());
+    assert_eq!(client.total, 0.0);
+    let types: Vec<TransactionType> = client.transactions[&1]
+        .iter()
+        .map(|transaction| transaction.transaction_type.clone())
+        .collect();
+    assert_eq!(
+        types,
+        vec![
+            TransactionType::Deposit,
+            TransactionType::Dispute,
+            TransactionType::Chargeback
+        ]
+    );
+    assert_eq!(
+        client.rebuild_from_log_with_config(&config).unwrap(),
+        client
+    );
+
+    // An open full dispute left behind by a resolved partial one keeps the amount it holds.
+    let config = ProcessingConfig {
+        allow_partial_disputes: true,
+        ..config
+    };
+    let client = initialize_client_with_config(
+        transaction(TransactionType::Deposit, 1, Some(10.0)),
+        &config,
+    )
+    .unwrap()
+    .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, Some(3.0)), &config)
+    .unwrap()
+    .apply_transaction_with_config(transaction(TransactionType::Dispute, 1, None), &config)
+    .unwrap()
+    .apply_transaction_with_config(transaction(TransactionType::Resolve, 1, None), &config)
+    .unwrap();
+    assert_eq!(client.transactions[&1].len(), 2);
+    assert_eq!(client.transactions[&1][1].amount, Some(7.0));
+    assert_eq!(client.open_disputes(), vec![(1, 7.0)]);
+    let client = client
+        .apply_transaction_with_config(transaction(TransactionType::Resolve, 1, None), &config)
+        .unwrap();
+    assert_eq!((client.available, client.held), (10.0, 0.0));
+    assert_eq!(client.transactions[&1].len(), 1);
 }
 
 #[test]
 fn test_round_to_four_decimals() {
     assert_eq!(round_to_four_decimals(0.1234001), 0.1234);
+    assert_eq!(round_to_four_decimals(0.1 + 0.2), 0.3);
+    assert_eq!(round_to_four_decimals(1.1 + 2.2), 3.3);
+    assert_eq!(round_to_four_decimals(2.2 - 1.2), 1.0);
+    assert_eq!(round_to_four_decimals(0.00005), 0.0001);
+    assert_eq!(round_to_four_decimals(-0.00005), -0.0001);
+    assert_eq!(round_to_four_decimals(0.00004), 0.0);
+    assert!(round_to_four_decimals(-0.00004).is_sign_negative());
+}
+
+#[test]
+fn test_negative_zero_serializes_as_zero() {
+    fn serialize<T: serde::Serialize>(value: &T) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(value).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+    // 3.3 - 1.1 - 2.2 is a tiny negative number, which rounds to negative zero.
+    let negative_zero = 3.3 - 1.1 - 2.2;
+    assert!(negative_zero < 0.0);
+    let client = Client {
+        available: negative_zero,
+        held: -0.0,
+        total: -0.00004,
+        ..Client::new(1)
+    };
+    assert_eq!(
+        serialize(&client),
+        "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
+    );
+    assert_eq!(
+        serialize(&client.state()),
+        "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
+    );
+
+    // Rounding small amounts away from zero is unaffected.
+    let client = Client {
+        available: -0.00005,
+        ..Client::new(1)
+    };
+    assert_eq!(
+        serialize(&client),
+        "client,available,held,total,locked\n1,-0.0001,0.0,0.0,false\n"
+    );
 }
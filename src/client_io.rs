@@ -0,0 +1,56 @@
+use csv::Writer;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::client::*;
+
+/// Open a buffered csv stream at the given path for writing client accounts one at a time.
+/// Any existing file at the path is replaced.
+/// May produce an error if the file cannot be created.
+pub fn initialize_stream(output_file_path: &str) -> Result<Writer<BufWriter<File>>, csv::Error> {
+    Ok(Writer::from_writer(BufWriter::new(File::create(
+        output_file_path,
+    )?)))
+}
+
+/// Serialize the given client account as a csv row and write it to the given stream, flushing it so the row is emitted immediately.
+/// The header row is written before the first client only.
+/// May produce an error if there is a problem serializing the data or writing.
+/// ```
+/// use transaction_manager::client::*;
+/// use transaction_manager::client_io::*;
+/// let mut stream = csv::Writer::from_writer(Vec::new());
+/// write_client_info(&mut stream, &Client::new(1)).unwrap();
+/// let output = String::from_utf8(stream.into_inner().unwrap()).unwrap();
+/// assert_eq!(output, "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n");
+/// ```
+pub fn write_client_info<W>(stream: &mut Writer<W>, client: &Client) -> Result<(), csv::Error>
+where
+    W: Write,
+{
+    stream.serialize(client)?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_write_client_info() {
+    let output_path = std::env::temp_dir().join("transaction-manager-client-io.csv");
+    let output_path = output_path.to_str().unwrap();
+    let mut stream = initialize_stream(output_path).unwrap();
+    let mut locked_client = Client::new(2);
+    locked_client.available = 1.5;
+    locked_client.total = 1.5;
+    locked_client.locked = true;
+    write_client_info(&mut stream, &Client::new(1)).unwrap();
+    write_client_info(&mut stream, &locked_client).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(output_path).unwrap(),
+        "\
+client,available,held,total,locked
+1,0.0,0.0,0.0,false
+2,1.5,0.0,1.5,true
+"
+    );
+    std::fs::remove_file(output_path).unwrap();
+}
@@ -0,0 +1,330 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::client::DisputeReplay;
+use crate::transaction::*;
+
+/// The accounts that ledger lines are posted against.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LedgerAccount {
+    /// Funds owed to the client that are available for withdrawal.
+    ClientAvailable,
+    /// Funds owed to the client that are held in dispute.
+    ClientHeld,
+    /// Cash held by the platform on behalf of all clients.
+    PlatformCash,
+}
+
+/// The side of the ledger that a line is posted to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LedgerSide {
+    /// Increases an asset or decreases a liability.
+    Debit,
+    /// Decreases an asset or increases a liability.
+    Credit,
+}
+
+/// A single ledger line. Every applied transaction produces a balanced debit/credit pair of lines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LedgerLine {
+    /// The transaction ID the line was produced from.
+    pub transaction_id: u32,
+    /// The client whose account the line relates to.
+    pub client_id: u16,
+    /// The type of the transaction the line was produced from.
+    pub transaction_type: TransactionType,
+    /// The account the line is posted against.
+    pub account: LedgerAccount,
+    /// Whether the line is a debit or a credit.
+    pub side: LedgerSide,
+    /// The amount posted.
+    pub amount: f64,
+}
+
+/// Produce double-entry ledger lines for a list of transactions that were successfully applied, in the order they were applied.
+/// Dispute, resolve, and chargeback transactions use the amount of the transaction they reference, or the amount of a partial dispute.
+/// When a transaction has several open disputes, each resolve or chargeback settles the oldest one, unless its ref_tx names a dispute by its own id.
+/// A disputed withdrawal credits the client from platform cash until it is resolved, and a withdrawal chargeback moves no funds.
+/// Transactions that had no effect on any balance (such as a dispute of an unknown transaction) produce no lines.
+/// ```
+/// use transaction_manager::ledger::*;
+/// use transaction_manager::transaction::*;
+/// let lines = ledger_lines(&[Transaction {
+///     transaction_type: TransactionType::Withdrawal,
+///     client_id: 0,
+///     id: 0,
+///     amount: Some(1.2),
+///     ..Default::default()
+/// }]);
+/// assert_eq!(lines.len(), 2);
+/// assert_eq!(lines[0].account, LedgerAccount::ClientAvailable);
+/// assert_eq!(lines[0].side, LedgerSide::Debit);
+/// assert_eq!(lines[1].account, LedgerAccount::PlatformCash);
+/// assert_eq!(lines[1].side, LedgerSide::Credit);
+/// ```
+pub fn ledger_lines(transactions_applied: &[Transaction]) -> Vec<LedgerLine> {
+    // The type of the deposit or withdrawal each group of related transactions references,
+    // with the group replayed as it is applied, so disputes are settled the same way the client settled them.
+    let mut referenced: BTreeMap<(u16, u32), (TransactionType, DisputeReplay)> = BTreeMap::new();
+    // The transaction each dispute with a ref_tx referenced, by its own id, for resolves and chargebacks that name it.
+    let mut dispute_groups: BTreeMap<(u16, u32), u32> = BTreeMap::new();
+    let mut lines: Vec<LedgerLine> = Vec::new();
+    for transaction in transactions_applied {
+        let client_id = transaction.client_id;
+        let key = match (&transaction.transaction_type, transaction.ref_tx) {
+            (TransactionType::Dispute, Some(ref_tx)) => {
+                dispute_groups.insert((client_id, transaction.id), ref_tx);
+                (client_id, ref_tx)
+            }
+            (TransactionType::Resolve | TransactionType::Chargeback, Some(ref_tx)) => {
+                match dispute_groups.get(&(client_id, ref_tx)) {
+                    Some(group_id) if !referenced.contains_key(&(client_id, ref_tx)) => {
+                        (client_id, *group_id)
+                    }
+                    _ => (client_id, ref_tx),
+                }
+            }
+            _ => (client_id, transaction.id),
+        };
+        if let (TransactionType::Deposit | TransactionType::Withdrawal, Some(amount)) =
+            (&transaction.transaction_type, transaction.amount)
+        {
+            referenced.entry(key).or_insert_with(|| {
+                (
+                    transaction.transaction_type.clone(),
+                    DisputeReplay::new(amount),
+                )
+            });
+        }
+        // The amount a dispute, resolve, or chargeback disputed or settled, if it referenced a known transaction.
+        let (referenced_type, disputed_amount) = match referenced.get_mut(&key) {
+            Some((referenced_type, replay)) => {
+                (Some(referenced_type.clone()), replay.replay(transaction))
+            }
+            None => (None, None),
+        };
+        let posting =
+            match (&transaction.transaction_type, referenced_type) {
+                (TransactionType::Deposit, _) => transaction.amount.map(|amount| {
+                    (
+                        LedgerAccount::PlatformCash,
+                        LedgerAccount::ClientAvailable,
+                        amount,
+                    )
+                }),
+                (TransactionType::OpenBalance, _) => transaction.amount.map(|amount| {
+                    (
+                        LedgerAccount::PlatformCash,
+                        LedgerAccount::ClientAvailable,
+                        amount,
+                    )
+                }),
+                (TransactionType::Withdrawal, _) => transaction.amount.map(|amount| {
+                    (
+                        LedgerAccount::ClientAvailable,
+                        LedgerAccount::PlatformCash,
+                        amount,
+                    )
+                }),
+                (TransactionType::Dispute, Some(TransactionType::Withdrawal)) => disputed_amount
+                    .map(|amount| {
+                        (
+                            LedgerAccount::PlatformCash,
+                            LedgerAccount::ClientAvailable,
+                            amount,
+                        )
+                    }),
+                (TransactionType::Dispute, Some(_)) => disputed_amount.map(|amount| {
+                    (
+                        LedgerAccount::ClientAvailable,
+                        LedgerAccount::ClientHeld,
+                        amount,
+                    )
+                }),
+                (TransactionType::Resolve, Some(TransactionType::Withdrawal)) => disputed_amount
+                    .map(|amount| {
+                        (
+                            LedgerAccount::ClientAvailable,
+                            LedgerAccount::PlatformCash,
+                            amount,
+                        )
+                    }),
+                (TransactionType::Resolve, Some(_)) => disputed_amount.map(|amount| {
+                    (
+                        LedgerAccount::ClientHeld,
+                        LedgerAccount::ClientAvailable,
+                        amount,
+                    )
+                }),
+                // A withdrawal chargeback makes the credit from its dispute permanent, so no funds move.
+                (TransactionType::Chargeback, Some(TransactionType::Withdrawal)) => None,
+                (TransactionType::Chargeback, Some(_)) => disputed_amount.map(|amount| {
+                    (
+                        LedgerAccount::ClientHeld,
+                        LedgerAccount::PlatformCash,
+                        amount,
+                    )
+                }),
+                (TransactionType::Dispute, None)
+                | (TransactionType::Resolve, _)
+                | (TransactionType::Chargeback, _)
+                | (TransactionType::Unlock, _)
+                | (TransactionType::Freeze, _)
+                | (TransactionType::Unfreeze, _) => None,
+            };
+        if let Some((debit_account, credit_account, amount)) = posting {
+            lines.push(ledger_line(
+                transaction,
+                debit_account,
+                LedgerSide::Debit,
+                amount,
+            ));
+            lines.push(ledger_line(
+                transaction,
+                credit_account,
+                LedgerSide::Credit,
+                amount,
+            ));
+        }
+    }
+    lines
+}
+
+/// Build a single ledger line for the given transaction.
+fn ledger_line(
+    transaction: &Transaction,
+    account: LedgerAccount,
+    side: LedgerSide,
+    amount: f64,
+) -> LedgerLine {
+    LedgerLine {
+        transaction_id: transaction.id,
+        client_id: transaction.client_id,
+        transaction_type: transaction.transaction_type.clone(),
+        account,
+        side,
+        amount,
+    }
+}
+
+#[test]
+fn test_deposit_ledger_lines_are_balanced() {
+    let lines = ledger_lines(&[Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        id: 7,
+        amount: Some(2.5),
+        ..Default::default()
+    }]);
+    assert_eq!(lines.len(), 2);
+    let debits: f64 = lines
+        .iter()
+        .filter(|line| line.side == LedgerSide::Debit)
+        .map(|line| line.amount)
+        .sum();
+    let credits: f64 = lines
+        .iter()
+        .filter(|line| line.side == LedgerSide::Credit)
+        .map(|line| line.amount)
+        .sum();
+    assert_eq!(debits, 2.5);
+    assert_eq!(credits, 2.5);
+    assert_eq!(lines[0].account, LedgerAccount::PlatformCash);
+    assert_eq!(lines[1].account, LedgerAccount::ClientAvailable);
+    assert!(lines
+        .iter()
+        .all(|line| line.transaction_id == 7 && line.client_id == 1));
+}
+
+#[test]
+fn test_dispute_ledger_lines() {
+    let deposit = Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        id: 7,
+        amount: Some(2.5),
+        ..Default::default()
+    };
+    let dispute = Transaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        id: 7,
+        amount: None,
+        ..Default::default()
+    };
+    let unknown_resolve = Transaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        id: 8,
+        amount: None,
+        ..Default::default()
+    };
+    let chargeback = Transaction {
+        transaction_type: TransactionType::Chargeback,
+        client_id: 1,
+        id: 7,
+        amount: None,
+        ..Default::default()
+    };
+    let lines = ledger_lines(&[deposit, dispute, unknown_resolve, chargeback]);
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[2].account, LedgerAccount::ClientAvailable);
+    assert_eq!(lines[3].account, LedgerAccount::ClientHeld);
+    assert_eq!(lines[4].account, LedgerAccount::ClientHeld);
+    assert_eq!(lines[4].side, LedgerSide::Debit);
+    assert_eq!(lines[5].account, LedgerAccount::PlatformCash);
+    assert_eq!(lines[5].amount, 2.5);
+}
+
+#[test]
+fn test_concurrent_dispute_ledger_lines() {
+    let transaction = |transaction_type, amount| Transaction {
+        transaction_type,
+        client_id: 1,
+        id: 7,
+        amount,
+        ..Default::default()
+    };
+    let lines = ledger_lines(&[
+        transaction(TransactionType::Deposit, Some(10.0)),
+        transaction(TransactionType::Dispute, Some(3.0)),
+        transaction(TransactionType::Dispute, None),
+        transaction(TransactionType::Resolve, None),
+        transaction(TransactionType::Chargeback, None),
+        transaction(TransactionType::Chargeback, None),
+    ]);
+    let amounts: Vec<f64> = lines
+        .iter()
+        .filter(|line| line.side == LedgerSide::Debit)
+        .map(|line| line.amount)
+        .collect();
+    assert_eq!(amounts, vec![10.0, 3.0, 7.0, 3.0, 7.0]);
+    assert_eq!(lines[8].account, LedgerAccount::ClientHeld);
+    assert_eq!(lines[9].account, LedgerAccount::PlatformCash);
+}
+
+#[test]
+fn test_ref_tx_dispute_ledger_lines() {
+    let transaction = |transaction_type, id, amount, ref_tx| Transaction {
+        transaction_type,
+        client_id: 1,
+        id,
+        amount,
+        ref_tx,
+        ..Default::default()
+    };
+    let lines = ledger_lines(&[
+        transaction(TransactionType::Deposit, 7, Some(10.0), None),
+        transaction(TransactionType::Dispute, 20, Some(3.0), Some(7)),
+        transaction(TransactionType::Dispute, 21, Some(4.0), Some(7)),
+        transaction(TransactionType::Resolve, 22, None, Some(21)),
+        transaction(TransactionType::Chargeback, 23, None, Some(7)),
+    ]);
+    let amounts: Vec<f64> = lines
+        .iter()
+        .filter(|line| line.side == LedgerSide::Debit)
+        .map(|line| line.amount)
+        .collect();
+    assert_eq!(amounts, vec![10.0, 3.0, 4.0, 4.0, 3.0]);
+    assert_eq!(lines[8].account, LedgerAccount::ClientHeld);
+}
@@ -1,3 +1,4 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_doc_code_examples)]
 
@@ -15,18 +16,67 @@
 //! cargo run resources/transaction-list.csv > resources/account-list.csv
 //! </pre>
 //!
+//! Will output account list directly to a csv file, without relying on shell redirection:
+//! <pre>
+//! cargo run resources/transaction-list.csv --output resources/account-list.csv
+//! </pre>
+//!
+//! ## Features
+//!
+//! The `std` feature is enabled by default. Without it, the crate builds under `no_std` with `alloc`, for embedded and WASM users,
+//! and contains only the account and ledger logic: the client, ledger, processing_config, processing_error, transaction, and transaction_error modules.
+//! Applying transactions with `Client::apply_transaction` and friends needs nothing from std.
+//! Everything that reads or writes files or csv (client_io, reporting, transaction_source, transaction_manager, and
+//! `ProcessingConfig::from_file`) requires the `std` feature, as does the binary.
+//! `cargo build --lib --no-default-features` checks that the core still builds without std.
+//!
 //! ## License
 //!
 //! This project is licensed under the MIT License - see the [LICENSE.md](https://github.com/tjhaskel/transaction-manager/blob/master/LICENSE.md) file for details
 
+extern crate alloc;
+
 /// Represents a client account with id, amounts, and status
 pub mod client;
 
+/// Writes client accounts to a csv stream one at a time, for incremental emission
+#[cfg(feature = "std")]
+pub mod client_io;
+
+/// Produces double-entry ledger lines from applied transactions for accounting integration
+pub mod ledger;
+
+/// Callbacks that let embedders react to balance changes as transactions are applied
+pub mod observer;
+
+/// Options that control how transactions are validated and applied
+pub mod processing_config;
+
+/// Represents errors with a processing run as a whole, such as unusable input or output paths
+pub mod processing_error;
+
+/// Functions that analyse processed client accounts for monitoring and reporting
+#[cfg(feature = "std")]
+pub mod reporting;
+
+/// Generates SQL upsert statements for loading client accounts into a database
+#[cfg(feature = "sql")]
+pub mod sql;
+
 /// Represents a client transaction with id, type, client id, and amount
 pub mod transaction;
 
 /// Represents various errors that could come from improper transactions
 pub mod transaction_error;
 
+/// Reads transactions one at a time from csv, JSON-lines, or other feeds
+#[cfg(feature = "std")]
+pub mod transaction_source;
+
 /// Controller module that performs business logic based on input transactions and modifies client accounts accordingly.
+#[cfg(feature = "std")]
 pub mod transaction_manager;
+
+/// Entry points for running the engine in the browser through wasm-bindgen
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,14 +1,181 @@
-use std::env;
+use clap::{ArgEnum, Parser};
+use std::error::Error;
+use std::fs::File;
 use std::io;
+use std::process;
 
+use transaction_manager::processing_config::*;
 use transaction_manager::transaction_manager::*;
 
-/// This program should be called with a single argument representing a csv file with transaction data. See resources/transaction-list.csv for an example.
-/// It outputs a list of accounts to stdout, which in turn can be piped to a csv file. See resources/account-list.csv for an example.
+/// Reads a csv file of transactions and outputs the state of every client account after all transactions are applied.
+/// See resources/transaction-list.csv for an example input, and resources/account-list.csv for an example output.
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// Transaction csv file to read. Equivalent to --input, kept so `transaction-manager FILE` still works
+    #[clap(
+        value_name = "INPUT",
+        conflicts_with = "input",
+        required_unless_present = "input"
+    )]
+    input_path: Option<String>,
+
+    /// Transaction csv file to read
+    #[clap(long, value_name = "PATH")]
+    input: Option<String>,
+
+    /// Write the account list to this file instead of stdout
+    #[clap(long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// Also write system-wide totals (client count, summed balances, and locked account count) to this csv file
+    #[clap(long, value_name = "PATH")]
+    summary: Option<String>,
+
+    /// Write only the accounts that differ from this account csv file, such as the previous run's output, plus any new accounts
+    #[clap(long, value_name = "PATH")]
+    changed_since: Option<String>,
+
+    /// Load processing policies from a TOML or YAML file. The flags below override the file
+    #[clap(long, value_name = "PATH")]
+    config: Option<String>,
+
+    /// Output format: the account csv, or a JSON document per client including its full transaction log, one per line or pretty-printed
+    #[clap(long, arg_enum, value_name = "FORMAT")]
+    format: Option<Format>,
+
+    /// Same as --format audit
+    #[clap(long, conflicts_with = "format")]
+    audit: bool,
+
+    /// Stop at the first invalid transaction, even if the config file skips invalid transactions
+    #[clap(long)]
+    strict: bool,
+
+    /// Start the output with a `#` comment line describing the run
+    #[clap(long)]
+    metadata_header: bool,
+
+    /// Read only the first N transaction rows, then write the accounts built from them
+    #[clap(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Reject any transaction that would create more than N client accounts, to bound memory on untrusted input
+    #[clap(long, value_name = "N")]
+    max_clients: Option<usize>,
+
+    /// Leave out unlocked accounts whose available, held, and total funds are all zero
+    #[clap(long)]
+    suppress_zero: bool,
+
+    /// Instead of writing the account list, check that it matches this account csv file, printing every difference and exiting with code 1 if any
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = &["output", "changed-since", "summary"]
+    )]
+    verify: Option<String>,
+}
+
+/// Exit code for a run that could not read, apply, or write the transactions, such as an invalid transaction or unreadable file.
+const PROCESSING_ERROR_EXIT_CODE: i32 = 1;
+
+/// The output formats that can be chosen on the command line.
+#[derive(ArgEnum, Clone)]
+enum Format {
+    Csv,
+    Audit,
+    PrettyAudit,
+}
+
+/// This program should be called with a csv file with transaction data, either as its only positional argument or with `--input PATH`.
+/// It outputs a list of accounts to stdout, which in turn can be piped to a csv file, or to the file given with `--output PATH`.
+/// Run with `--help` for every option.
+/// Errors are printed to stderr. Missing or invalid arguments print a usage message and exit with code 2,
+/// and a run that fails to load the config, read or apply the transactions, or write the output exits with code 1.
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        let transaction_file_path = &args[1];
-        process_transactions(io::stdout(), transaction_file_path).unwrap()
+    let args = Args::parse();
+    let mut config = match &args.config {
+        Some(config_file_path) => match ProcessingConfig::from_file(config_file_path) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(PROCESSING_ERROR_EXIT_CODE);
+            }
+        },
+        None => ProcessingConfig::default(),
+    };
+    if args.metadata_header {
+        config.metadata_header = true;
+    }
+    if args.strict {
+        config.skip_invalid_transactions = false;
+    }
+    if args.limit.is_some() {
+        config.row_limit = args.limit;
+    }
+    if args.max_clients.is_some() {
+        config.max_clients = args.max_clients;
+    }
+    if args.suppress_zero {
+        config.suppress_zero_accounts = true;
+    }
+    match args.format {
+        Some(Format::Csv) => config.output_format = OutputFormat::Csv,
+        Some(Format::Audit) => config.output_format = OutputFormat::Audit,
+        Some(Format::PrettyAudit) => config.output_format = OutputFormat::PrettyAudit,
+        None if args.audit => config.output_format = OutputFormat::Audit,
+        None => {}
+    }
+    if let Err(error) = run(&args, config) {
+        eprintln!("{}", error);
+        process::exit(PROCESSING_ERROR_EXIT_CODE);
+    }
+}
+
+/// Process the input file according to the given config, then write the account list and, if requested, the summary,
+/// or, if asked to verify, compare the account list against the expected file instead.
+/// The input is fully processed before any output file is created.
+fn run(args: &Args, config: ProcessingConfig) -> Result<(), Box<dyn Error>> {
+    let transaction_file_path = args
+        .input
+        .as_ref()
+        .or(args.input_path.as_ref())
+        .expect("clap requires an input path");
+    for output_file_path in args.output.iter().chain(&args.summary) {
+        check_output_path(output_file_path, transaction_file_path, &config)?;
+    }
+    let mut manager = TransactionManager::new(config);
+    manager.process_file(transaction_file_path)?;
+    if let Some(expected_file_path) = &args.verify {
+        let differences = manager.verify_accounts(expected_file_path)?;
+        for difference in &differences {
+            eprintln!("{}", difference);
+        }
+        if !differences.is_empty() {
+            return Err(format!(
+                "Error: {} accounts differ from {}.",
+                differences.len(),
+                expected_file_path
+            )
+            .into());
+        }
+        return Ok(());
+    }
+    match (&args.output, &args.changed_since) {
+        (Some(output_file_path), Some(baseline_file_path)) => {
+            manager.write_changed_accounts(File::create(output_file_path)?, baseline_file_path)?
+        }
+        (Some(output_file_path), None) => {
+            manager.write_accounts(File::create(output_file_path)?)?
+        }
+        (None, Some(baseline_file_path)) => {
+            manager.write_changed_accounts(io::stdout(), baseline_file_path)?
+        }
+        (None, None) => manager.write_accounts(io::stdout())?,
+    }
+    if let Some(summary_file_path) = &args.summary {
+        manager.write_summary(File::create(summary_file_path)?)?;
     }
+    Ok(())
 }
@@ -0,0 +1,107 @@
+use crate::client::*;
+use crate::transaction::*;
+
+/// Receives events as transactions are applied, so embedders can react to balance changes without polling,
+/// e.g. pushing a notification when an account locks.
+/// Each method is called after the transaction has been successfully applied, with the updated client.
+/// Every method does nothing by default, so an observer only implements the events it cares about.
+/// ```
+/// use transaction_manager::client::*;
+/// use transaction_manager::observer::*;
+/// use transaction_manager::transaction::*;
+///
+/// struct LockCounter(usize);
+///
+/// impl LedgerObserver for LockCounter {
+///     fn on_lock(&mut self, _client: &Client, _transaction: &Transaction) {
+///         self.0 += 1;
+///     }
+/// }
+/// ```
+pub trait LedgerObserver {
+    /// Called after every successfully applied transaction, before any more specific event.
+    fn on_transaction(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called after a deposit is applied.
+    fn on_deposit(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called after a withdrawal is applied.
+    fn on_withdrawal(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called when a transaction locks an unlocked account, i.e. a chargeback.
+    fn on_lock(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called when a transaction unlocks a locked account.
+    fn on_unlock(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called when a freeze transaction freezes an account that was not frozen.
+    fn on_freeze(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called when an unfreeze transaction unfreezes a frozen account.
+    fn on_unfreeze(&mut self, _client: &Client, _transaction: &Transaction) {}
+
+    /// Called when a transaction takes available funds from zero or more to below zero.
+    fn on_overdraft(&mut self, _client: &Client, _transaction: &Transaction) {}
+}
+
+/// The parts of a client that events are detected from, kept from before a transaction is applied
+/// so that the client does not have to be copied with its transaction log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObservedState {
+    /// Funds available for withdrawal.
+    pub available: f64,
+
+    /// Whether a chargeback has locked the account.
+    pub locked: bool,
+
+    /// Whether a freeze has blocked all activity on the account.
+    pub frozen: bool,
+}
+
+impl From<&Client> for ObservedState {
+    fn from(client: &Client) -> ObservedState {
+        ObservedState {
+            available: client.available,
+            locked: client.locked,
+            frozen: client.frozen,
+        }
+    }
+}
+
+/// An observer that ignores every event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopObserver;
+
+impl LedgerObserver for NoopObserver {}
+
+/// Send the events caused by applying the given transaction to the given observer, by comparing the client before and after.
+/// `previous` is the observed state of the client before the transaction, or None if the transaction created the client.
+pub fn notify_observer(
+    observer: &mut dyn LedgerObserver,
+    previous: Option<ObservedState>,
+    client: &Client,
+    transaction: &Transaction,
+) {
+    observer.on_transaction(client, transaction);
+    match transaction.transaction_type {
+        TransactionType::Deposit => observer.on_deposit(client, transaction),
+        TransactionType::Withdrawal => observer.on_withdrawal(client, transaction),
+        _ => {}
+    }
+    let was_locked = previous.is_some_and(|previous| previous.locked);
+    if client.locked && !was_locked {
+        observer.on_lock(client, transaction);
+    } else if !client.locked && was_locked {
+        observer.on_unlock(client, transaction);
+    }
+    let was_frozen = previous.is_some_and(|previous| previous.frozen);
+    if client.frozen && !was_frozen {
+        observer.on_freeze(client, transaction);
+    } else if !client.frozen && was_frozen {
+        observer.on_unfreeze(client, transaction);
+    }
+    let was_overdrawn = previous.is_some_and(|previous| previous.available < 0.0);
+    if client.available < 0.0 && !was_overdrawn {
+        observer.on_overdraft(client, transaction);
+    }
+}
@@ -0,0 +1,479 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::num::ParseFloatError;
+use serde::Deserialize;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[cfg(feature = "std")]
+use crate::processing_error::*;
+use crate::transaction::*;
+
+/// Options that control how transactions are validated and applied.
+/// The default configuration matches the original, strict behavior.
+/// A config can be loaded from a TOML or YAML file, where any option that is left out keeps its default.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessingConfig {
+    /// If true, a dispute, resolve, or chargeback with an amount of exactly zero is treated as if no amount was given,
+    /// rather than producing a HasMeaninglessAmount error. Some feeds always populate the amount column with 0.
+    pub zero_amount_as_none: bool,
+
+    /// The maximum number of distinct clients that may be created. A transaction for a new client beyond this limit
+    /// produces a ClientLimitExceeded error. None means unlimited.
+    pub max_clients: Option<usize>,
+
+    /// The maximum number of rows to read from each csv input, e.g. to sample the start of a huge file.
+    /// Reading stops after that many rows, and the accounts built from them are written as usual. None means every row is read.
+    pub row_limit: Option<usize>,
+
+    /// How far below zero a withdrawal may take a client's available funds. Defaults to 0.0, allowing no overdraft.
+    pub overdraft_limit: f64,
+
+    /// What to do when writing output to a file that is also the transaction input file.
+    pub same_path_policy: SamePathPolicy,
+
+    /// The order in which client accounts are written to the output.
+    pub sort_order: SortOrder,
+
+    /// Whether the first row of a transaction file is a header. If false, every row is read as data,
+    /// with columns in the order type, client, tx, amount, timestamp, currency, ref_tx. Defaults to true.
+    pub has_headers: bool,
+
+    /// If true, csv columns other than the transaction columns are kept in each transaction's metadata, keyed by header,
+    /// instead of the header being rejected with an InvalidHeader error. Needs a header row. Defaults to false.
+    pub keep_extra_columns: bool,
+
+    /// If true, a deposit dispute that would make held funds exceed total funds produces an InsufficientFunds error.
+    /// This can happen when disputed funds have already been withdrawn. Defaults to false.
+    pub limit_held_to_total: bool,
+
+    /// If true, each resolve or chargeback compacts the log of the transaction it settles down to the entries that balances and future
+    /// disputes depend on: deposits, withdrawals, opening balances, open disputes, and charged back disputes with their chargebacks.
+    /// Resolved disputes and their resolves are dropped, so an id disputed and resolved over and over keeps a bounded log.
+    /// Anything derived from the log, such as audit output, dispute counts, and balance history, only sees the kept entries.
+    /// Defaults to false.
+    pub compact_transaction_log: bool,
+
+    /// If true, a transaction that breaks any rule is recorded and skipped instead of stopping processing. Defaults to false.
+    pub skip_invalid_transactions: bool,
+
+    /// The most transactions that may be rejected before processing stops with a TooManyErrors error, if any.
+    /// Only matters when invalid transactions are skipped, or rejections are passed to a callback. Defaults to None.
+    pub max_errors: Option<usize>,
+
+    /// The largest fraction of rows, from 0 to 1, that may be rejected, checked when each input has been read, if any.
+    /// Exceeding it produces a TooManyErrors error instead of output built from a likely corrupt file. Defaults to None.
+    pub max_error_rate: Option<f64>,
+
+    /// What to do with a deposit or withdrawal whose client, transaction ID, and type exactly match one already applied,
+    /// such as when a retry resends the same file.
+    pub replay_policy: ReplayPolicy,
+
+    /// If true, the output starts with a comment line recording when the run happened, the input files, the number of rows,
+    /// and the crate version. Readers can skip it by treating '#' as a comment character. Defaults to false.
+    pub metadata_header: bool,
+
+    /// If true, accounts whose available, held, and total funds are all zero are left out of the output, unless they are locked,
+    /// since a lock is still meaningful on an empty account. Defaults to false.
+    pub suppress_zero_accounts: bool,
+
+    /// Whether to write the flat account csv, or a JSON audit document per client that includes its transaction log.
+    pub output_format: OutputFormat,
+
+    /// The separators balances are written with in the account csv output, for readers in locales that expect e.g. 1.234,56.
+    /// Balances are still rounded to four decimal places first. None writes plain numbers such as 1234.56. The JSON audit output
+    /// is not affected, since its balances must stay JSON numbers. Defaults to None.
+    pub number_format: Option<NumberFormat>,
+
+    /// What to do when an opening-balance seed file has more than one row for the same client.
+    pub seed_duplicate_policy: SeedDuplicatePolicy,
+
+    /// How balances are rounded to four decimal places as transactions are applied, and again when accounts are written.
+    pub rounding_mode: RoundingMode,
+
+    /// The smallest amount a deposit may have. A smaller deposit produces an AmountOutOfBounds error. None means no minimum.
+    pub min_deposit: Option<f64>,
+
+    /// The largest amount a deposit or withdrawal may have. A larger amount produces an AmountOutOfBounds error. None means no maximum.
+    pub max_amount: Option<f64>,
+
+    /// If set, the amount column of transaction input is read as an integer number of minor units and divided by this value,
+    /// e.g. 100 to read 1250 as 12.50. Output is always written in decimal units. None reads amounts as decimal units.
+    pub amount_minor_units: Option<u32>,
+
+    /// If true, csv amount cells may contain the currency symbol and ',' thousands separators, e.g. `$1,250.00`,
+    /// which are removed before the amount is parsed. Defaults to false, so such amounts are rejected as malformed.
+    /// This applies to every csv input, sync or async. JSON-lines amounts are numbers, so they are never formatted this way.
+    pub lenient_amounts: bool,
+
+    /// The currency symbol removed from amount cells when lenient amounts are enabled. Defaults to "$".
+    pub currency_symbol: String,
+
+    /// Other spellings of transaction types in csv input, each mapped to the type it stands for, such as "withdraw" for withdrawal.
+    /// Aliases are matched ignoring case, like the type names themselves, which are always accepted.
+    /// Defaults to "dep" for deposit and "withdraw" for withdrawal.
+    pub type_aliases: BTreeMap<String, TransactionType>,
+
+    /// The byte that separates fields in csv input and output, e.g. b';' or b'\t'. Defaults to b','.
+    pub delimiter: u8,
+
+    /// The byte used to quote fields in csv input and output. Defaults to b'"'.
+    pub quote: u8,
+
+    /// If true, a dispute may give an amount to dispute only that portion of the referenced transaction,
+    /// rather than producing a HasMeaninglessAmount error. A dispute without an amount still disputes the full amount. Defaults to false.
+    pub allow_partial_disputes: bool,
+
+    /// Optional cap on the cumulative amount each client may withdraw. Disputed and charged-back withdrawals do not count towards it.
+    /// A withdrawal that would exceed it produces a WithdrawalLimitExceeded error. Defaults to None, meaning no cap.
+    pub max_total_withdrawal: Option<f64>,
+
+    /// Optional number of later transactions on the same account after which a dispute that is still open is automatically resolved,
+    /// returning its held funds to available. The count is kept per disputed transaction, so when a transaction is disputed more than once,
+    /// its oldest open dispute sets the clock. Defaults to None, meaning disputes stay open until resolved or charged back.
+    pub auto_resolve_disputes_after: Option<usize>,
+
+    /// Optional cap on how many disputes each client may have open at once. A dispute beyond it produces a TooManyOpenDisputes error,
+    /// and each resolve or chargeback frees a slot. Defaults to None, meaning no cap.
+    pub max_open_disputes: Option<usize>,
+
+    /// The types of transaction that may be disputed. Disputing any other type produces a NotDisputable error.
+    /// Defaults to deposits and withdrawals, so that operators can restrict disputes to deposits only.
+    pub disputable_types: BTreeSet<TransactionType>,
+
+    /// The types of transaction, besides unlocks, freezes, and unfreezes, that may still be applied to a locked account, e.g. deposits only.
+    /// Any other type produces an AccountLocked error. Defaults to none.
+    pub locked_allows: BTreeSet<TransactionType>,
+
+    /// The types of transaction this deployment processes. A transaction of any other type produces a TransactionTypeDisabled error
+    /// before it is applied, e.g. to reject chargebacks that are handled out of band. Defaults to every type.
+    pub enabled_types: BTreeSet<TransactionType>,
+
+    /// If true, a deposit with a negative amount reverses that much of an earlier credit, subtracting it from available and total funds,
+    /// rather than producing a NonPositiveAmount error. A reversal may take available or total funds below zero only by the overdraft limit,
+    /// and produces an InsufficientFunds error otherwise. Defaults to false.
+    pub treat_negative_deposit_as_reversal: bool,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        ProcessingConfig {
+            zero_amount_as_none: false,
+            max_clients: None,
+            row_limit: None,
+            overdraft_limit: 0.0,
+            same_path_policy: SamePathPolicy::default(),
+            sort_order: SortOrder::default(),
+            has_headers: true,
+            keep_extra_columns: false,
+            limit_held_to_total: false,
+            compact_transaction_log: false,
+            skip_invalid_transactions: false,
+            max_errors: None,
+            max_error_rate: None,
+            replay_policy: ReplayPolicy::default(),
+            metadata_header: false,
+            suppress_zero_accounts: false,
+            output_format: OutputFormat::default(),
+            number_format: None,
+            seed_duplicate_policy: SeedDuplicatePolicy::default(),
+            rounding_mode: RoundingMode::default(),
+            min_deposit: None,
+            max_amount: None,
+            amount_minor_units: None,
+            lenient_amounts: false,
+            currency_symbol: "$".to_string(),
+            type_aliases: [
+                ("dep".to_string(), TransactionType::Deposit),
+                ("withdraw".to_string(), TransactionType::Withdrawal),
+            ]
+            .into_iter()
+            .collect(),
+            delimiter: b',',
+            quote: b'"',
+            allow_partial_disputes: false,
+            max_total_withdrawal: None,
+            auto_resolve_disputes_after: None,
+            max_open_disputes: None,
+            disputable_types: [TransactionType::Deposit, TransactionType::Withdrawal]
+                .into_iter()
+                .collect(),
+            locked_allows: BTreeSet::new(),
+            enabled_types: [
+                TransactionType::Deposit,
+                TransactionType::Withdrawal,
+                TransactionType::Dispute,
+                TransactionType::Resolve,
+                TransactionType::Chargeback,
+                TransactionType::Unlock,
+                TransactionType::Freeze,
+                TransactionType::Unfreeze,
+                TransactionType::OpenBalance,
+            ]
+            .into_iter()
+            .collect(),
+            treat_negative_deposit_as_reversal: false,
+        }
+    }
+}
+
+impl ProcessingConfig {
+    /// Load a config from the TOML (.toml) or YAML (.yaml or .yml) file at the given path.
+    /// Enum options are written in snake case, e.g. `rounding_mode = "half_even"`.
+    /// May produce an error if the file cannot be read or parsed, or an UnsupportedConfigFormat error for any other extension.
+    /// Requires the std feature.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// let config = ProcessingConfig::from_file("resources/processing-config.toml").unwrap();
+    /// assert_eq!(config.rounding_mode, RoundingMode::HalfEven);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_file(config_file_path: &str) -> Result<ProcessingConfig, Box<dyn Error>> {
+        let contents = fs::read_to_string(config_file_path)?;
+        let extension = Path::new(config_file_path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        match extension {
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&contents)?),
+            _ => Err(Box::new(ProcessingError::UnsupportedConfigFormat(
+                config_file_path.to_string(),
+            ))),
+        }
+    }
+}
+
+/// What to do when the output path and the transaction input path refer to the same file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SamePathPolicy {
+    /// Refuse to process, leaving the input file untouched.
+    #[default]
+    Error,
+    /// Read and process the whole input file before replacing it with the output.
+    Overwrite,
+}
+
+/// The order in which client accounts are written to the output. Clients that compare equal are written in ascending client id order.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Ascending client id.
+    #[default]
+    ClientId,
+    /// Largest total funds first.
+    TotalDescending,
+    /// Largest available funds first.
+    AvailableDescending,
+    /// Locked accounts first.
+    LockedFirst,
+}
+
+/// What to do when a deposit or withdrawal is replayed within a single run.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayPolicy {
+    /// Apply the replayed transaction again, as with any other transaction.
+    #[default]
+    Apply,
+    /// Silently skip the replayed transaction.
+    Skip,
+    /// Reject the replayed transaction with a ReplayedTransaction error.
+    Error,
+}
+
+/// The format client accounts are written in.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One csv row per client with its balances and lock status.
+    #[default]
+    Csv,
+    /// One JSON document per line for each client, including every transaction applied to the account in transaction ID order.
+    Audit,
+    /// The same JSON documents as Audit, pretty-printed over several lines each, so two runs' logs can be compared with a line diff.
+    PrettyAudit,
+}
+
+/// The separators used to write balances, such as a decimal comma and a dot between thousands for European readers.
+/// A decimal separator that matches the csv delimiter is quoted, so a decimal comma is usually paired with a ';' delimiter.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NumberFormat {
+    /// The character between the whole and fractional digits. Defaults to '.'.
+    pub decimal_separator: char,
+
+    /// The character between each group of three whole digits, if any. Defaults to None.
+    pub grouping_separator: Option<char>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            decimal_separator: '.',
+            grouping_separator: None,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Write a balance rounded half up to four decimal places with this format's separators, keeping the digits of the default output.
+    /// The account output rounds balances with the config's rounding mode before formatting them, so this rounding leaves them unchanged.
+    /// A negative zero is written as zero, as in the default output.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// let format = NumberFormat {
+    ///     decimal_separator: ',',
+    ///     grouping_separator: Some('.'),
+    /// };
+    /// assert_eq!(format.format(1234567.891), "1.234.567,891");
+    /// assert_eq!(format.format(-0.5), "-0,5");
+    /// assert_eq!(NumberFormat::default().format(2.0), "2.0");
+    /// ```
+    pub fn format(&self, balance: f64) -> String {
+        let rounded = RoundingMode::HalfUp.round(balance);
+        let digits = format!("{:?}", if rounded == 0.0 { 0.0 } else { rounded });
+        let (sign, digits) = match digits.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", digits.as_str()),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+        let mut formatted = String::from(sign);
+        for (index, digit) in whole.chars().enumerate() {
+            if index > 0 && (whole.len() - index) % 3 == 0 {
+                formatted.extend(self.grouping_separator);
+            }
+            formatted.push(digit);
+        }
+        formatted.push(self.decimal_separator);
+        formatted.push_str(fraction);
+        formatted
+    }
+
+    /// Read a balance written with this format's separators back into a number, dropping any grouping separators.
+    /// May produce an error if what remains is not a number.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// let format = NumberFormat {
+    ///     decimal_separator: ',',
+    ///     grouping_separator: Some('.'),
+    /// };
+    /// assert_eq!(format.parse("1.234.567,891"), Ok(1234567.891));
+    /// assert!(format.parse("1,2,3").is_err());
+    /// ```
+    pub fn parse(&self, balance: &str) -> Result<f64, ParseFloatError> {
+        let digits: String = balance
+            .chars()
+            .filter(|c| Some(*c) != self.grouping_separator)
+            .map(|c| if c == self.decimal_separator { '.' } else { c })
+            .collect();
+        digits.parse()
+    }
+}
+
+/// What to do when an opening-balance seed file has more than one row for the same client.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedDuplicatePolicy {
+    /// Refuse to seed, producing a DuplicateSeedClient error.
+    #[default]
+    Error,
+    /// Add the duplicate row's balances to the client's seeded balances. The client is locked if any of its rows is locked.
+    Merge,
+}
+
+/// How amounts are rounded to four decimal places.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Round halves away from zero, so 0.12345 becomes 0.1235.
+    #[default]
+    HalfUp,
+    /// Round halves to the nearest even digit, so 0.12345 becomes 0.1234 but 0.12355 becomes 0.1236. Avoids systematic bias.
+    HalfEven,
+    /// Drop any digits past the fourth decimal place, rounding toward zero.
+    Truncate,
+}
+
+impl RoundingMode {
+    /// Round the given amount to four decimal places using this mode.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// assert_eq!(RoundingMode::HalfUp.round(0.12345), 0.1235);
+    /// assert_eq!(RoundingMode::HalfEven.round(0.12345), 0.1234);
+    /// assert_eq!(RoundingMode::Truncate.round(0.12349), 0.1234);
+    /// ```
+    pub fn round(&self, n: f64) -> f64 {
+        let scaled = n * 10000.0;
+        let rounded = match self {
+            RoundingMode::HalfUp => libm::round(scaled),
+            RoundingMode::HalfEven => {
+                let rounded = libm::round(scaled);
+                if libm::fabs(scaled - libm::trunc(scaled)) == 0.5 && rounded % 2.0 != 0.0 {
+                    rounded - libm::copysign(1.0, scaled)
+                } else {
+                    rounded
+                }
+            }
+            RoundingMode::Truncate => libm::trunc(scaled),
+        };
+        rounded / 10000.0
+    }
+}
+
+#[test]
+fn test_rounding_modes() {
+    assert_eq!(RoundingMode::HalfUp.round(0.12345), 0.1235);
+    assert_eq!(RoundingMode::HalfEven.round(0.12345), 0.1234);
+    assert_eq!(RoundingMode::HalfEven.round(0.12355), 0.1236);
+    assert_eq!(RoundingMode::HalfEven.round(-0.12345), -0.1234);
+    assert_eq!(RoundingMode::HalfEven.round(0.123451), 0.1235);
+    assert_eq!(RoundingMode::Truncate.round(0.12349), 0.1234);
+    assert_eq!(RoundingMode::Truncate.round(-0.12349), -0.1234);
+}
+
+#[test]
+fn test_config_files() {
+    let expected = ProcessingConfig {
+        overdraft_limit: 2.5,
+        sort_order: SortOrder::TotalDescending,
+        skip_invalid_transactions: true,
+        rounding_mode: RoundingMode::HalfEven,
+        max_amount: Some(1000.0),
+        ..Default::default()
+    };
+    let toml_config = ProcessingConfig::from_file("resources/processing-config.toml").unwrap();
+    let yaml_config = ProcessingConfig::from_file("resources/processing-config.yaml").unwrap();
+    assert_eq!(toml_config, expected);
+    assert_eq!(yaml_config, expected);
+
+    let mut output = Vec::new();
+    crate::transaction_manager::process_transactions_with_config(
+        &mut output,
+        "resources/timestamped-list-a.csv",
+        &toml_config,
+    )
+    .unwrap();
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    assert_eq!(
+        output,
+        "\
+client,available,held,total,locked
+2,2.0,0.0,2.0,false
+1,-0.5,0.0,-0.5,false
+"
+    );
+
+    assert!(
+        ProcessingConfig::from_file("resources/transaction-list.csv")
+            .unwrap_err()
+            .to_string()
+            .contains("Unsupported config file format")
+    );
+}
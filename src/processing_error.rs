@@ -0,0 +1,85 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// Represents errors with a processing run as a whole, rather than with any single transaction.
+#[derive(Debug)]
+pub enum ProcessingError {
+    /// If the output path refers to the same file as the transaction input path.
+    SameInputAndOutput(String),
+    /// If an opening-balance seed file has more than one row for a client, and the seed duplicate policy rejects duplicates.
+    DuplicateSeedClient(u16),
+    /// If two managers being merged both have an account for a client, so their inputs were not sharded by client. Gives the client id.
+    OverlappingMergeClient(u16),
+    /// If a config file path does not end in .toml, .yaml, or .yml.
+    UnsupportedConfigFormat(String),
+    /// If a client or transaction ID in the input is too large for its type. Names the field and the value.
+    IdOutOfRange(&'static str, u64),
+    /// If a transaction csv header row is missing any of the type, client, tx, and amount columns, or has any column that is not
+    /// one of those, timestamp, currency, or ref_tx. Lists the missing columns, then the unexpected columns.
+    InvalidHeader(Vec<String>, Vec<String>),
+    /// If more transactions were rejected than the config's max_errors or max_error_rate allow. Gives the number of rejected
+    /// transactions and the number of rows read so far.
+    TooManyErrors(usize, usize),
+    /// If a manager snapshot is inconsistent, such as one with more transactions applied than attempted. Describes the problem.
+    InvalidSnapshot(String),
+}
+
+impl fmt::Display for ProcessingError {
+    /// Readable error messages for each error
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessingError::SameInputAndOutput(path) => write!(
+                f,
+                "Error: Output path {} is the same file as the input path.",
+                path
+            ),
+            ProcessingError::DuplicateSeedClient(client_id) => write!(
+                f,
+                "Error: Seed file has more than one row for client {}.",
+                client_id
+            ),
+            ProcessingError::OverlappingMergeClient(client_id) => write!(
+                f,
+                "Error: Both managers being merged have an account for client {}. Apply their transactions in order with one manager instead.",
+                client_id
+            ),
+            ProcessingError::UnsupportedConfigFormat(path) => write!(
+                f,
+                "Error: Unsupported config file format for {}. Use a .toml, .yaml, or .yml file.",
+                path
+            ),
+            ProcessingError::IdOutOfRange(field, value) => write!(
+                f,
+                "Error: {} id {} is out of range. Client ids must be at most {} and tx ids at most {}.",
+                field,
+                value,
+                u16::MAX,
+                u32::MAX
+            ),
+            ProcessingError::InvalidHeader(missing, extra) => {
+                write!(f, "Error: Invalid transaction csv header.")?;
+                if !missing.is_empty() {
+                    write!(f, " Missing columns: {}.", missing.join(", "))?;
+                }
+                if !extra.is_empty() {
+                    write!(f, " Unexpected columns: {}.", extra.join(", "))?;
+                }
+                Ok(())
+            }
+            ProcessingError::TooManyErrors(errors, rows) => write!(
+                f,
+                "Error: Stopped after {} of {} transactions were rejected, which is more than the config allows.",
+                errors, rows
+            ),
+            ProcessingError::InvalidSnapshot(problem) => {
+                write!(f, "Error: Invalid manager snapshot: {}.", problem)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ProcessingError {}
@@ -0,0 +1,615 @@
+use csv::{ReaderBuilder, StringRecord, Trim, WriterBuilder};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+use crate::client::*;
+use crate::processing_config::*;
+#[cfg(test)]
+use crate::transaction::*;
+
+/// Find every transaction that changed a client's total balance by more than `factor` times the total before it.
+/// Each client's log is replayed in timestamp order if every logged transaction has a timestamp, otherwise in transaction ID order.
+/// Transactions applied to a zero balance are never flagged, since any change from zero is an unbounded jump.
+/// Returns (client id, transaction id) pairs in client order.
+/// ```
+/// use std::collections::BTreeMap;
+/// use transaction_manager::client::*;
+/// use transaction_manager::reporting::*;
+/// let clients: BTreeMap<u16, Client> = BTreeMap::new();
+/// assert!(balance_anomalies(&clients, 10.0).is_empty());
+/// ```
+pub fn balance_anomalies(clients: &BTreeMap<u16, Client>, factor: f64) -> Vec<(u16, u32)> {
+    let mut anomalies = Vec::new();
+    for client in clients.values() {
+        let mut total = 0.0;
+        for (transaction, change) in
+            replay_order(client.total_changes(), |(transaction, _)| *transaction)
+        {
+            if total != 0.0 && change.abs() > factor * f64::abs(total) {
+                anomalies.push((client.id, transaction.id));
+            }
+            total += change;
+        }
+    }
+    anomalies
+}
+
+/// System-wide totals across every client account in a batch, written as a sidecar to the account csv.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatchSummary {
+    /// Number of client accounts.
+    pub clients: usize,
+    /// Sum of every account's available funds.
+    pub available: f64,
+    /// Sum of every account's held funds.
+    pub held: f64,
+    /// Sum of every account's total funds.
+    pub total: f64,
+    /// Number of locked accounts.
+    pub locked_accounts: usize,
+}
+
+/// Add up the balances of every client account, rounding each sum to four decimal places.
+/// ```
+/// use std::collections::BTreeMap;
+/// use transaction_manager::client::*;
+/// use transaction_manager::reporting::*;
+/// let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+/// let mut client = Client::new(1);
+/// client.available = 0.1;
+/// client.total = 0.1;
+/// clients.insert(1, client.clone());
+/// client.id = 2;
+/// client.available = 0.2;
+/// client.total = 0.2;
+/// clients.insert(2, client);
+/// let summary = batch_summary(&clients);
+/// assert_eq!(summary.clients, 2);
+/// assert_eq!(summary.available, 0.3);
+/// ```
+pub fn batch_summary(clients: &BTreeMap<u16, Client>) -> BatchSummary {
+    let mut summary = BatchSummary {
+        clients: clients.len(),
+        available: 0.0,
+        held: 0.0,
+        total: 0.0,
+        locked_accounts: 0,
+    };
+    for client in clients.values() {
+        summary.available = round_to_four_decimals(summary.available + client.available);
+        summary.held = round_to_four_decimals(summary.held + client.held);
+        summary.total = round_to_four_decimals(summary.total + client.total);
+        if client.locked {
+            summary.locked_accounts += 1;
+        }
+    }
+    summary
+}
+
+/// Write the batch summary of the given client accounts to the given writer as a csv header and a single row,
+/// using the config's delimiter and quote.
+/// May produce an error if there is a problem serializing the data or writing.
+pub fn write_summary<W>(
+    writer: W,
+    clients: &BTreeMap<u16, Client>,
+    config: &ProcessingConfig,
+) -> Result<(), Box<dyn Error>>
+where
+    W: Write,
+{
+    let mut writer = WriterBuilder::new()
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .from_writer(writer);
+    writer.serialize(batch_summary(clients))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// A client account left holding funds at the end of a batch, with the disputes that are still open on it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HeldFunds {
+    /// Unique client ID.
+    pub client_id: u16,
+    /// Funds still held in dispute.
+    pub held: f64,
+    /// The IDs of the transactions that are still disputed, in ascending order.
+    pub open_dispute_ids: Vec<u32>,
+}
+
+/// Find every client account with non-zero held funds, and list the open disputes that need to be resolved or charged back.
+/// Returns one entry per such client, in client order.
+/// ```
+/// use std::collections::BTreeMap;
+/// use transaction_manager::client::*;
+/// use transaction_manager::reporting::*;
+/// let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+/// clients.insert(1, Client::new(1));
+/// assert!(held_funds_report(&clients).is_empty());
+/// ```
+pub fn held_funds_report(clients: &BTreeMap<u16, Client>) -> Vec<HeldFunds> {
+    clients
+        .values()
+        .filter(|client| client.held != 0.0)
+        .map(|client| HeldFunds {
+            client_id: client.id,
+            held: client.held,
+            open_dispute_ids: client
+                .open_disputes()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect(),
+        })
+        .collect()
+}
+
+/// The distribution of time between a dispute and the chargeback that settled it, in timestamp units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LagStats {
+    /// Number of dispute and chargeback pairs measured.
+    pub count: usize,
+    /// Shortest lag.
+    pub min: u64,
+    /// Longest lag.
+    pub max: u64,
+    /// Average lag.
+    pub mean: f64,
+    /// Middle lag, or the average of the two middle lags if there is an even number of pairs.
+    pub median: f64,
+}
+
+/// Measure the time between each chargeback and the dispute it settled, across every client.
+/// Chargebacks are paired with disputes as they are applied, by ref_tx or oldest open dispute first, so a chargeback that settled
+/// nothing is not measured. Pairs missing either timestamp are skipped. See Client::settled_chargebacks.
+/// Returns None if no pairs could be measured.
+/// ```
+/// use std::collections::BTreeMap;
+/// use transaction_manager::client::*;
+/// use transaction_manager::reporting::*;
+/// let clients: BTreeMap<u16, Client> = BTreeMap::new();
+/// assert_eq!(chargeback_lag_stats(&clients), None);
+/// ```
+pub fn chargeback_lag_stats(clients: &BTreeMap<u16, Client>) -> Option<LagStats> {
+    let mut lags: Vec<u64> = Vec::new();
+    for client in clients.values() {
+        for (dispute, chargeback) in client.settled_chargebacks() {
+            if let (Some(disputed_at), Some(charged_back_at)) =
+                (dispute.timestamp, chargeback.timestamp)
+            {
+                lags.push(charged_back_at.saturating_sub(disputed_at));
+            }
+        }
+    }
+    if lags.is_empty() {
+        return None;
+    }
+    lags.sort_unstable();
+    let count = lags.len();
+    let median = if count.is_multiple_of(2) {
+        (lags[count / 2 - 1] + lags[count / 2]) as f64 / 2.0
+    } else {
+        lags[count / 2] as f64
+    };
+    Some(LagStats {
+        count,
+        min: lags[0],
+        max: lags[count - 1],
+        mean: lags.iter().sum::<u64>() as f64 / count as f64,
+        median,
+    })
+}
+
+/// A client whose account differs between two account csv files.
+#[derive(Clone, Debug)]
+pub struct AccountDifference {
+    /// The client ID.
+    pub client_id: u16,
+    /// The account in the first file, or None if the client only appears in the second file.
+    pub first: Option<AccountState>,
+    /// The account in the second file, or None if the client only appears in the first file.
+    pub second: Option<AccountState>,
+    /// The names of the fields that differ ("available", "held", "total", or "locked"). Empty if the client is missing from either file.
+    pub differing_fields: Vec<&'static str>,
+}
+
+impl fmt::Display for AccountDifference {
+    /// Describe the difference field by field, giving the first value then the second, e.g. "client 2: available 0.5 vs 0.75".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "client {}: ", self.client_id)?;
+        let (first, second) = match (&self.first, &self.second) {
+            (Some(first), Some(second)) => (first, second),
+            (Some(_), None) => return write!(f, "only in the first accounts"),
+            (None, _) => return write!(f, "only in the second accounts"),
+        };
+        let details: Vec<String> = self
+            .differing_fields
+            .iter()
+            .map(|field| match *field {
+                "available" => format!("available {:?} vs {:?}", first.available, second.available),
+                "held" => format!("held {:?} vs {:?}", first.held, second.held),
+                "total" => format!("total {:?} vs {:?}", first.total, second.total),
+                _ => format!("locked {} vs {}", first.locked, second.locked),
+            })
+            .collect();
+        write!(f, "{}", details.join(", "))
+    }
+}
+
+/// Compare the accounts in two account csv files, such as the output of two runs with different code versions.
+/// Balances are compared at four decimal places, so differences in float noise beyond that are ignored.
+/// Returns a difference for every client that is missing from either file or has any differing field, in client order.
+/// May produce an error if either file cannot be read.
+/// ```
+/// use transaction_manager::reporting::*;
+/// let differences = reconcile("resources/reconcile-a.csv", "resources/reconcile-a.csv").unwrap();
+/// assert!(differences.is_empty());
+/// ```
+pub fn reconcile(
+    first_file_path: &str,
+    second_file_path: &str,
+) -> Result<Vec<AccountDifference>, Box<dyn Error>> {
+    let config = ProcessingConfig::default();
+    let first = account_states(read_accounts(first_file_path, &config)?);
+    let second = account_states(read_accounts(second_file_path, &config)?);
+    Ok(account_differences(&first, &second))
+}
+
+/// Compare two sets of accounts keyed by client ID, with balances compared at four decimal places.
+/// Returns a difference for every client that is missing from either set or has any differing field, in client order.
+/// ```
+/// use std::collections::BTreeMap;
+/// use transaction_manager::client::*;
+/// use transaction_manager::reporting::*;
+/// let mut first: BTreeMap<u16, AccountState> = BTreeMap::new();
+/// first.insert(1, Client::new(1).state());
+/// let mut second = first.clone();
+/// second.get_mut(&1).unwrap().locked = true;
+/// let differences = account_differences(&first, &second);
+/// assert_eq!(differences[0].differing_fields, vec!["locked"]);
+/// ```
+pub fn account_differences(
+    first: &BTreeMap<u16, AccountState>,
+    second: &BTreeMap<u16, AccountState>,
+) -> Vec<AccountDifference> {
+    let client_ids: BTreeSet<&u16> = first.keys().chain(second.keys()).collect();
+    let mut differences = Vec::new();
+    for client_id in client_ids {
+        let first_client = first.get(client_id);
+        let second_client = second.get(client_id);
+        let mut differing_fields = Vec::new();
+        if let (Some(first_client), Some(second_client)) = (first_client, second_client) {
+            differing_fields = account_differing_fields(first_client, second_client);
+            if differing_fields.is_empty() {
+                continue;
+            }
+        }
+        differences.push(AccountDifference {
+            client_id: *client_id,
+            first: first_client.cloned(),
+            second: second_client.cloned(),
+            differing_fields,
+        });
+    }
+    differences
+}
+
+/// Find the clients whose accounts changed since the given baseline accounts, such as the account file from a previous run.
+/// A client is changed if its available, held, or total funds differ at four decimal places, or its lock status differs.
+/// Clients that are not in the baseline are new, and count as changed. Baseline clients that are not in the given accounts
+/// have no new state to report, so they are not included. Returns the changed clients in client order.
+/// ```
+/// use std::collections::BTreeMap;
+/// use transaction_manager::client::*;
+/// use transaction_manager::reporting::*;
+/// let mut baseline: BTreeMap<u16, Client> = BTreeMap::new();
+/// baseline.insert(1, Client::new(1));
+/// let mut clients = baseline.clone();
+/// assert!(changed_accounts(&baseline, &clients).is_empty());
+/// clients.insert(2, Client::new(2));
+/// assert_eq!(changed_accounts(&baseline, &clients)[0].id, 2);
+/// ```
+pub fn changed_accounts<'a>(
+    baseline: &BTreeMap<u16, Client>,
+    clients: &'a BTreeMap<u16, Client>,
+) -> Vec<&'a Client> {
+    clients
+        .values()
+        .filter(|client| match baseline.get(&client.id) {
+            Some(baseline_client) => {
+                !account_differing_fields(&baseline_client.state(), &client.state()).is_empty()
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// The names of the fields that differ between two accounts, with balances compared at four decimal places.
+fn account_differing_fields(first: &AccountState, second: &AccountState) -> Vec<&'static str> {
+    let mut differing_fields = Vec::new();
+    for (field, first_value, second_value) in [
+        ("available", first.available, second.available),
+        ("held", first.held, second.held),
+        ("total", first.total, second.total),
+    ] {
+        if round_to_four_decimals(first_value) != round_to_four_decimals(second_value) {
+            differing_fields.push(field);
+        }
+    }
+    if first.locked != second.locked {
+        differing_fields.push("locked");
+    }
+    differing_fields
+}
+
+/// The balances and status of each of the given accounts, keyed by client ID.
+pub(crate) fn account_states(clients: BTreeMap<u16, Client>) -> BTreeMap<u16, AccountState> {
+    clients
+        .into_iter()
+        .map(|(client_id, client)| (client_id, client.state()))
+        .collect()
+}
+
+/// Read every account from the given account csv file path, keyed by client ID, using the config's delimiter and quote.
+/// Balances are read with the config's number format if it has one, so an account file written with the same config reads back.
+/// Comment lines starting with '#', such as a metadata header, are skipped.
+/// May produce an error if the file cannot be read or has a malformed row.
+pub fn read_accounts(
+    accounts_file_path: &str,
+    config: &ProcessingConfig,
+) -> Result<BTreeMap<u16, Client>, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .comment(Some(b'#'))
+        .from_path(accounts_file_path)?;
+    let headers = reader.headers()?.clone();
+    let balance_columns: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| ["available", "held", "total"].contains(column))
+        .map(|(index, _)| index)
+        .collect();
+    let mut clients = BTreeMap::new();
+    for next_record_result in reader.records() {
+        let mut record = next_record_result?;
+        if let Some(number_format) = &config.number_format {
+            record = record
+                .iter()
+                .enumerate()
+                .map(|(index, field)| {
+                    if balance_columns.contains(&index) {
+                        number_format
+                            .parse(field)
+                            .map(|balance| balance.to_string())
+                    } else {
+                        Ok(field.to_string())
+                    }
+                })
+                .collect::<Result<StringRecord, _>>()?;
+        }
+        let client: Client = record.deserialize(Some(&headers))?;
+        clients.insert(client.id, client);
+    }
+    Ok(clients)
+}
+
+#[test]
+fn test_balance_anomalies() {
+    let mut client = Client::new(3);
+    for (id, amount) in [(0, 1.0), (1, 1000.0), (2, 50.0)] {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 3,
+                id,
+                amount: Some(amount),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    clients.insert(client.id, client);
+    assert_eq!(balance_anomalies(&clients, 10.0), vec![(3, 1)]);
+    assert_eq!(balance_anomalies(&clients, 0.01), vec![(3, 1), (3, 2)]);
+}
+
+#[test]
+fn test_held_funds_report() {
+    let transaction = |transaction_type, id, amount| Transaction {
+        transaction_type,
+        client_id: 1,
+        id,
+        amount,
+        ..Default::default()
+    };
+    let mut client = Client::new(1);
+    for (transaction_type, id, amount) in [
+        (TransactionType::Deposit, 1, Some(2.0)),
+        (TransactionType::Deposit, 2, Some(3.0)),
+        (TransactionType::Deposit, 3, Some(4.0)),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Dispute, 2, None),
+        (TransactionType::Resolve, 2, None),
+        (TransactionType::Dispute, 3, None),
+    ] {
+        client = client
+            .apply_transaction(transaction(transaction_type, id, amount))
+            .unwrap();
+    }
+    assert_eq!(client.open_disputes(), vec![(1, 2.0), (3, 4.0)]);
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    clients.insert(client.id, client);
+    clients.insert(2, Client::new(2));
+    assert_eq!(
+        held_funds_report(&clients),
+        vec![HeldFunds {
+            client_id: 1,
+            held: 6.0,
+            open_dispute_ids: vec![1, 3],
+        }]
+    );
+}
+
+#[test]
+fn test_chargeback_lag_stats() {
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    for (client_id, id, disputed_at, charged_back_at) in [
+        (1, 1, 10, 15),
+        (2, 2, 20, 50),
+        (3, 3, 5, 6),
+        (4, 4, 100, 120),
+    ] {
+        let mut client = Client::new(client_id);
+        for (transaction_type, amount, timestamp) in [
+            (TransactionType::Deposit, Some(1.0), disputed_at - 1),
+            (TransactionType::Dispute, None, disputed_at),
+            (TransactionType::Chargeback, None, charged_back_at),
+        ] {
+            client = client
+                .apply_transaction(Transaction {
+                    transaction_type,
+                    client_id,
+                    id,
+                    amount,
+                    timestamp: Some(timestamp),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        clients.insert(client_id, client);
+    }
+    assert_eq!(
+        chargeback_lag_stats(&clients),
+        Some(LagStats {
+            count: 4,
+            min: 1,
+            max: 30,
+            mean: 14.0,
+            median: 12.5,
+        })
+    );
+}
+
+#[test]
+fn test_chargeback_lag_stats_skips_chargeback_after_resolve() {
+    let mut client = Client::new(1);
+    for (transaction_type, amount, timestamp) in [
+        (TransactionType::Deposit, Some(1.0), 1),
+        (TransactionType::Dispute, None, 10),
+        (TransactionType::Resolve, None, 12),
+        (TransactionType::Chargeback, None, 30),
+    ] {
+        client = client
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 1,
+                id: 1,
+                amount,
+                timestamp: Some(timestamp),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    // The chargeback found no open dispute, so it settled nothing and has no lag.
+    assert!(!client.locked);
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    clients.insert(1, client);
+    assert_eq!(chargeback_lag_stats(&clients), None);
+}
+
+#[test]
+fn test_chargeback_lag_stats_measures_from_settled_dispute() {
+    let config = ProcessingConfig {
+        allow_partial_disputes: true,
+        ..Default::default()
+    };
+    let mut client = Client::new(1);
+    for (transaction_type, amount, timestamp) in [
+        (TransactionType::Deposit, Some(2.0), 1),
+        (TransactionType::Dispute, Some(1.0), 10),
+        (TransactionType::Dispute, Some(1.0), 20),
+        (TransactionType::Chargeback, None, 30),
+    ] {
+        client = client
+            .apply_transaction_with_config(
+                Transaction {
+                    transaction_type,
+                    client_id: 1,
+                    id: 1,
+                    amount,
+                    timestamp: Some(timestamp),
+                    ..Default::default()
+                },
+                &config,
+            )
+            .unwrap();
+    }
+    // The chargeback settles the oldest open dispute, not the latest one.
+    assert_eq!(client.held, 1.0);
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    clients.insert(1, client);
+    assert_eq!(
+        chargeback_lag_stats(&clients),
+        Some(LagStats {
+            count: 1,
+            min: 20,
+            max: 20,
+            mean: 20.0,
+            median: 20.0,
+        })
+    );
+}
+
+#[test]
+fn test_reconcile() {
+    let differences = reconcile("resources/reconcile-a.csv", "resources/reconcile-b.csv").unwrap();
+    assert_eq!(differences.len(), 1);
+    assert_eq!(differences[0].client_id, 2);
+    assert_eq!(differences[0].differing_fields, vec!["available", "total"]);
+    assert_eq!(differences[0].first.as_ref().unwrap().available, 0.5);
+    assert_eq!(differences[0].second.as_ref().unwrap().available, 0.75);
+}
+
+#[test]
+fn test_write_summary() {
+    let mut manager = crate::transaction_manager::TransactionManager::new(Default::default());
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let mut output = Vec::new();
+    manager.write_summary(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+clients,available,held,total,locked_accounts
+4,10.0,3.3,13.3,1
+"
+    );
+
+    // The summary uses the same delimiter as the account output.
+    let mut manager = crate::transaction_manager::TransactionManager::new(ProcessingConfig {
+        delimiter: b';',
+        ..Default::default()
+    });
+    manager
+        .apply_transaction(Transaction {
+            client_id: 1,
+            id: 1,
+            amount: Some(2.5),
+            ..Default::default()
+        })
+        .unwrap();
+    let mut output = Vec::new();
+    manager.write_summary(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+clients;available;held;total;locked_accounts
+1;2.5;0.0;2.5;0
+"
+    );
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::io::Write;
+
+use crate::client::*;
+use crate::processing_config::*;
+
+/// Write an upsert statement for each of the given accounts, in the given order, to insert it into the given table
+/// or update the existing row for its client. The table needs columns named after the account csv columns,
+/// with client as its primary key. The statements use `INSERT ... ON CONFLICT (client) DO UPDATE`, which both
+/// PostgreSQL and SQLite accept, and are only generated as text, so no database driver is needed. Requires the `sql` feature.
+/// Balances are rounded with the config's rounding mode, so they match the account csv output. See AccountState::rounded.
+/// May produce an error if there is a problem writing.
+/// ```
+/// use transaction_manager::client::*;
+/// use transaction_manager::processing_config::*;
+/// use transaction_manager::sql::*;
+/// let mut output = Vec::new();
+/// write_accounts_sql(&mut output, vec![Client::new(1).state()], "accounts", &ProcessingConfig::default()).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "\
+/// INSERT INTO \"accounts\" (client, available, held, total, locked) VALUES (1, 0.0, 0.0, 0.0, FALSE) \
+/// ON CONFLICT (client) DO UPDATE SET available = excluded.available, held = excluded.held, \
+/// total = excluded.total, locked = excluded.locked;
+/// ");
+/// ```
+pub fn write_accounts_sql<W, I>(
+    mut writer: W,
+    account_states: I,
+    table: &str,
+    config: &ProcessingConfig,
+) -> Result<(), Box<dyn Error>>
+where
+    W: Write,
+    I: IntoIterator<Item = AccountState>,
+{
+    let table = quote_identifier(table);
+    for account_state in account_states {
+        let account_state = account_state.rounded(&config.rounding_mode);
+        writeln!(
+            writer,
+            "INSERT INTO {} (client, available, held, total, locked) VALUES ({}, {:?}, {:?}, {:?}, {}) \
+             ON CONFLICT (client) DO UPDATE SET available = excluded.available, held = excluded.held, \
+             total = excluded.total, locked = excluded.locked;",
+            table,
+            account_state.id,
+            account_state.available,
+            account_state.held,
+            account_state.total,
+            if account_state.locked { "TRUE" } else { "FALSE" },
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Quote the given table name as an SQL identifier, doubling any quotes inside it, so it cannot end the statement early.
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[test]
+fn test_write_accounts_sql() {
+    use crate::transaction_manager::*;
+
+    let config = ProcessingConfig::default();
+    let mut manager = TransactionManager::new(config.clone());
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let mut output = Vec::new();
+    write_accounts_sql(
+        &mut output,
+        manager.into_account_states(),
+        "accounts",
+        &config,
+    )
+    .unwrap();
+    let upsert =
+        "ON CONFLICT (client) DO UPDATE SET available = excluded.available, held = excluded.held, \
+                  total = excluded.total, locked = excluded.locked;";
+    let expected: String = [
+        "(1, 1.0, 0.0, 1.0, FALSE)",
+        "(2, 0.0, 3.3, 3.3, FALSE)",
+        "(3, 4.0, 0.0, 4.0, FALSE)",
+        "(4, 5.0, 0.0, 5.0, TRUE)",
+    ]
+    .iter()
+    .map(|values| {
+        format!(
+            "INSERT INTO \"accounts\" (client, available, held, total, locked) VALUES {} {}\n",
+            values, upsert
+        )
+    })
+    .collect();
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+    assert_eq!(quote_identifier("a\"b"), "\"a\"\"b\"");
+
+    // Balances are rounded with the configured mode, and a balance that rounds to zero is written as zero, as in the csv output.
+    let account_state = AccountState {
+        id: 5,
+        available: 1.23459,
+        held: -0.00001,
+        total: 1.23458,
+        locked: false,
+    };
+    let config = ProcessingConfig {
+        rounding_mode: RoundingMode::Truncate,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_accounts_sql(
+        &mut output,
+        vec![account_state.clone()],
+        "accounts",
+        &config,
+    )
+    .unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("VALUES (5, 1.2345, 0.0, 1.2345, FALSE)"));
+    let mut csv_output = Vec::new();
+    write_account_states(&mut csv_output, vec![account_state], &config).unwrap();
+    assert!(String::from_utf8(csv_output)
+        .unwrap()
+        .ends_with("\n5,1.2345,0.0,1.2345,false\n"));
+}
@@ -1,7 +1,15 @@
-use serde::Deserialize;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use core::convert::TryFrom;
+use core::fmt;
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 
-/// Represent the types of transactions accepted
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+use crate::processing_error::*;
+
+/// Represent the types of transactions accepted.
+/// Types are written by their lowercase name, and read case-insensitively, so "deposit", "Deposit", and "DEPOSIT" are the same type.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     /// Adding funds.
@@ -14,23 +22,305 @@ pub enum TransactionType {
     Resolve,
     /// Removing funds forcefully. Locks account.
     Chargeback,
+    /// Administratively unlocking an account after manual review. Unless the config allows other types on locked accounts, the only transaction allowed on a locked account.
+    /// Anyone who can write rows to the transaction input can unlock any account, so input containing unlocks must come from a trusted source.
+    Unlock,
+    /// Administratively freezing an account, which blocks all activity on it without moving funds.
+    /// Freezing is separate from a chargeback lock: the two have their own flags, and neither an unfreeze nor an unlock clears the other.
+    Freeze,
+    /// Administratively unfreezing an account. The only transaction allowed on a frozen account, other than another freeze.
+    Unfreeze,
+    /// Setting a client's starting balance, such as when migrating accounts from another system. Only accepted as a client's
+    /// first transaction. Adds funds like a deposit, but is never disputable, whatever the config's disputable types.
+    #[serde(rename = "open_balance")]
+    OpenBalance,
+}
+
+/// The name of every transaction type, as it is written in csv and JSON.
+const TRANSACTION_TYPE_NAMES: [&str; 9] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "unlock",
+    "freeze",
+    "unfreeze",
+    "open_balance",
+];
+
+impl TransactionType {
+    /// The name of the transaction type, as it is written in csv and JSON.
+    /// ```
+    /// use transaction_manager::transaction::*;
+    /// assert_eq!(TransactionType::OpenBalance.name(), "open_balance");
+    /// ```
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => TRANSACTION_TYPE_NAMES[0],
+            TransactionType::Withdrawal => TRANSACTION_TYPE_NAMES[1],
+            TransactionType::Dispute => TRANSACTION_TYPE_NAMES[2],
+            TransactionType::Resolve => TRANSACTION_TYPE_NAMES[3],
+            TransactionType::Chargeback => TRANSACTION_TYPE_NAMES[4],
+            TransactionType::Unlock => TRANSACTION_TYPE_NAMES[5],
+            TransactionType::Freeze => TRANSACTION_TYPE_NAMES[6],
+            TransactionType::Unfreeze => TRANSACTION_TYPE_NAMES[7],
+            TransactionType::OpenBalance => TRANSACTION_TYPE_NAMES[8],
+        }
+    }
+
+    /// Find the transaction type with the given name, ignoring case, or None if no type has that name.
+    /// ```
+    /// use transaction_manager::transaction::*;
+    /// assert_eq!(TransactionType::from_name("Withdrawal"), Some(TransactionType::Withdrawal));
+    /// assert_eq!(TransactionType::from_name("withdraw"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<TransactionType> {
+        [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Unlock,
+            TransactionType::Freeze,
+            TransactionType::Unfreeze,
+            TransactionType::OpenBalance,
+        ]
+        .into_iter()
+        .find(|transaction_type| transaction_type.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    /// Read a transaction type from its name in any case, producing an unknown variant error for any other name.
+    fn deserialize<D>(deserializer: D) -> Result<TransactionType, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(TransactionTypeVisitor)
+    }
+}
+
+/// Reads a transaction type from a borrowed name, so reading a type never allocates.
+struct TransactionTypeVisitor;
+
+impl<'de> Visitor<'de> for TransactionTypeVisitor {
+    type Value = TransactionType;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a transaction type name")
+    }
+
+    fn visit_str<E>(self, name: &str) -> Result<TransactionType, E>
+    where
+        E: Error,
+    {
+        TransactionType::from_name(name)
+            .ok_or_else(|| E::unknown_variant(name, &TRANSACTION_TYPE_NAMES))
+    }
 }
 
-/// A transaction has a type, client id, transaction id, and optional amount.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+/// A transaction has a type, client id, transaction id, optional amount, optional timestamp, and optional currency.
+/// Transactions serialize to the same columns they are read from, so a written transaction file reads back identically.
+/// Missing optional values are written as empty cells.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Transaction {
     /// Transaction type
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
 
     /// Unique client ID
-    #[serde(rename = "client")]
+    #[serde(rename = "client", deserialize_with = "deserialize_client_id")]
     pub client_id: u16,
 
     /// Unique or referenced transaction ID
-    #[serde(rename = "tx")]
+    #[serde(rename = "tx", deserialize_with = "deserialize_transaction_id")]
     pub id: u32,
 
-    /// Amount is specified only for deposit or withdrawal
+    /// Amount is specified only for deposit or withdrawal.
+    /// csv fields are trimmed, so an amount cell holding only whitespace is read as a blank cell, giving None,
+    /// which a deposit or withdrawal rejects with a MissingRequiredAmount error. A present zero amount is Some(0.0),
+    /// which a deposit or withdrawal rejects with a NonPositiveAmount error instead.
     pub amount: Option<f64>,
+
+    /// Optional time the transaction occurred, used to order transactions merged from several files
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+
+    /// Optional currency code. Transactions naming a different currency than their account are rejected
+    #[serde(default)]
+    pub currency: Option<String>,
+
+    /// Optional transaction a dispute, resolve, or chargeback refers to, when its own tx id is unique rather than the id of the original.
+    /// A dispute names the original deposit or withdrawal. A resolve or chargeback names either the original, settling its oldest open dispute,
+    /// or a dispute by its own id, settling that dispute. Takes precedence over tx when present.
+    /// Omitted when serialized if None, so transactions without one are written in the original columns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ref_tx: Option<u32>,
+
+    /// Extra columns passed through from the input by name, such as a merchant id or memo, if the config keeps them.
+    /// They are not used by the engine, but are kept in the transaction log and audit output for downstream correlation.
+    /// Omitted when serialized if empty, and csv output cannot hold them, so only transactions without metadata can be written as csv.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Default for Transaction {
+    /// A deposit of no amount with client and transaction id 0, and no optional values or metadata, so that a transaction can be
+    /// written with only the fields it cares about, followed by `..Default::default()`.
+    /// ```
+    /// use transaction_manager::transaction::*;
+    /// let transaction = Transaction {
+    ///     amount: Some(1.0),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(transaction.transaction_type, TransactionType::Deposit);
+    /// assert_eq!(transaction.currency, None);
+    /// ```
+    fn default() -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 0,
+            id: 0,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            ref_tx: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// Remove the given currency symbol, ',' thousands separators, and surrounding whitespace from an amount cell,
+/// so that an amount such as `$1,250.00` can be parsed as 1250.0. An empty cell stays empty, and is read as no amount.
+/// ```
+/// use transaction_manager::transaction::*;
+/// assert_eq!(strip_amount_formatting("$1,250.00", "$"), "1250.00");
+/// assert_eq!(strip_amount_formatting("1250", "$"), "1250");
+/// assert_eq!(strip_amount_formatting("", "$"), "");
+/// ```
+pub fn strip_amount_formatting(amount: &str, currency_symbol: &str) -> String {
+    let amount = if currency_symbol.is_empty() {
+        amount.to_string()
+    } else {
+        amount.replace(currency_symbol, "")
+    };
+    amount.replace(',', "").trim().to_string()
+}
+
+/// Deserialize a client ID, producing an IdOutOfRange error that names the value if it does not fit in a u16.
+fn deserialize_client_id<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_id(deserializer, "client")
+}
+
+/// Deserialize a transaction ID, producing an IdOutOfRange error that names the value if it does not fit in a u32.
+fn deserialize_transaction_id<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_id(deserializer, "tx")
+}
+
+/// Deserialize an ID of any unsigned type from a u64, naming the field and value if it is out of range for the type.
+fn deserialize_id<'de, D, T>(deserializer: D, field: &'static str) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let value = u64::deserialize(deserializer)?;
+    T::try_from(value).map_err(|_| D::Error::custom(ProcessingError::IdOutOfRange(field, value)))
+}
+
+#[test]
+fn test_csv_round_trip() {
+    let transactions = vec![
+        Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            id: 1,
+            amount: Some(2.2),
+            timestamp: Some(100),
+            currency: Some("USD".to_string()),
+            ..Default::default()
+        },
+        Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            client_id: 1,
+            id: 2,
+            amount: Some(1.2345),
+            ..Default::default()
+        },
+        Transaction {
+            transaction_type: TransactionType::Dispute,
+            client_id: 1,
+            id: 1,
+            amount: None,
+            ..Default::default()
+        },
+        Transaction {
+            transaction_type: TransactionType::Resolve,
+            client_id: 1,
+            id: 1,
+            amount: None,
+            timestamp: Some(101),
+            ..Default::default()
+        },
+        Transaction {
+            transaction_type: TransactionType::Chargeback,
+            client_id: 65535,
+            id: 4294967295,
+            amount: None,
+            currency: Some("EUR".to_string()),
+            ..Default::default()
+        },
+    ];
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for transaction in &transactions {
+        writer.serialize(transaction).unwrap();
+    }
+    let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    assert_eq!(
+        output,
+        "\
+type,client,tx,amount,timestamp,currency
+deposit,1,1,2.2,100,USD
+withdrawal,1,2,1.2345,,
+dispute,1,1,,,
+resolve,1,1,,101,
+chargeback,65535,4294967295,,,EUR
+"
+    );
+    let read_back: Vec<Transaction> = csv::Reader::from_reader(output.as_bytes())
+        .deserialize()
+        .map(|transaction| transaction.unwrap())
+        .collect();
+    assert_eq!(read_back, transactions);
+}
+
+#[test]
+fn test_id_out_of_range() {
+    let read = |row: &str| -> Result<Transaction, csv::Error> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(format!("type, client, tx, amount\n{}\n", row).as_bytes())
+            .deserialize()
+            .next()
+            .unwrap()
+    };
+    let transaction = read("deposit, 65535, 4294967295, 1.0").unwrap();
+    assert_eq!(transaction.client_id, u16::MAX);
+    assert_eq!(transaction.id, u32::MAX);
+
+    let error = read("deposit, 70000, 1, 1.0").unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("client id 70000 is out of range"));
+    let error = read("deposit, 1, 4294967296, 1.0").unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("tx id 4294967296 is out of range"));
 }
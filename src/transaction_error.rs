@@ -1,60 +1,234 @@
+use alloc::boxed::Box;
+use alloc::format;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fmt;
 
 use crate::client::*;
 use crate::transaction::*;
 
 /// Represents various errors that could be encountered when attempting to apply transactions incorrectly.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum TransactionErrorTypes {
     /// If deposit or withdrawal are attempted with zero or negative amount.
     NonPositiveAmount,
+    /// If deposit or withdrawal are attempted with an amount that is NaN or infinite.
+    NonFiniteAmount,
+    /// If a deposit is below the configured minimum deposit, or a deposit or withdrawal is above the configured maximum amount.
+    /// Also if an amount, or the balance a deposit would produce, is above client::MAX_SAFE_AMOUNT.
+    /// Carries the offending amount.
+    AmountOutOfBounds(f64),
     /// If deposit or withdrawal are attempted with no amount specified.
     MissingRequiredAmount,
-    /// If dispute, resolve, or chargeback are attempted with an amount specified.
+    /// If dispute, resolve, chargeback, or unlock are attempted with an amount specified.
     HasMeaninglessAmount,
     /// If withdrawal is attempted with amount greater than available funds.
     InsufficientFunds,
-    /// If a client is initialized with any transaction type other than deposit.
+    /// If a withdrawal would push a client's withdrawn total over the configured maximum total withdrawal.
+    WithdrawalLimitExceeded,
+    /// If a client is initialized with a withdrawal rather than a deposit.
     FirstTransactionNotDeposit,
-    /// If any transaction is attempted on a locked account.
+    /// If a dispute, resolve, chargeback, unlock, freeze, or unfreeze is the first transaction seen for a client, so the account it references does not exist.
+    AccountNotFound,
+    /// If any transaction other than an unlock, or a type the config allows on locked accounts, is attempted on a locked account.
     AccountLocked,
-    /// Generic error for very uncommon issues.
+    /// If any transaction other than a freeze or unfreeze is attempted on a frozen account.
+    AccountFrozen,
+    /// If a transaction names a different currency than the account it is applied to.
+    CurrencyMismatch,
+    /// If a dispute would give a client more open disputes than the configured maximum.
+    TooManyOpenDisputes,
+    /// If a transaction would create a new client beyond the configured maximum number of clients.
+    ClientLimitExceeded,
+    /// If an opening balance is applied to a client that already has transactions or funds.
+    OpenBalanceNotFirst,
+    /// If a deposit or withdrawal exactly repeats one already applied in this run, and the replay policy rejects replays.
+    ReplayedTransaction,
+    /// If a dispute, resolve, or chargeback references an id that has no logged deposit or withdrawal,
+    /// such as an id only used by an earlier dispute of an unknown transaction.
+    InvalidIdReferenced,
+    /// If a dispute references a type of transaction that the config does not allow to be disputed.
+    NotDisputable,
+    /// If a transaction's type is not enabled by the config, so the deployment does not process that type.
+    TransactionTypeDisabled,
+    /// If a partial dispute amount is not positive or exceeds the amount of the transaction it references.
+    InvalidDisputeAmount,
+    /// If applying a transaction left an account whose total funds do not equal its available plus held funds.
+    /// Only checked when the invariant-checks feature is enabled.
+    InvariantViolation,
+    /// Generic error for unexpected internal states, such as an empty group of logged transactions.
     Unspecified,
 }
 
+impl TransactionErrorTypes {
+    /// A stable, machine-readable code for each error type, suitable for logging pipelines and programmatic consumers.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionErrorTypes::NonPositiveAmount => "NON_POSITIVE_AMOUNT",
+            TransactionErrorTypes::MissingRequiredAmount => "MISSING_REQUIRED_AMOUNT",
+            TransactionErrorTypes::HasMeaninglessAmount => "HAS_MEANINGLESS_AMOUNT",
+            TransactionErrorTypes::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            TransactionErrorTypes::FirstTransactionNotDeposit => "FIRST_TRANSACTION_NOT_DEPOSIT",
+            TransactionErrorTypes::AccountNotFound => "ACCOUNT_NOT_FOUND",
+            TransactionErrorTypes::AccountLocked => "ACCOUNT_LOCKED",
+            TransactionErrorTypes::CurrencyMismatch => "CURRENCY_MISMATCH",
+            TransactionErrorTypes::ClientLimitExceeded => "CLIENT_LIMIT_EXCEEDED",
+            TransactionErrorTypes::ReplayedTransaction => "REPLAYED_TRANSACTION",
+            TransactionErrorTypes::NonFiniteAmount => "NON_FINITE_AMOUNT",
+            TransactionErrorTypes::AmountOutOfBounds(_) => "AMOUNT_OUT_OF_BOUNDS",
+            TransactionErrorTypes::InvariantViolation => "INVARIANT_VIOLATION",
+            TransactionErrorTypes::InvalidDisputeAmount => "INVALID_DISPUTE_AMOUNT",
+            TransactionErrorTypes::WithdrawalLimitExceeded => "WITHDRAWAL_LIMIT_EXCEEDED",
+            TransactionErrorTypes::InvalidIdReferenced => "INVALID_ID_REFERENCED",
+            TransactionErrorTypes::NotDisputable => "NOT_DISPUTABLE",
+            TransactionErrorTypes::TransactionTypeDisabled => "TRANSACTION_TYPE_DISABLED",
+            TransactionErrorTypes::AccountFrozen => "ACCOUNT_FROZEN",
+            TransactionErrorTypes::TooManyOpenDisputes => "TOO_MANY_OPEN_DISPUTES",
+            TransactionErrorTypes::OpenBalanceNotFirst => "OPEN_BALANCE_NOT_FIRST",
+            TransactionErrorTypes::Unspecified => "UNSPECIFIED",
+        }
+    }
+
+    /// A stable numeric code for each error type. Codes are never reused once assigned.
+    pub fn code_num(&self) -> u16 {
+        match self {
+            TransactionErrorTypes::Unspecified => 0,
+            TransactionErrorTypes::NonPositiveAmount => 1,
+            TransactionErrorTypes::MissingRequiredAmount => 2,
+            TransactionErrorTypes::HasMeaninglessAmount => 3,
+            TransactionErrorTypes::InsufficientFunds => 4,
+            TransactionErrorTypes::FirstTransactionNotDeposit => 5,
+            TransactionErrorTypes::AccountLocked => 6,
+            TransactionErrorTypes::ClientLimitExceeded => 7,
+            TransactionErrorTypes::AccountNotFound => 8,
+            TransactionErrorTypes::CurrencyMismatch => 9,
+            TransactionErrorTypes::ReplayedTransaction => 10,
+            TransactionErrorTypes::NonFiniteAmount => 11,
+            TransactionErrorTypes::AmountOutOfBounds(_) => 12,
+            TransactionErrorTypes::InvariantViolation => 13,
+            TransactionErrorTypes::InvalidDisputeAmount => 14,
+            TransactionErrorTypes::WithdrawalLimitExceeded => 15,
+            TransactionErrorTypes::InvalidIdReferenced => 16,
+            TransactionErrorTypes::NotDisputable => 17,
+            TransactionErrorTypes::TransactionTypeDisabled => 18,
+            TransactionErrorTypes::AccountFrozen => 19,
+            TransactionErrorTypes::TooManyOpenDisputes => 20,
+            TransactionErrorTypes::OpenBalanceNotFirst => 21,
+        }
+    }
+}
+
 /// A transaction error has a type and captures the transaction/account environment in which it occurred.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TransactionError {
     /// One of the specified error types, or unspecified.
     pub error_type: TransactionErrorTypes,
     /// The transaction that caused the error.
-    pub transaction: Transaction,
-    /// The client account that the transaction was attempted on.
-    pub client: Client,
+    pub transaction: Box<Transaction>,
+    /// The balances and status of the client account that the transaction was attempted on, as they were when it was rejected.
+    /// AccountState is the lightweight client snapshot (id, available, held, total, locked) the error keeps instead of a full Client,
+    /// so building an error never copies the transaction log.
+    pub client: AccountState,
+}
+
+impl TransactionError {
+    /// A stable, machine-readable code for this error's type, e.g. "NON_POSITIVE_AMOUNT".
+    /// ```
+    /// use transaction_manager::client::*;
+    /// use transaction_manager::transaction::*;
+    /// let error = initialize_client(Transaction {
+    ///     transaction_type: TransactionType::Deposit,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(-1.0),
+    ///     ..Default::default()
+    /// }).unwrap_err();
+    /// assert_eq!(error.code(), "NON_POSITIVE_AMOUNT");
+    /// assert_eq!(error.code_num(), 1);
+    /// ```
+    pub fn code(&self) -> &'static str {
+        self.error_type.code()
+    }
+
+    /// A stable numeric code for this error's type.
+    pub fn code_num(&self) -> u16 {
+        self.error_type.code_num()
+    }
 }
 
 impl fmt::Display for TransactionError {
     /// Readable error messages for each specified type
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bounds_message;
         let message = match self.error_type {
             TransactionErrorTypes::NonPositiveAmount => {
                 "Negative or zero value provided for transaction amount."
             }
+            TransactionErrorTypes::NonFiniteAmount => {
+                "NaN or infinite value provided for transaction amount."
+            }
+            TransactionErrorTypes::AmountOutOfBounds(amount) => {
+                bounds_message = format!(
+                    "Transaction amount {} is outside the configured or safe bounds.",
+                    amount
+                );
+                &bounds_message
+            }
             TransactionErrorTypes::MissingRequiredAmount => {
                 "Deposit or withdrawel without specified amount."
             }
             TransactionErrorTypes::HasMeaninglessAmount => {
-                "Dispute, resolve, or chargeback with specified amount."
+                "Dispute, resolve, chargeback, or unlock with specified amount."
             }
             TransactionErrorTypes::InsufficientFunds => "Insufficient funds for transaction.",
+            TransactionErrorTypes::WithdrawalLimitExceeded => {
+                "Withdrawal would exceed the configured maximum total withdrawal."
+            }
             TransactionErrorTypes::FirstTransactionNotDeposit => {
                 "First transaction is not deposit."
             }
+            TransactionErrorTypes::AccountNotFound => {
+                "Dispute, resolve, chargeback, unlock, freeze, or unfreeze references an account that does not exist."
+            }
             TransactionErrorTypes::AccountLocked => {
                 "Attempted to apply transaction to locked account."
             }
-            TransactionErrorTypes::Unspecified => "Unspecified.",
+            TransactionErrorTypes::AccountFrozen => {
+                "Attempted to apply transaction to frozen account."
+            }
+            TransactionErrorTypes::CurrencyMismatch => {
+                "Transaction currency does not match the account currency."
+            }
+            TransactionErrorTypes::TooManyOpenDisputes => {
+                "Dispute would exceed the configured maximum number of open disputes."
+            }
+            TransactionErrorTypes::ClientLimitExceeded => {
+                "Transaction would create a client beyond the configured client limit."
+            }
+            TransactionErrorTypes::OpenBalanceNotFirst => {
+                "Opening balance must be the first transaction of a client with no funds."
+            }
+            TransactionErrorTypes::ReplayedTransaction => {
+                "Deposit or withdrawal has already been applied in this run."
+            }
+            TransactionErrorTypes::InvalidIdReferenced => {
+                "Dispute, resolve, or chargeback references an id with no deposit or withdrawal."
+            }
+            TransactionErrorTypes::NotDisputable => {
+                "Dispute references a type of transaction that may not be disputed."
+            }
+            TransactionErrorTypes::TransactionTypeDisabled => {
+                "Transaction type is not enabled for processing."
+            }
+            TransactionErrorTypes::InvalidDisputeAmount => {
+                "Partial dispute amount is not positive or exceeds the disputed transaction amount."
+            }
+            TransactionErrorTypes::InvariantViolation => {
+                "Account total funds no longer equal available plus held funds."
+            }
+            TransactionErrorTypes::Unspecified => {
+                "Unspecified. The referenced transaction log is in an unexpected state."
+            }
         };
         write!(
             f,
@@ -64,4 +238,48 @@ impl fmt::Display for TransactionError {
     }
 }
 
+#[cfg(feature = "std")]
 impl Error for TransactionError {}
+
+#[test]
+fn test_error_codes_are_distinct() {
+    let error_types = [
+        TransactionErrorTypes::NonPositiveAmount,
+        TransactionErrorTypes::MissingRequiredAmount,
+        TransactionErrorTypes::HasMeaninglessAmount,
+        TransactionErrorTypes::InsufficientFunds,
+        TransactionErrorTypes::FirstTransactionNotDeposit,
+        TransactionErrorTypes::AccountNotFound,
+        TransactionErrorTypes::AccountLocked,
+        TransactionErrorTypes::CurrencyMismatch,
+        TransactionErrorTypes::ClientLimitExceeded,
+        TransactionErrorTypes::ReplayedTransaction,
+        TransactionErrorTypes::NonFiniteAmount,
+        TransactionErrorTypes::AmountOutOfBounds(0.0),
+        TransactionErrorTypes::InvariantViolation,
+        TransactionErrorTypes::InvalidDisputeAmount,
+        TransactionErrorTypes::WithdrawalLimitExceeded,
+        TransactionErrorTypes::InvalidIdReferenced,
+        TransactionErrorTypes::NotDisputable,
+        TransactionErrorTypes::TransactionTypeDisabled,
+        TransactionErrorTypes::AccountFrozen,
+        TransactionErrorTypes::TooManyOpenDisputes,
+        TransactionErrorTypes::OpenBalanceNotFirst,
+        TransactionErrorTypes::Unspecified,
+    ];
+    let codes: std::collections::BTreeSet<&str> = error_types
+        .iter()
+        .map(|error_type| error_type.code())
+        .collect();
+    let code_nums: std::collections::BTreeSet<u16> = error_types
+        .iter()
+        .map(|error_type| error_type.code_num())
+        .collect();
+    assert_eq!(codes.len(), error_types.len());
+    assert_eq!(code_nums.len(), error_types.len());
+    assert_eq!(
+        TransactionErrorTypes::InsufficientFunds.code(),
+        "INSUFFICIENT_FUNDS"
+    );
+    assert_eq!(TransactionErrorTypes::InsufficientFunds.code_num(), 4);
+}
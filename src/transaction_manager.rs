@@ -1,20 +1,741 @@
-use csv::{ReaderBuilder, Trim, Writer};
-use std::collections::BTreeMap;
+use csv::{ReaderBuilder, Trim, Writer, WriterBuilder};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
-use std::io::Write;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::panic;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::client::*;
+use crate::observer::*;
+use crate::processing_config::*;
+use crate::processing_error::*;
+use crate::reporting;
 use crate::transaction::*;
 use crate::transaction_error::*;
+use crate::transaction_source::*;
+
+/// Holds the client account environment while transactions are applied one at a time, along with statistics about the run.
+#[derive(Clone, Debug)]
+pub struct TransactionManager {
+    config: ProcessingConfig,
+    clients: BTreeMap<u16, Client>,
+    stats: ProcessingStats,
+    applied: HashSet<(u16, u32, TransactionType)>,
+    input_file_paths: Vec<String>,
+    rows: usize,
+    errors: usize,
+    observers: Observers,
+}
+
+/// The observers registered with a manager, which are shared with whoever registered them.
+#[derive(Clone, Default)]
+struct Observers(Vec<Arc<Mutex<dyn LedgerObserver + Send>>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} observers", self.0.len())
+    }
+}
+
+/// Statistics gathered while transactions are applied, including transactions that were rejected.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ProcessingStats {
+    /// Number of transactions attempted for each client, including rejected ones.
+    pub per_client_attempted: BTreeMap<u16, usize>,
+
+    /// Number of transactions successfully applied for each client.
+    pub per_client_applied: BTreeMap<u16, usize>,
+
+    /// Fraction of each client's attempted transactions that were successfully applied.
+    pub per_client_success_rate: BTreeMap<u16, f64>,
+}
+
+/// A summary of the volume handled by a processing run, for monitoring and sanity checks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessingSummary {
+    /// Number of transaction rows read, including rejected rows and skipped replays.
+    pub rows_read: usize,
+
+    /// Number of transactions successfully applied.
+    pub transactions_applied: usize,
+
+    /// Number of distinct clients that any transaction was attempted on.
+    pub clients_touched: usize,
+
+    /// Number of transactions rejected. This is only non-zero when the config skips invalid transactions.
+    pub errors: usize,
+}
+
+/// A row of an opening-balance seed file. Columns match the account output, and the total column is ignored if present.
+#[derive(Debug, Deserialize)]
+struct SeedRow {
+    #[serde(rename = "client")]
+    client_id: u16,
+    available: f64,
+    held: f64,
+    locked: bool,
+}
+
+/// A client account with every field, including those the account csv omits, for saving and restoring manager state.
+/// Saving borrows the client's transaction log and dispute ages rather than copying them; loading owns what it reads.
+#[derive(Deserialize, Serialize)]
+struct SavedClient<'a> {
+    id: u16,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    currency: Option<Cow<'a, str>>,
+    transactions: Cow<'a, TransactionLog>,
+    withdrawn_total: f64,
+    dispute_ages: Cow<'a, BTreeMap<u32, usize>>,
+    #[serde(default)]
+    open_dispute_count: usize,
+    #[serde(default)]
+    locked_by: Option<u32>,
+    #[serde(default)]
+    first_overdraft_tx: Option<u32>,
+    #[serde(default)]
+    frozen: bool,
+}
+
+/// Everything a manager has accumulated from the transactions applied so far. The config is not included.
+/// As with SavedClient, saving borrows from the manager and loading owns what it reads.
+#[derive(Deserialize, Serialize)]
+struct ManagerSnapshot<'a> {
+    clients: Vec<SavedClient<'a>>,
+    stats: Cow<'a, ProcessingStats>,
+    applied: Cow<'a, HashSet<(u16, u32, TransactionType)>>,
+    input_file_paths: Cow<'a, [String]>,
+    rows: usize,
+    /// Snapshots saved before the error count was included have none, and it is worked out from the statistics instead.
+    #[serde(default)]
+    errors: Option<usize>,
+}
+
+impl ProcessingStats {
+    /// Add the counts of another run's statistics to these, recomputing the success rate of any client both runs attempted.
+    fn merge(&mut self, other: ProcessingStats) {
+        for (client_id, attempted) in other.per_client_attempted {
+            *self.per_client_attempted.entry(client_id).or_insert(0) += attempted;
+        }
+        for (client_id, applied) in other.per_client_applied {
+            *self.per_client_applied.entry(client_id).or_insert(0) += applied;
+        }
+        for (client_id, attempted) in &self.per_client_attempted {
+            let applied = self.per_client_applied.get(client_id).copied().unwrap_or(0);
+            self.per_client_success_rate
+                .insert(*client_id, applied as f64 / *attempted as f64);
+        }
+    }
+
+    /// Record an attempted transaction for the given client, and whether it was applied.
+    fn record(&mut self, client_id: u16, applied: bool) {
+        let attempted = self.per_client_attempted.entry(client_id).or_insert(0);
+        *attempted += 1;
+        let attempted = *attempted;
+        let applied_count = self.per_client_applied.entry(client_id).or_insert(0);
+        if applied {
+            *applied_count += 1;
+        }
+        let success_rate = *applied_count as f64 / attempted as f64;
+        self.per_client_success_rate.insert(client_id, success_rate);
+    }
+}
+
+impl TransactionManager {
+    /// Create a manager with no clients that applies transactions according to the given config.
+    pub fn new(config: ProcessingConfig) -> TransactionManager {
+        TransactionManager {
+            config,
+            clients: BTreeMap::new(),
+            stats: ProcessingStats::default(),
+            applied: HashSet::new(),
+            input_file_paths: Vec::new(),
+            rows: 0,
+            errors: 0,
+            observers: Observers::default(),
+        }
+    }
+
+    /// Register an observer to be notified after each transaction is successfully applied.
+    /// The caller can keep its own handle to the observer to inspect it later.
+    /// Observers are not included in snapshots, and must be registered again on a manager loaded from one.
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use transaction_manager::observer::*;
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.add_observer(Arc::new(Mutex::new(NoopObserver)));
+    /// ```
+    pub fn add_observer(&mut self, observer: Arc<Mutex<dyn LedgerObserver + Send>>) {
+        self.observers.0.push(observer);
+    }
+
+    /// Attempt to apply the given transaction to the client account environment, recording the attempt in the statistics.
+    /// May produce a TransactionError if any rules are violated, unless the config skips invalid transactions,
+    /// in which case the rejected transaction is only recorded.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig {
+    ///     skip_invalid_transactions: true,
+    ///     ..Default::default()
+    /// });
+    /// manager.apply_transaction(Transaction {
+    ///     transaction_type: TransactionType::Withdrawal,
+    ///     client_id: 0,
+    ///     id: 0,
+    ///     amount: Some(1.2),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(manager.stats().per_client_success_rate[&0], 0.0);
+    /// ```
+    pub fn apply_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        match self.try_apply_transaction(transaction) {
+            Err(_) if self.config.skip_invalid_transactions => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Work out what applying the given transaction would do to its client right now, without changing the manager,
+    /// e.g. to answer what would happen if a client withdrew some amount against state restored with load_snapshot.
+    /// The client is copied and the transaction applied to the copy with the manager's config, returning the resulting
+    /// account state, or the error applying it would produce, whether or not the config skips invalid transactions.
+    /// A transaction for a client with no account is simulated as that client's first transaction.
+    /// The client limit and replay policy are not checked, and neither the statistics nor observers see the simulation.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.process_file("resources/transaction-list.csv").unwrap();
+    /// let state = manager.simulate(Transaction {
+    ///     transaction_type: TransactionType::Withdrawal,
+    ///     client_id: 3,
+    ///     id: 100,
+    ///     amount: Some(1.5),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(state.available, 2.5);
+    /// ```
+    pub fn simulate(&self, transaction: Transaction) -> Result<AccountState, TransactionError> {
+        let simulated = match self.clients.get(&transaction.client_id) {
+            Some(client) => client
+                .clone()
+                .apply_transaction_with_config(transaction, &self.config),
+            None => initialize_client_with_config(transaction, &self.config),
+        };
+        simulated.map(|client| client.state())
+    }
+
+    /// Attempt to apply the given transaction, recording the attempt in the statistics, and return any rejection
+    /// regardless of whether the config skips invalid transactions.
+    fn try_apply_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        self.rows += 1;
+        let client_id = transaction.client_id;
+        // Replays are only looked for, and applied transactions only remembered, if the replay policy does not just apply them.
+        let replay_key = match (&self.config.replay_policy, &transaction.transaction_type) {
+            (ReplayPolicy::Apply, _) => None,
+            (_, TransactionType::Deposit | TransactionType::Withdrawal) => Some((
+                client_id,
+                transaction.id,
+                transaction.transaction_type.clone(),
+            )),
+            _ => None,
+        };
+        let replayed = match &replay_key {
+            Some(key) => self.applied.contains(key),
+            None => false,
+        };
+        let result = match (&self.config.replay_policy, replayed) {
+            (ReplayPolicy::Skip, true) => {
+                debug!(
+                    "skipped replay of tx {} for client {}",
+                    transaction.id, client_id
+                );
+                return Ok(());
+            }
+            (ReplayPolicy::Error, true) => Err(TransactionError {
+                error_type: TransactionErrorTypes::ReplayedTransaction,
+                client: self
+                    .clients
+                    .get(&client_id)
+                    .map(Client::state)
+                    .unwrap_or_else(|| Client::new(client_id).state()),
+                transaction: Box::new(transaction),
+            }),
+            _ if self.observers.0.is_empty() => {
+                update_client(&mut self.clients, transaction, &self.config)
+            }
+            _ => {
+                let previous = self.clients.get(&client_id).map(ObservedState::from);
+                let applied_transaction = transaction.clone();
+                let result = update_client(&mut self.clients, transaction, &self.config);
+                if result.is_ok() {
+                    self.notify_observers(previous, &applied_transaction);
+                }
+                result
+            }
+        };
+        if let (Ok(()), Some(key)) = (&result, replay_key) {
+            self.applied.insert(key);
+        }
+        self.stats.record(client_id, result.is_ok());
+        if result.is_err() {
+            self.errors += 1;
+        }
+        result
+    }
+
+    /// Send the events caused by the given applied transaction to every registered observer.
+    fn notify_observers(&self, previous: Option<ObservedState>, transaction: &Transaction) {
+        if let Some(client) = self.clients.get(&transaction.client_id) {
+            for observer in &self.observers.0 {
+                let mut observer = observer
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                notify_observer(&mut *observer, previous, client, transaction);
+            }
+        }
+    }
+
+    /// Read every transaction from the given csv file path and apply it to the client account environment.
+    /// May produce an error if reading fails, or if there is any invalid transaction that the config does not skip.
+    pub fn process_file(&mut self, transactions_file_path: &str) -> Result<(), Box<dyn Error>> {
+        self.input_file_paths
+            .push(transactions_file_path.to_string());
+        self.process_source(CsvSource::open(transactions_file_path, &self.config)?)
+    }
+
+    /// Read every transaction from the given source and apply it to the client account environment.
+    /// May produce an error if reading fails, or if there is any invalid transaction that the config does not skip.
+    /// Produces a TooManyErrors error if skipped transactions exceed the config's max_errors or max_error_rate.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// use transaction_manager::transaction_source::*;
+    /// let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 2.0}"#;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.process_source(JsonLinesSource::new(input.as_bytes())).unwrap();
+    /// assert_eq!(manager.summary().transactions_applied, 1);
+    /// ```
+    pub fn process_source<S>(&mut self, mut source: S) -> Result<(), Box<dyn Error>>
+    where
+        S: TransactionSource,
+    {
+        while let Some(next_transaction_result) = source.next() {
+            let transaction = ingest_transaction(next_transaction_result?, &self.config);
+            self.apply_transaction(transaction)?;
+            self.check_error_count()?;
+        }
+        self.check_error_rate()?;
+        Ok(())
+    }
+
+    /// Produce a TooManyErrors error if more transactions have been rejected than the config's max_errors allows.
+    fn check_error_count(&self) -> Result<(), ProcessingError> {
+        match self.config.max_errors {
+            Some(max_errors) if self.errors > max_errors => {
+                Err(ProcessingError::TooManyErrors(self.errors, self.rows))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Produce a TooManyErrors error if a larger fraction of rows has been rejected than the config's max_error_rate allows.
+    fn check_error_rate(&self) -> Result<(), ProcessingError> {
+        match self.config.max_error_rate {
+            Some(max_error_rate)
+                if self.rows > 0 && self.errors as f64 / self.rows as f64 > max_error_rate =>
+            {
+                Err(ProcessingError::TooManyErrors(self.errors, self.rows))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read every transaction from the given csv file path and apply it to the client account environment,
+    /// calling `on_error` with each rejected transaction and continuing with the next row.
+    /// May produce an error if reading fails, or a TooManyErrors error if rejections exceed the config's max_errors or max_error_rate.
+    pub fn process_file_with_callback<F>(
+        &mut self,
+        transactions_file_path: &str,
+        mut on_error: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&TransactionError),
+    {
+        self.input_file_paths
+            .push(transactions_file_path.to_string());
+        let mut source = CsvSource::open(transactions_file_path, &self.config)?;
+        while let Some(next_transaction_result) = source.next() {
+            let transaction = ingest_transaction(next_transaction_result?, &self.config);
+            if let Err(error) = self.try_apply_transaction(transaction) {
+                on_error(&error);
+            }
+            self.check_error_count()?;
+        }
+        self.check_error_rate()?;
+        Ok(())
+    }
+
+    /// Read opening balances from the given csv file path, creating an account for each client before any transactions are applied.
+    /// The file has the same columns as the account output: client, available, held, total, and locked.
+    /// A row for a client that already has an account is handled according to the config's seed duplicate policy.
+    /// May produce an error if reading fails, or a DuplicateSeedClient error if the policy rejects duplicates.
+    pub fn seed_file(&mut self, seed_file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .delimiter(self.config.delimiter)
+            .quote(self.config.quote)
+            .from_path(seed_file_path)?;
+        for next_row_result in reader.deserialize() {
+            let row: SeedRow = next_row_result?;
+            let client = match self.clients.get(&row.client_id) {
+                None => Client::new(row.client_id),
+                Some(client) => match self.config.seed_duplicate_policy {
+                    SeedDuplicatePolicy::Error => {
+                        return Err(Box::new(ProcessingError::DuplicateSeedClient(
+                            row.client_id,
+                        )))
+                    }
+                    SeedDuplicatePolicy::Merge => client.clone(),
+                },
+            };
+            let available = self
+                .config
+                .rounding_mode
+                .round(client.available + row.available);
+            let held = self.config.rounding_mode.round(client.held + row.held);
+            self.clients.insert(
+                row.client_id,
+                Client {
+                    available,
+                    held,
+                    total: self.config.rounding_mode.round(available + held),
+                    locked: client.locked || row.locked,
+                    ..client
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Summarize the volume of transactions handled so far.
+    pub fn summary(&self) -> ProcessingSummary {
+        let transactions_applied: usize = self.stats.per_client_applied.values().sum();
+        ProcessingSummary {
+            rows_read: self.rows,
+            transactions_applied,
+            clients_touched: self.stats.per_client_attempted.len(),
+            errors: self.errors,
+        }
+    }
+
+    /// The statistics gathered from every transaction attempted so far.
+    pub fn stats(&self) -> &ProcessingStats {
+        &self.stats
+    }
+
+    /// Serialize the client account environment in the config's output format and sort order, and write it to the given writer.
+    /// If the config asks for a metadata header, a comment line describing the run is written before the csv header.
+    /// Audit output is never given a metadata header, so that every line remains a JSON document.
+    /// May produce an error if there is a problem serializing the data or writing.
+    pub fn write_accounts<W>(&self, mut writer: W) -> Result<(), Box<dyn Error>>
+    where
+        W: Write,
+    {
+        if self.config.metadata_header && self.config.output_format == OutputFormat::Csv {
+            writeln!(writer, "{}", self.metadata_comment())?;
+        }
+        write_clients(writer, &self.clients, &self.config)
+    }
+
+    /// Compare the accounts computed so far against the expected account csv file at the given path, such as a previous run's output
+    /// kept as a golden file. The file is read with the config's delimiter, quote, and number format.
+    /// Accounts the config suppresses from the output are left out, so they must be absent from the file too.
+    /// Returns a difference for every client that is missing from either side or has any differing field, in client order, with the
+    /// expected account first. See reporting::account_differences.
+    /// May produce an error if the expected file cannot be read.
+    pub fn verify_accounts(
+        &self,
+        expected_file_path: &str,
+    ) -> Result<Vec<reporting::AccountDifference>, Box<dyn Error>> {
+        let expected =
+            reporting::account_states(reporting::read_accounts(expected_file_path, &self.config)?);
+        let computed = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| (*client_id, client.state()))
+            .filter(|(_, state)| !is_suppressed(state, &self.config))
+            .collect();
+        Ok(reporting::account_differences(&expected, &computed))
+    }
+
+    /// Write only the accounts that changed since the baseline account csv file at the given path, such as the previous run's output,
+    /// in csv format using the config's sort order, delimiter, and quote. New clients are written, and baseline clients
+    /// that this run has no account for are not. The baseline is read with the config's delimiter, quote, and number format,
+    /// so a file written by an earlier run with the same config reads back. See reporting::changed_accounts.
+    /// The output is csv whatever the config's output format, and a metadata header is written if the config asks for one.
+    /// May produce an error if the baseline cannot be read, or there is a problem serializing the data or writing.
+    pub fn write_changed_accounts<W>(
+        &self,
+        mut writer: W,
+        baseline_file_path: &str,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        W: Write,
+    {
+        let baseline = reporting::read_accounts(baseline_file_path, &self.config)?;
+        if self.config.metadata_header {
+            writeln!(writer, "{}", self.metadata_comment())?;
+        }
+        let account_states = reporting::changed_accounts(&baseline, &self.clients)
+            .into_iter()
+            .map(Client::state);
+        write_account_states(writer, account_states, &self.config)
+    }
+
+    /// Write system-wide totals across the client account environment to the given writer, as a csv header and a single row,
+    /// using the config's delimiter and quote.
+    /// May produce an error if there is a problem serializing the data or writing.
+    pub fn write_summary<W>(&self, writer: W) -> Result<(), Box<dyn Error>>
+    where
+        W: Write,
+    {
+        reporting::write_summary(writer, &self.clients, &self.config)
+    }
+
+    /// Serialize the manager's state as JSON and write it to the given writer, so processing can be resumed later with load_snapshot.
+    /// The snapshot holds every client account with its full transaction log, along with the statistics and replay history, but not the config.
+    /// Replay history is only kept while the config's replay policy skips or rejects replays, so a snapshot taken while replays are applied has none.
+    /// May produce an error if there is a problem serializing the data or writing.
+    pub fn save_snapshot<W>(&self, writer: W) -> Result<(), Box<dyn Error>>
+    where
+        W: Write,
+    {
+        let snapshot = ManagerSnapshot {
+            clients: self
+                .clients
+                .values()
+                .map(|client| SavedClient {
+                    id: client.id,
+                    available: client.available,
+                    held: client.held,
+                    total: client.total,
+                    locked: client.locked,
+                    currency: client.currency.as_deref().map(Cow::Borrowed),
+                    transactions: Cow::Borrowed(&client.transactions),
+                    withdrawn_total: client.withdrawn_total,
+                    dispute_ages: Cow::Borrowed(&client.dispute_ages),
+                    open_dispute_count: client.open_dispute_count,
+                    locked_by: client.locked_by,
+                    first_overdraft_tx: client.first_overdraft_tx,
+                    frozen: client.frozen,
+                })
+                .collect(),
+            stats: Cow::Borrowed(&self.stats),
+            applied: Cow::Borrowed(&self.applied),
+            input_file_paths: Cow::Borrowed(&self.input_file_paths),
+            rows: self.rows,
+            errors: Some(self.errors),
+        };
+        serde_json::to_writer(writer, &snapshot)?;
+        Ok(())
+    }
+
+    /// Read a snapshot written by save_snapshot and create a manager that continues from it, applying transactions according to the given config.
+    /// May produce an error if reading fails or the snapshot is invalid, or an InvalidSnapshot error if a snapshot without an error count
+    /// has more transactions applied than attempted.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.process_file("resources/transaction-list.csv").unwrap();
+    /// let mut snapshot = Vec::new();
+    /// manager.save_snapshot(&mut snapshot).unwrap();
+    /// let restored = TransactionManager::load_snapshot(snapshot.as_slice(), ProcessingConfig::default()).unwrap();
+    /// assert_eq!(restored.summary(), manager.summary());
+    /// ```
+    pub fn load_snapshot<R>(
+        reader: R,
+        config: ProcessingConfig,
+    ) -> Result<TransactionManager, Box<dyn Error>>
+    where
+        R: Read,
+    {
+        let snapshot: ManagerSnapshot = serde_json::from_reader(reader)?;
+        let clients = snapshot
+            .clients
+            .into_iter()
+            .map(|client| {
+                (
+                    client.id,
+                    Client {
+                        id: client.id,
+                        available: client.available,
+                        held: client.held,
+                        total: client.total,
+                        locked: client.locked,
+                        currency: client.currency.map(Cow::into_owned),
+                        dispute_groups: dispute_groups(&client.transactions),
+                        transactions: client.transactions.into_owned(),
+                        withdrawn_total: client.withdrawn_total,
+                        dispute_ages: client.dispute_ages.into_owned(),
+                        open_dispute_count: client.open_dispute_count,
+                        locked_by: client.locked_by,
+                        first_overdraft_tx: client.first_overdraft_tx,
+                        frozen: client.frozen,
+                    },
+                )
+            })
+            .collect();
+        let errors = match snapshot.errors {
+            Some(errors) => errors,
+            None => {
+                let attempted: usize = snapshot.stats.per_client_attempted.values().sum();
+                let applied: usize = snapshot.stats.per_client_applied.values().sum();
+                attempted.checked_sub(applied).ok_or_else(|| {
+                    ProcessingError::InvalidSnapshot(String::from(
+                        "more transactions applied than attempted",
+                    ))
+                })?
+            }
+        };
+        Ok(TransactionManager {
+            config,
+            clients,
+            errors,
+            stats: snapshot.stats.into_owned(),
+            applied: snapshot.applied.into_owned(),
+            input_file_paths: snapshot.input_file_paths.into_owned(),
+            rows: snapshot.rows,
+            observers: Observers::default(),
+        })
+    }
+
+    /// Combine this manager with another that processed a disjoint set of clients, such as another shard of the same input,
+    /// into one manager holding both sets of accounts. Statistics, applied transactions, input file paths, row counts,
+    /// and observers are combined, and this manager's config is kept.
+    /// Produces an OverlappingMergeClient error if both managers have an account for the same client, since the two
+    /// sets of transactions for that client would have to be reapplied in order to get its state.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut first = TransactionManager::new(ProcessingConfig::default());
+    /// first.process_file("resources/transaction-list.csv").unwrap();
+    /// let second = TransactionManager::new(ProcessingConfig::default());
+    /// let merged = first.merge(second).unwrap();
+    /// assert_eq!(merged.clients().count(), 4);
+    /// ```
+    pub fn merge(
+        mut self,
+        other: TransactionManager,
+    ) -> Result<TransactionManager, ProcessingError> {
+        if let Some(client_id) = other
+            .clients
+            .keys()
+            .find(|client_id| self.clients.contains_key(client_id))
+        {
+            return Err(ProcessingError::OverlappingMergeClient(*client_id));
+        }
+        self.clients.extend(other.clients);
+        self.stats.merge(other.stats);
+        self.applied.extend(other.applied);
+        self.input_file_paths.extend(other.input_file_paths);
+        self.rows += other.rows;
+        self.errors += other.errors;
+        self.observers.0.extend(other.observers.0);
+        Ok(self)
+    }
+
+    /// Every client account, with its transaction log, in ascending client id order.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.process_file("resources/transaction-list.csv").unwrap();
+    /// let ids: Vec<u16> = manager.clients().map(|client| client.id).collect();
+    /// assert_eq!(ids, vec![1, 2, 3, 4]);
+    /// let locked = manager.clients().filter(|client| client.is_locked()).count();
+    /// assert_eq!(locked, 1);
+    /// ```
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    /// Consume the manager, taking ownership of every client account, with its transaction log, in ascending client id order.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.process_file("resources/transaction-list.csv").unwrap();
+    /// let clients = manager.into_clients();
+    /// assert_eq!(clients.len(), 4);
+    /// for client in clients {
+    ///     assert_eq!(client.total, client.available + client.held);
+    /// }
+    /// ```
+    pub fn into_clients(self) -> Vec<Client> {
+        self.clients.into_values().collect()
+    }
+
+    /// Consume the manager, producing only the balances and status of each client account in ascending client id order.
+    /// Each client's transaction log is dropped as its account is produced, so writing the accounts with write_account_states
+    /// in client id order frees every log once its row is written.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_manager::*;
+    /// let mut manager = TransactionManager::new(ProcessingConfig::default());
+    /// manager.process_file("resources/transaction-list.csv").unwrap();
+    /// let account_states: Vec<_> = manager.into_account_states().collect();
+    /// assert_eq!(account_states.len(), 4);
+    /// assert_eq!(account_states[3].locked, true);
+    /// ```
+    pub fn into_account_states(self) -> impl Iterator<Item = AccountState> {
+        self.clients
+            .into_values()
+            .map(|client| client.into_parts().0)
+    }
+
+    /// A comment line describing the run: when it was written, which files were read, how many rows were applied or rejected,
+    /// and the version of this crate, e.g. "# processed-at=1700000000 input=transactions.csv rows=11 version=0.1.0".
+    fn metadata_comment(&self) -> String {
+        let processed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        format!(
+            "# processed-at={} input={} rows={} version={}",
+            processed_at,
+            self.input_file_paths.join(","),
+            self.rows,
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+}
 
 /// Reads from the given transaction csv file path, applying each transaction one at a time to the client account environment.
 /// Once all transactions have been processed, the client account environment is serialized and written to stdout.
+/// Returns a summary of how many rows, transactions, and clients were processed.
 /// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
 /// ```
 /// use transaction_manager::transaction_manager::*;
 ///
 /// let mut output = Vec::new();
-/// process_transactions(&mut output, "resources/transaction-list.csv").unwrap();
+/// let summary = process_transactions(&mut output, "resources/transaction-list.csv").unwrap();
+/// assert_eq!(summary.clients_touched, 4);
 /// let output = String::from_utf8(output).expect("Not UTF-8");
 ///
 /// assert_eq!(output, "\
@@ -28,61 +749,728 @@ use crate::transaction_error::*;
 pub fn process_transactions<W>(
     writer: W,
     transactions_file_path: &str,
-) -> Result<(), Box<dyn Error>>
+) -> Result<ProcessingSummary, Box<dyn Error>>
 where
     W: Write,
 {
-    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
-    let reader = ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_path(transactions_file_path);
-    for next_transaction_result in reader?.deserialize() {
-        let transaction: Transaction = next_transaction_result?;
-        update_client(&mut clients, transaction)?;
-    }
-    write_accounts(writer, clients)?;
+    process_transactions_with_config(writer, transactions_file_path, &ProcessingConfig::default())
+}
+
+/// Reads from the given transaction csv file path, applying each transaction according to the given config.
+/// Once all transactions have been processed, the client account environment is serialized and written to the given writer.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+pub fn process_transactions_with_config<W>(
+    writer: W,
+    transactions_file_path: &str,
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+{
+    let mut manager = TransactionManager::new(config.clone());
+    manager.process_file(transactions_file_path)?;
+    manager.write_accounts(writer)?;
+    Ok(manager.summary())
+}
+
+/// Process the given transaction csv file in memory and compare the resulting accounts against the expected account csv file,
+/// to check that a produced account file is reproducible, such as a golden file in CI. Returns every account that differs,
+/// with the expected account first, so an empty result means the output matches.
+/// May produce an error if either file cannot be read, or if there is any invalid transaction.
+/// ```
+/// use transaction_manager::transaction_manager::*;
+/// let differences = verify("resources/transaction-list.csv", "resources/expected-account-list.csv").unwrap();
+/// assert!(differences.is_empty());
+/// ```
+pub fn verify(
+    transactions_file_path: &str,
+    expected_file_path: &str,
+) -> Result<Vec<reporting::AccountDifference>, Box<dyn Error>> {
+    verify_with_config(
+        transactions_file_path,
+        expected_file_path,
+        &ProcessingConfig::default(),
+    )
+}
+
+/// Process the given transaction csv file in memory according to the given config, which should be the config the expected
+/// account csv file was produced with, and compare the resulting accounts against it. See verify.
+pub fn verify_with_config(
+    transactions_file_path: &str,
+    expected_file_path: &str,
+    config: &ProcessingConfig,
+) -> Result<Vec<reporting::AccountDifference>, Box<dyn Error>> {
+    let mut manager = TransactionManager::new(config.clone());
+    manager.process_file(transactions_file_path)?;
+    manager.verify_accounts(expected_file_path)
+}
+
+/// Reads from the given transaction csv file path, applying each transaction according to the given config.
+/// Each rejected transaction is passed to `on_error` as soon as it is found, and processing continues with the next row,
+/// so callers can stream rejections to a dead-letter file without buffering them.
+/// Once all transactions have been processed, the client account environment is serialized and written to the given writer.
+/// May produce an error if reading, serializing, or writing fails.
+/// ```
+/// use transaction_manager::processing_config::*;
+/// use transaction_manager::transaction_manager::*;
+///
+/// let mut rejected = 0;
+/// process_transactions_with_callback(
+///     std::io::sink(),
+///     "resources/transaction-list.csv",
+///     &ProcessingConfig::default(),
+///     |_| rejected += 1,
+/// ).unwrap();
+/// assert_eq!(rejected, 0);
+/// ```
+pub fn process_transactions_with_callback<W, F>(
+    writer: W,
+    transactions_file_path: &str,
+    config: &ProcessingConfig,
+    on_error: F,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+    F: FnMut(&TransactionError),
+{
+    let mut manager = TransactionManager::new(config.clone());
+    manager.process_file_with_callback(transactions_file_path, on_error)?;
+    manager.write_accounts(writer)?;
+    Ok(manager.summary())
+}
+
+/// Reads every transaction from the given source, applying each one according to the given config.
+/// Once all transactions have been processed, the client account environment is serialized and written to the given writer.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+pub fn process_transactions_from_source<W, S>(
+    writer: W,
+    source: S,
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+    S: TransactionSource,
+{
+    let mut manager = TransactionManager::new(config.clone());
+    manager.process_source(source)?;
+    manager.write_accounts(writer)?;
+    Ok(manager.summary())
+}
+
+/// Reads transactions in csv format from the given reader, applying each transaction according to the given config.
+/// Once all transactions have been processed, the client account environment is serialized and written to the given writer.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+/// ```
+/// use transaction_manager::processing_config::*;
+/// use transaction_manager::transaction_manager::*;
+///
+/// let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+/// let mut output = Vec::new();
+/// process_transactions_from_reader(&mut output, input.as_bytes(), &ProcessingConfig::default()).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n");
+/// ```
+pub fn process_transactions_from_reader<W, R>(
+    writer: W,
+    reader: R,
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+    R: Read,
+{
+    process_transactions_from_source(writer, CsvSource::new(reader, config), config)
+}
+
+/// Reads transactions from the given csv text with the default config, and returns the account csv as text.
+/// This is the string-in, string-out form used by the `wasm` feature's `process_csv_string`.
+/// May produce an error if reading or serializing fails, or if there is any invalid transaction.
+pub fn process_csv_str(input: &str) -> Result<String, Box<dyn Error>> {
+    let mut output = Vec::new();
+    process_transactions_from_reader(&mut output, input.as_bytes(), &ProcessingConfig::default())?;
+    Ok(String::from_utf8(output)?)
+}
+
+/// Reads transactions in csv format from the given async reader, parsing and applying each one as soon as it arrives,
+/// according to the given config. Once the reader is exhausted, the client account environment is written to the given async writer.
+/// Rows are parsed and transactions applied with the same synchronous logic as process_transactions, so the config's header,
+/// extra column, and lenient amount settings and type aliases are used as they are by CsvSource. Requires the `async` feature.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+#[cfg(feature = "async")]
+pub async fn process_transactions_async<W, R>(
+    mut writer: W,
+    reader: R,
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+    R: tokio::io::AsyncRead + Unpin + Send,
+{
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let mut reader = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .has_headers(config.has_headers)
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .create_reader(reader);
+    let mut parser = CsvRowParser::new(config);
+    if config.has_headers {
+        parser.read_headers(reader.headers().await?.iter().collect())?;
+    }
+    let mut records = reader.records();
+    let mut manager = TransactionManager::new(config.clone());
+    while let Some(record) = records.next().await {
+        let transaction = parser.parse(&record?.iter().collect())?;
+        manager.apply_transaction(ingest_transaction(transaction, config))?;
+    }
+    let mut output = Vec::new();
+    manager.write_accounts(&mut output)?;
+    writer.write_all(&output).await?;
+    writer.flush().await?;
+    Ok(manager.summary())
+}
+
+/// Reads from the given transaction csv file path, applying each transaction according to the given config,
+/// then writes the client account environment to the file at the given output path.
+/// The input is fully processed before the output file is created, and the config's same path policy decides
+/// whether an output path that refers to the input file is an error or overwrites it.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+pub fn process_transactions_to_file(
+    output_file_path: &str,
+    transactions_file_path: &str,
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>> {
+    check_output_path(output_file_path, transactions_file_path, config)?;
+    let mut manager = TransactionManager::new(config.clone());
+    manager.process_file(transactions_file_path)?;
+    manager.write_accounts(File::create(output_file_path)?)?;
+    Ok(manager.summary())
+}
+
+/// Check that the given output path may be written after reading the given transaction file path.
+/// Produces a SameInputAndOutput error if both refer to the same file and the config's same path policy is Error.
+pub fn check_output_path(
+    output_file_path: &str,
+    transactions_file_path: &str,
+    config: &ProcessingConfig,
+) -> Result<(), ProcessingError> {
+    if config.same_path_policy == SamePathPolicy::Error
+        && is_same_file(output_file_path, transactions_file_path)
+    {
+        return Err(ProcessingError::SameInputAndOutput(
+            output_file_path.to_string(),
+        ));
+    }
     Ok(())
 }
 
-/// Attempt to apply the given transaction to the given client account environment.
+/// Returns true if both paths exist and resolve to the same file.
+fn is_same_file(first_path: &str, second_path: &str) -> bool {
+    match (fs::canonicalize(first_path), fs::canonicalize(second_path)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false,
+    }
+}
+
+/// Reads from the given transaction csv file path and applies each transaction according to the given config,
+/// spreading the work across the given number of worker threads.
+/// Rows are routed to workers by client id, so each worker owns a disjoint set of clients and every client's transactions
+/// are still applied in file order. The workers' clients are merged before being written to the given writer.
+/// Because each worker only sees its own clients, the config's client limit applies to each worker separately.
+/// If more than one worker encounters an invalid transaction, the error from the lowest numbered worker is returned.
+/// The config's max_errors and max_error_rate are checked against the merged counts once every worker has finished,
+/// so a TooManyErrors error reports the whole file's rejections rather than stopping at the row that crossed the limit.
+/// As with process_transactions_with_config, a metadata header is written if the config asks for one, and a summary of the run is returned.
+/// The file is still read and parsed on one thread, so only applying transactions runs in parallel;
+/// see the serial_vs_parallel benchmark before choosing a worker count.
+/// ```
+/// use transaction_manager::processing_config::*;
+/// use transaction_manager::transaction_manager::*;
+///
+/// let mut output = Vec::new();
+/// process_transactions_parallel(
+///     &mut output,
+///     "resources/transaction-list.csv",
+///     4,
+///     &ProcessingConfig::default(),
+/// ).unwrap();
+/// let output = String::from_utf8(output).expect("Not UTF-8");
+///
+/// assert_eq!(output, "\
+/// client,available,held,total,locked
+/// 1,1.0,0.0,1.0,false
+/// 2,0.0,3.3,3.3,false
+/// 3,4.0,0.0,4.0,false
+/// 4,5.0,0.0,5.0,true
+/// ");
+/// ```
+pub fn process_transactions_parallel<W>(
+    writer: W,
+    transactions_file_path: &str,
+    worker_count: usize,
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+{
+    let worker_count = worker_count.max(1);
+    let mut senders = Vec::with_capacity(worker_count);
+    let mut workers = Vec::with_capacity(worker_count);
+    for _ in 0..worker_count {
+        let (sender, receiver) = mpsc::channel::<Transaction>();
+        let config = config.clone();
+        senders.push(sender);
+        workers.push(thread::spawn(
+            move || -> Result<TransactionManager, TransactionError> {
+                let mut manager = TransactionManager::new(config);
+                for transaction in receiver {
+                    manager.apply_transaction(transaction)?;
+                }
+                Ok(manager)
+            },
+        ));
+    }
+
+    let mut source = CsvSource::open(transactions_file_path, config)?;
+    while let Some(next_transaction_result) = source.next() {
+        let transaction = ingest_transaction(next_transaction_result?, config);
+        let worker_index = transaction.client_id as usize % worker_count;
+        if senders[worker_index].send(transaction).is_err() {
+            // The worker has stopped because of an invalid transaction, which is reported when it is joined below.
+            break;
+        }
+    }
+    drop(senders);
+
+    let mut manager = TransactionManager::new(config.clone());
+    manager
+        .input_file_paths
+        .push(transactions_file_path.to_string());
+    for worker in workers {
+        let worker_manager = worker
+            .join()
+            .unwrap_or_else(|worker_panic| panic::resume_unwind(worker_panic))?;
+        // Workers own disjoint sets of clients, so their accounts and per-client statistics never overlap.
+        manager.clients.extend(worker_manager.clients);
+        let stats = worker_manager.stats;
+        manager
+            .stats
+            .per_client_attempted
+            .extend(stats.per_client_attempted);
+        manager
+            .stats
+            .per_client_applied
+            .extend(stats.per_client_applied);
+        manager
+            .stats
+            .per_client_success_rate
+            .extend(stats.per_client_success_rate);
+        manager.rows += worker_manager.rows;
+        manager.errors += worker_manager.errors;
+    }
+    manager.check_error_count()?;
+    manager.check_error_rate()?;
+    manager.write_accounts(writer)?;
+    Ok(manager.summary())
+}
+
+/// Reads every row from each of the given transaction csv file paths, merges them, and applies them to the client account environment.
+/// If every row has a timestamp, rows are applied in timestamp order, otherwise they are applied in file-then-row order.
+/// Rows with equal timestamps are applied in file-then-row order.
+/// Once all transactions have been processed, the client account environment is serialized and written to the given writer.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+/// ```
+/// use transaction_manager::transaction_manager::*;
+///
+/// let mut output = Vec::new();
+/// process_transaction_files(
+///     &mut output,
+///     &["resources/timestamped-list-a.csv", "resources/timestamped-list-b.csv"],
+/// ).unwrap();
+/// let output = String::from_utf8(output).expect("Not UTF-8");
+///
+/// assert_eq!(output, "\
+/// client,available,held,total,locked
+/// 1,0.5,0.0,0.5,false
+/// 2,0.0,2.0,2.0,false
+/// ");
+/// ```
+pub fn process_transaction_files<W>(
+    writer: W,
+    transactions_file_paths: &[&str],
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+{
+    process_transaction_files_with_config(
+        writer,
+        transactions_file_paths,
+        &ProcessingConfig::default(),
+    )
+}
+
+/// Reads every row from each of the given transaction csv file paths, merges them, and applies them according to the given config.
+/// If every row has a timestamp, rows are applied in timestamp order, otherwise they are applied in file-then-row order.
+/// Rows with equal timestamps are applied in the order of their files in the given paths, then in their order within the file,
+/// so merging the same files in the same order always produces the same result.
+/// Once all transactions have been processed, the client account environment is serialized and written to the given writer.
+/// May produce an error if reading, serializing, or writing fails, or if there is any invalid transaction.
+pub fn process_transaction_files_with_config<W>(
+    writer: W,
+    transactions_file_paths: &[&str],
+    config: &ProcessingConfig,
+) -> Result<ProcessingSummary, Box<dyn Error>>
+where
+    W: Write,
+{
+    // Each transaction is kept with its file index and row index, the tie-break for equal timestamps.
+    let mut transactions: Vec<(usize, usize, Transaction)> = Vec::new();
+    for (file_index, transactions_file_path) in transactions_file_paths.iter().enumerate() {
+        let mut source = CsvSource::open(transactions_file_path, config)?;
+        let mut row_index = 0;
+        while let Some(next_transaction_result) = source.next() {
+            let transaction = ingest_transaction(next_transaction_result?, config);
+            transactions.push((file_index, row_index, transaction));
+            row_index += 1;
+        }
+    }
+    if transactions
+        .iter()
+        .all(|(_, _, transaction)| transaction.timestamp.is_some())
+    {
+        transactions.sort_by_key(|(file_index, row_index, transaction)| {
+            (transaction.timestamp, *file_index, *row_index)
+        });
+    }
+    let mut manager = TransactionManager::new(config.clone());
+    manager.input_file_paths.extend(
+        transactions_file_paths
+            .iter()
+            .map(|transactions_file_path| transactions_file_path.to_string()),
+    );
+    for (_, _, transaction) in transactions {
+        manager.apply_transaction(transaction)?;
+        manager.check_error_count()?;
+    }
+    manager.check_error_rate()?;
+    manager.write_accounts(writer)?;
+    Ok(manager.summary())
+}
+
+/// Convert a transaction as read from input into the form it is applied in.
+/// If the config reads amounts as integer minor units, the amount is divided into decimal units.
+/// Amounts are then rounded to four decimal places with the config's rounding mode, the precision balances are kept to,
+/// so an amount written as `1.2E2` or with extra digits is logged exactly as `120.0` would be, and later comparisons
+/// against it, such as partial dispute amounts, are exact.
+fn ingest_transaction(mut transaction: Transaction, config: &ProcessingConfig) -> Transaction {
+    if let Some(minor_units) = config.amount_minor_units {
+        transaction.amount = transaction.amount.map(|amount| amount / minor_units as f64);
+    }
+    transaction.amount = transaction
+        .amount
+        .map(|amount| config.rounding_mode.round(amount));
+    transaction
+}
+
+/// Attempt to apply the given transaction to the given client account environment according to the given config.
 /// May produce a TransactionError if any rules are violated.
 fn update_client(
     clients: &mut BTreeMap<u16, Client>,
     transaction: Transaction,
+    config: &ProcessingConfig,
+) -> Result<(), TransactionError> {
+    let result = try_update_client(clients, transaction, config);
+    if let Err(error) = &result {
+        warn!(
+            "rejected tx {} for client {}: {}",
+            error.transaction.id,
+            error.transaction.client_id,
+            error.error_type.code()
+        );
+    }
+    result
+}
+
+/// Apply the given transaction to the given client account environment, without logging rejections.
+fn try_update_client(
+    clients: &mut BTreeMap<u16, Client>,
+    transaction: Transaction,
+    config: &ProcessingConfig,
 ) -> Result<(), TransactionError> {
     let updated_client: Client = match clients.get(&transaction.client_id) {
-        Some(client) => client.clone().apply_transaction(transaction)?,
-        None => initialize_client(transaction)?,
+        Some(client) => client
+            .clone()
+            .apply_transaction_with_config(transaction, config)?,
+        None => {
+            if let Some(max_clients) = config.max_clients {
+                if clients.len() >= max_clients {
+                    return Err(TransactionError {
+                        error_type: TransactionErrorTypes::ClientLimitExceeded,
+                        client: Client::new(transaction.client_id).state(),
+                        transaction: Box::new(transaction),
+                    });
+                }
+            }
+            initialize_client_with_config(transaction, config)?
+        }
     };
     clients.insert(updated_client.id, updated_client);
     Ok(())
 }
 
-/// Serialize the given client account environment to csv format and write it to stdout
+/// Serialize the given client account environment in the config's output format and sort order, and write it to the given writer.
+fn write_clients<W>(
+    writer: W,
+    clients: &BTreeMap<u16, Client>,
+    config: &ProcessingConfig,
+) -> Result<(), Box<dyn Error>>
+where
+    W: Write,
+{
+    match config.output_format {
+        OutputFormat::Csv => {
+            write_account_states(writer, clients.values().map(Client::state), config)
+        }
+        OutputFormat::Audit => write_audit(writer, clients, config, false),
+        OutputFormat::PrettyAudit => write_audit(writer, clients, config, true),
+    }
+}
+
+/// Serialize each client produced by the given iterator to csv format, using the config's delimiter and quote, and write it to the given writer.
+/// Clients are written as soon as they are produced and then dropped, so an iterator that finalizes accounts lazily never needs to hold them all.
+/// Clients are written in the order the iterator produces them, and the config's sort order is not applied,
+/// so sorted output depends on the caller producing clients in sorted order.
+/// May produce an error if there is a problem serializing the data or writing.
+/// ```
+/// use transaction_manager::client::*;
+/// use transaction_manager::processing_config::*;
+/// use transaction_manager::transaction_manager::*;
+/// let mut output = Vec::new();
+/// write_accounts(&mut output, vec![Client::new(2), Client::new(1)], &ProcessingConfig::default()).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "\
+/// client,available,held,total,locked
+/// 2,0.0,0.0,0.0,false
+/// 1,0.0,0.0,0.0,false
+/// ");
+/// ```
+pub fn write_accounts<W, I>(
+    writer: W,
+    clients: I,
+    config: &ProcessingConfig,
+) -> Result<(), Box<dyn Error>>
+where
+    W: Write,
+    I: IntoIterator<Item = Client>,
+{
+    let mut writer = WriterBuilder::new()
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .from_writer(writer);
+    let account_states = clients
+        .into_iter()
+        .map(|client| client.state())
+        .filter(|account_state| !is_suppressed(account_state, config));
+    write_account_rows(&mut writer, account_states, config)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serialize the given account balances and statuses, which must be in ascending client id order, to csv format,
+/// using the config's sort order, delimiter, and quote, and write them to the given writer.
+/// No transaction logs are needed, so accounts taken from TransactionManager::into_account_states can be written after their logs are dropped.
+/// In client id order each account is written as soon as it is produced; any other sort order collects the accounts to sort them first.
 /// May produce an error if there is a problem serializing the data or writing.
-fn write_accounts<W>(writer: W, clients: BTreeMap<u16, Client>) -> Result<(), Box<dyn Error>>
+/// ```
+/// use transaction_manager::client::*;
+/// use transaction_manager::processing_config::*;
+/// use transaction_manager::transaction_manager::*;
+/// let account_states = vec![AccountState {
+///     id: 1,
+///     available: 1.0,
+///     held: 0.0,
+///     total: 1.0,
+///     locked: false,
+/// }];
+/// let mut output = Vec::new();
+/// write_account_states(&mut output, account_states, &ProcessingConfig::default()).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "\
+/// client,available,held,total,locked
+/// 1,1.0,0.0,1.0,false
+/// ");
+/// ```
+pub fn write_account_states<W, I>(
+    writer: W,
+    account_states: I,
+    config: &ProcessingConfig,
+) -> Result<(), Box<dyn Error>>
 where
     W: Write,
+    I: IntoIterator<Item = AccountState>,
 {
-    let mut writer = Writer::from_writer(writer);
-    for (_, client) in clients {
-        writer.serialize(client)?;
+    let mut writer = WriterBuilder::new()
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .from_writer(writer);
+    let account_states = account_states
+        .into_iter()
+        .filter(|account_state| !is_suppressed(account_state, config));
+    match config.sort_order {
+        SortOrder::ClientId => write_account_rows(&mut writer, account_states, config)?,
+        _ => write_account_rows(
+            &mut writer,
+            sorted_account_states(account_states.collect(), &config.sort_order),
+            config,
+        )?,
     }
     writer.flush()?;
     Ok(())
 }
 
-#[test]
-fn test_update_client() -> () {
-    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
-    update_client(
-        &mut clients,
-        Transaction {
-            transaction_type: TransactionType::Deposit,
-            client_id: 0,
-            id: 0,
-            amount: Some(1.2),
+/// Write each of the given accounts as a csv row in the order they are produced, writing the header row directly
+/// when there are no accounts or balances are formatted. See ACCOUNT_COLUMNS.
+fn write_account_rows<W, I>(
+    writer: &mut Writer<W>,
+    account_states: I,
+    config: &ProcessingConfig,
+) -> Result<(), csv::Error>
+where
+    W: Write,
+    I: IntoIterator<Item = AccountState>,
+{
+    let mut written_any = false;
+    for account_state in account_states {
+        if !written_any && config.number_format.is_some() {
+            writer.write_record(ACCOUNT_COLUMNS)?;
+        }
+        write_account_row(writer, &account_state, config)?;
+        written_any = true;
+    }
+    if !written_any {
+        writer.write_record(ACCOUNT_COLUMNS)?;
+    }
+    Ok(())
+}
+
+/// Write one account as a csv row. Balances are rounded to four decimal places with the config's rounding mode, then serialized
+/// as numbers, or formatted with the config's number format if it has one, in which case the header row is not written by serializing
+/// and the caller writes it.
+fn write_account_row<W>(
+    writer: &mut Writer<W>,
+    account_state: &AccountState,
+    config: &ProcessingConfig,
+) -> Result<(), csv::Error>
+where
+    W: Write,
+{
+    let account_state = &account_state.rounded(&config.rounding_mode);
+    match &config.number_format {
+        None => writer.serialize(account_state),
+        Some(number_format) => writer.write_record([
+            account_state.id.to_string(),
+            number_format.format(account_state.available),
+            number_format.format(account_state.held),
+            number_format.format(account_state.total),
+            account_state.locked.to_string(),
+        ]),
+    }
+}
+
+/// The header row of the account csv output. Serializing the first account writes it, so it is only written directly
+/// when there are no accounts, or when balances are formatted instead of serialized, so that the output is always a valid account csv.
+const ACCOUNT_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Returns true if the config suppresses zero accounts and the given account is unlocked with every balance zero at four decimal places.
+fn is_suppressed(account_state: &AccountState, config: &ProcessingConfig) -> bool {
+    config.suppress_zero_accounts
+        && !account_state.locked
+        && [
+            account_state.available,
+            account_state.held,
+            account_state.total,
+        ]
+        .iter()
+        .all(|balance| round_to_four_decimals(*balance) == 0.0)
+}
+
+/// Serialize each client in the given client account environment that the config does not suppress, in the config's sort order,
+/// with its full transaction log, as a JSON document on its own line,
+/// or pretty-printed over several lines if `pretty` is true. The log is written in ascending transaction ID order, and each ID's
+/// transactions in the order they were applied, so identical input always produces byte-identical output.
+/// May produce an error if there is a problem serializing the data or writing.
+fn write_audit<W>(
+    mut writer: W,
+    clients: &BTreeMap<u16, Client>,
+    config: &ProcessingConfig,
+    pretty: bool,
+) -> Result<(), Box<dyn Error>>
+where
+    W: Write,
+{
+    let account_states = clients
+        .values()
+        .map(Client::state)
+        .filter(|account_state| !is_suppressed(account_state, config))
+        .collect();
+    for account_state in sorted_account_states(account_states, &config.sort_order) {
+        let mut audit_record = clients[&account_state.id].audit_record();
+        for balance in [
+            &mut audit_record.available,
+            &mut audit_record.held,
+            &mut audit_record.total,
+        ] {
+            *balance = config.rounding_mode.round(*balance);
+        }
+        if pretty {
+            serde_json::to_writer_pretty(&mut writer, &audit_record)?;
+        } else {
+            serde_json::to_writer(&mut writer, &audit_record)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sort the given account balances and statuses in the given sort order.
+fn sorted_account_states(
+    mut account_states: Vec<AccountState>,
+    sort_order: &SortOrder,
+) -> Vec<AccountState> {
+    // sort_by is stable, so accounts that compare equal stay in their given order, which is ascending client id order.
+    match sort_order {
+        SortOrder::ClientId => {}
+        SortOrder::TotalDescending => {
+            account_states.sort_by(|a, b| compare_funds(b.total, a.total))
+        }
+        SortOrder::AvailableDescending => {
+            account_states.sort_by(|a, b| compare_funds(b.available, a.available))
+        }
+        SortOrder::LockedFirst => {
+            account_states.sort_by_key(|state| std::cmp::Reverse(state.locked))
+        }
+    }
+    account_states
+}
+
+/// Compare two amounts of funds, treating incomparable values as equal.
+fn compare_funds(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[test]
+fn test_update_client() {
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    update_client(
+        &mut clients,
+        Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 0,
+            id: 0,
+            amount: Some(1.2),
+            ..Default::default()
         },
+        &ProcessingConfig::default(),
     )
     .unwrap();
     assert_eq!(clients.len(), 1);
@@ -91,27 +1479,861 @@ fn test_update_client() -> () {
     assert_eq!(client.available, 1.2);
     assert_eq!(client.held, 0.0);
     assert_eq!(client.total, 1.2);
-    assert_eq!(client.locked, false);
-    assert_eq!(client.locked, false);
+    assert!(!client.locked);
+    assert!(!client.locked);
     assert_eq!(client.transactions[&0].len(), 1);
 }
 
 #[test]
-fn test_write_accounts() -> () {
+fn test_update_client_leading_dispute() {
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    let error = update_client(
+        &mut clients,
+        Transaction {
+            transaction_type: TransactionType::Dispute,
+            client_id: 9,
+            id: 1,
+            amount: None,
+            ..Default::default()
+        },
+        &ProcessingConfig::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::AccountNotFound
+    ));
+    assert!(clients.is_empty());
+}
+
+#[test]
+fn test_update_client_limit() {
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    let config = ProcessingConfig {
+        max_clients: Some(2),
+        ..Default::default()
+    };
+    for client_id in 0..3 {
+        let result = update_client(
+            &mut clients,
+            Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id,
+                id: client_id as u32,
+                amount: Some(1.0),
+                ..Default::default()
+            },
+            &config,
+        );
+        if client_id < 2 {
+            result.unwrap();
+        } else {
+            assert!(matches!(
+                result.unwrap_err().error_type,
+                TransactionErrorTypes::ClientLimitExceeded
+            ));
+        }
+    }
+    assert_eq!(clients.len(), 2);
+
+    // Existing clients can still transact once the limit is reached.
+    update_client(
+        &mut clients,
+        Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            id: 3,
+            amount: Some(1.0),
+            ..Default::default()
+        },
+        &config,
+    )
+    .unwrap();
+    assert_eq!(clients[&1].total, 2.0);
+}
+
+#[test]
+fn test_per_client_success_rate() {
+    let mut manager = TransactionManager::new(ProcessingConfig {
+        skip_invalid_transactions: true,
+        ..Default::default()
+    });
+    let transactions = [
+        (TransactionType::Deposit, 0, Some(2.0)),
+        (TransactionType::Withdrawal, 1, Some(5.0)),
+        (TransactionType::Deposit, 2, Some(1.0)),
+        (TransactionType::Withdrawal, 3, Some(2.5)),
+    ];
+    for (transaction_type, id, amount) in transactions {
+        manager
+            .apply_transaction(Transaction {
+                transaction_type,
+                client_id: 4,
+                id,
+                amount,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let stats = manager.stats();
+    assert_eq!(stats.per_client_attempted[&4], 4);
+    assert_eq!(stats.per_client_applied[&4], 3);
+    assert_eq!(stats.per_client_success_rate[&4], 0.75);
+    assert_eq!(manager.clients[&4].total, 0.5);
+
+    let mut strict_manager = TransactionManager::new(ProcessingConfig::default());
+    assert!(strict_manager
+        .apply_transaction(Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            client_id: 4,
+            id: 0,
+            amount: Some(1.0),
+            ..Default::default()
+        })
+        .is_err());
+    assert_eq!(strict_manager.stats().per_client_success_rate[&4], 0.0);
+}
+
+#[test]
+fn test_replay_guard() {
+    let mut manager = TransactionManager::new(ProcessingConfig {
+        replay_policy: ReplayPolicy::Skip,
+        ..Default::default()
+    });
+    manager
+        .process_file("resources/replayable-transaction-list.csv")
+        .unwrap();
+    let single_application = manager.clients.clone();
+    manager
+        .process_file("resources/replayable-transaction-list.csv")
+        .unwrap();
+    assert_eq!(manager.clients.len(), single_application.len());
+    for (client_id, client) in &single_application {
+        assert_eq!(manager.clients[client_id].available, client.available);
+        assert_eq!(manager.clients[client_id].total, client.total);
+    }
+
+    let mut strict_manager = TransactionManager::new(ProcessingConfig {
+        replay_policy: ReplayPolicy::Error,
+        ..Default::default()
+    });
+    strict_manager
+        .process_file("resources/replayable-transaction-list.csv")
+        .unwrap();
+    let error = strict_manager
+        .process_file("resources/replayable-transaction-list.csv")
+        .unwrap_err();
+    assert!(error.to_string().contains("already been applied"));
+
+    let mut unguarded_manager = TransactionManager::new(ProcessingConfig::default());
+    unguarded_manager
+        .process_file("resources/replayable-transaction-list.csv")
+        .unwrap();
+    unguarded_manager
+        .process_file("resources/replayable-transaction-list.csv")
+        .unwrap();
+    assert_eq!(
+        unguarded_manager.clients[&1].total,
+        2.0 * single_application[&1].total
+    );
+    assert!(unguarded_manager.applied.is_empty());
+}
+
+#[test]
+fn test_seed_duplicate_policy() {
+    let mut manager = TransactionManager::new(ProcessingConfig::default());
+    let error = manager
+        .seed_file("resources/duplicate-seed-list.csv")
+        .unwrap_err();
+    assert!(error.to_string().contains("more than one row for client 1"));
+
+    let mut manager = TransactionManager::new(ProcessingConfig {
+        seed_duplicate_policy: SeedDuplicatePolicy::Merge,
+        ..Default::default()
+    });
+    manager
+        .seed_file("resources/duplicate-seed-list.csv")
+        .unwrap();
+    assert_eq!(manager.clients.len(), 2);
+    assert_eq!(manager.clients[&1].available, 3.5);
+    assert_eq!(manager.clients[&1].held, 0.5);
+    assert_eq!(manager.clients[&1].total, 4.0);
+    assert!(manager.clients[&1].locked);
+    assert_eq!(manager.clients[&2].total, 2.0);
+    assert!(!manager.clients[&2].locked);
+}
+
+#[cfg(all(test, feature = "async"))]
+#[tokio::test]
+async fn test_process_transactions_async() {
+    use tokio::io::AsyncWriteExt;
+
+    let (mut input, reader) = tokio::io::duplex(16);
+    let feeder = tokio::spawn(async move {
+        let transactions = fs::read("resources/transaction-list.csv").unwrap();
+        input.write_all(&transactions).await.unwrap();
+    });
+    let mut output = Vec::new();
+    process_transactions_async(&mut output, reader, &ProcessingConfig::default())
+        .await
+        .unwrap();
+    feeder.await.unwrap();
+
+    let mut expected = Vec::new();
+    process_transactions(&mut expected, "resources/transaction-list.csv").unwrap();
+    assert_eq!(output, expected);
+
+    // Rows are parsed like CsvSource parses them, including lenient amounts and type aliases.
+    let config = ProcessingConfig {
+        lenient_amounts: true,
+        ..Default::default()
+    };
+    let input = "type,client,tx,amount\ndep,1,1,\"$1,250.00\"\n";
+    let mut output = Vec::new();
+    process_transactions_async(&mut output, input.as_bytes(), &config)
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1250.0,0.0,1250.0,false\n"
+    );
+}
+
+#[test]
+fn test_gzipped_transaction_file() {
+    let mut plain_output = Vec::new();
+    process_transactions(&mut plain_output, "resources/transaction-list.csv").unwrap();
+    let mut gzipped_output = Vec::new();
+    process_transactions(&mut gzipped_output, "resources/transaction-list.csv.gz").unwrap();
+    assert_eq!(gzipped_output, plain_output);
+}
+
+#[test]
+fn test_processing_summary() {
+    let summary = process_transactions(std::io::sink(), "resources/transaction-list.csv").unwrap();
+    assert_eq!(
+        summary,
+        ProcessingSummary {
+            rows_read: 11,
+            transactions_applied: 11,
+            clients_touched: 4,
+            errors: 0,
+        }
+    );
+
+    let summary = process_transactions_with_config(
+        std::io::sink(),
+        "resources/timestamped-list-a.csv",
+        &ProcessingConfig {
+            skip_invalid_transactions: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        summary,
+        ProcessingSummary {
+            rows_read: 3,
+            transactions_applied: 2,
+            clients_touched: 2,
+            errors: 1,
+        }
+    );
+}
+
+#[test]
+fn test_integer_minor_unit_amounts() {
+    let mut output = Vec::new();
+    process_transactions_with_config(
+        &mut output,
+        "resources/minor-unit-transaction-list.csv",
+        &ProcessingConfig {
+            amount_minor_units: Some(100),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    assert_eq!(
+        output,
+        "\
+client,available,held,total,locked
+1,7.55,0.0,7.55,false
+2,0.0,0.99,0.99,false
+"
+    );
+}
+
+#[test]
+fn test_scientific_notation_amounts() {
+    let config = ProcessingConfig {
+        allow_partial_disputes: true,
+        ..Default::default()
+    };
+    let process = |input: &str| {
+        let mut manager = TransactionManager::new(config.clone());
+        manager
+            .process_source(CsvSource::new(input.as_bytes(), &config))
+            .unwrap();
+        manager.clients[&1].clone()
+    };
+    let scientific = process(
+        "type,client,tx,amount\ndeposit,1,1,1.2E2\ndispute,1,1,6E1\nresolve,1,1,\ndispute,1,1,\n",
+    );
+    let decimal = process(
+        "type,client,tx,amount\ndeposit,1,1,120.0\ndispute,1,1,60.0\nresolve,1,1,\ndispute,1,1,\n",
+    );
+    assert_eq!(scientific, decimal);
+    assert_eq!(scientific.transactions[&1][0].amount, Some(120.0));
+    assert_eq!(scientific.held, 120.0);
+
+    // Extra digits are rounded away at ingest, so a dispute of the logged amount matches it exactly.
+    let client =
+        process("type,client,tx,amount\ndeposit,1,1,0.30000000000000004\ndispute,1,1,0.3\n");
+    assert_eq!(client.transactions[&1][0].amount, Some(0.3));
+    assert_eq!((client.available, client.held), (0.0, 0.3));
+}
+
+#[test]
+fn test_number_format() {
+    let write = |config: ProcessingConfig| {
+        let mut manager = TransactionManager::new(config);
+        manager
+            .apply_transaction(Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                id: 1,
+                amount: Some(1234.56),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut output = Vec::new();
+        manager.write_accounts(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    let number_format = NumberFormat {
+        decimal_separator: ',',
+        grouping_separator: Some('.'),
+    };
+    assert_eq!(
+        write(ProcessingConfig {
+            number_format: Some(number_format.clone()),
+            delimiter: b';',
+            ..Default::default()
+        }),
+        "client;available;held;total;locked\n1;1.234,56;0,0;1.234,56;false\n"
+    );
+
+    // With the default delimiter, balances with a decimal comma are quoted.
+    assert_eq!(
+        write(ProcessingConfig {
+            number_format: Some(number_format),
+            ..Default::default()
+        }),
+        "client,available,held,total,locked\n1,\"1.234,56\",\"0,0\",\"1.234,56\",false\n"
+    );
+
+    // The default number format writes the same balances as serializing them.
+    assert_eq!(
+        write(ProcessingConfig {
+            number_format: Some(NumberFormat::default()),
+            ..Default::default()
+        }),
+        write(ProcessingConfig::default())
+    );
+}
+
+#[test]
+fn test_merge() {
+    let config = ProcessingConfig::default();
+    let mut transactions = Vec::new();
+    let mut source = CsvSource::open("resources/transaction-list.csv", &config).unwrap();
+    while let Some(next_transaction_result) = source.next() {
+        transactions.push(next_transaction_result.unwrap());
+    }
+
+    // Shard the transactions by client, and merging the shards matches a single pass.
+    let mut odd = TransactionManager::new(config.clone());
+    let mut even = TransactionManager::new(config.clone());
+    let mut single_pass = TransactionManager::new(config.clone());
+    for transaction in transactions {
+        let shard = if transaction.client_id % 2 == 1 {
+            &mut odd
+        } else {
+            &mut even
+        };
+        shard.apply_transaction(transaction.clone()).unwrap();
+        single_pass.apply_transaction(transaction).unwrap();
+    }
+    let merged = odd.clone().merge(even).unwrap();
+    assert_eq!(merged.clients, single_pass.clients);
+    assert_eq!(merged.summary(), single_pass.summary());
+    assert_eq!(merged.stats(), single_pass.stats());
+    let mut merged_output = Vec::new();
+    merged.write_accounts(&mut merged_output).unwrap();
+    let mut single_pass_output = Vec::new();
+    single_pass.write_accounts(&mut single_pass_output).unwrap();
+    assert_eq!(merged_output, single_pass_output);
+
+    // Managers that both have an account for a client cannot be merged.
+    assert!(matches!(
+        odd.merge(merged),
+        Err(ProcessingError::OverlappingMergeClient(1))
+    ));
+}
+
+#[test]
+fn test_verify() {
+    assert!(verify(
+        "resources/transaction-list.csv",
+        "resources/expected-account-list.csv"
+    )
+    .unwrap()
+    .is_empty());
+
+    let differences = verify(
+        "resources/transaction-list.csv",
+        "resources/mismatched-account-list.csv",
+    )
+    .unwrap();
+    let descriptions: Vec<String> = differences
+        .iter()
+        .map(|difference| difference.to_string())
+        .collect();
+    assert_eq!(
+        descriptions,
+        vec![
+            "client 2: held 3.0 vs 3.3, total 3.0 vs 3.3",
+            "client 3: only in the second accounts",
+            "client 4: locked false vs true",
+            "client 5: only in the first accounts",
+        ]
+    );
+    assert_eq!(differences[0].differing_fields, vec!["held", "total"]);
+}
+
+#[test]
+fn test_verify_with_delimiter_and_number_format() {
+    let config = ProcessingConfig {
+        delimiter: b';',
+        number_format: Some(NumberFormat {
+            decimal_separator: ',',
+            grouping_separator: None,
+        }),
+        ..Default::default()
+    };
+    assert!(verify_with_config(
+        "resources/semicolon-transaction-list.csv",
+        "resources/semicolon-expected-account-list.csv",
+        &config,
+    )
+    .unwrap()
+    .is_empty());
+
+    let mut output = Vec::new();
+    process_transactions_with_config(
+        &mut output,
+        "resources/semicolon-transaction-list.csv",
+        &config,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        fs::read_to_string("resources/semicolon-expected-account-list.csv").unwrap()
+    );
+}
+
+#[test]
+fn test_error_tolerance() {
+    let input = "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        withdrawal,1,2,5.0\n\
+        withdrawal,2,3,1.0\n\
+        deposit,1,4,2.0\n\
+        withdrawal,1,5,9.0\n";
+    let process = |config: ProcessingConfig| {
+        let mut manager = TransactionManager::new(config.clone());
+        manager
+            .process_source(CsvSource::new(input.as_bytes(), &config))
+            .map(|()| manager.summary())
+    };
+    let config = ProcessingConfig {
+        skip_invalid_transactions: true,
+        ..Default::default()
+    };
+
+    // Under either threshold, processing completes.
+    let summary = process(ProcessingConfig {
+        max_errors: Some(3),
+        max_error_rate: Some(0.6),
+        ..config.clone()
+    })
+    .unwrap();
+    assert_eq!((summary.errors, summary.rows_read), (3, 5));
+
+    // Over the count, processing stops at the row that crossed it.
+    let error = process(ProcessingConfig {
+        max_errors: Some(1),
+        ..config.clone()
+    })
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<ProcessingError>(),
+        Some(ProcessingError::TooManyErrors(2, 3))
+    ));
+
+    // Over the rate, processing fails once the input has been read.
+    let error = process(ProcessingConfig {
+        max_error_rate: Some(0.5),
+        ..config
+    })
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<ProcessingError>(),
+        Some(ProcessingError::TooManyErrors(3, 5))
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Error: Stopped after 3 of 5 transactions were rejected, which is more than the config allows."
+    );
+}
+
+#[test]
+fn test_process_transactions_with_callback() {
+    let mut errors: Vec<TransactionError> = Vec::new();
+    let mut output = Vec::new();
+    let summary = process_transactions_with_callback(
+        &mut output,
+        "resources/rejected-transaction-list.csv",
+        &ProcessingConfig::default(),
+        |error| errors.push(error.clone()),
+    )
+    .unwrap();
+    let rejected: Vec<(u32, &str)> = errors
+        .iter()
+        .map(|error| (error.transaction.id, error.code()))
+        .collect();
+    assert_eq!(
+        rejected,
+        vec![
+            (2, "INSUFFICIENT_FUNDS"),
+            (3, "FIRST_TRANSACTION_NOT_DEPOSIT")
+        ]
+    );
+    assert_eq!(summary.errors, 2);
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    assert_eq!(
+        output,
+        "\
+client,available,held,total,locked
+1,0.5,0.0,0.5,false
+"
+    );
+}
+
+#[test]
+fn test_delimiters() {
+    let mut expected = Vec::new();
+    process_transactions(&mut expected, "resources/transaction-list.csv").unwrap();
+    for (delimiter, path) in [
+        (b';', "resources/semicolon-transaction-list.csv"),
+        (b'\t', "resources/tab-transaction-list.tsv"),
+    ] {
+        let mut output = Vec::new();
+        process_transactions_with_config(
+            &mut output,
+            path,
+            &ProcessingConfig {
+                delimiter,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).expect("Not UTF-8");
+        let expected = String::from_utf8(expected.clone())
+            .expect("Not UTF-8")
+            .replace(',', &(delimiter as char).to_string());
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn test_process_transaction_files_breaks_timestamp_ties_by_file_order() {
+    let process = |paths: &[&str]| {
+        let mut output = Vec::new();
+        process_transaction_files(&mut output, paths).unwrap();
+        String::from_utf8(output).expect("Not UTF-8")
+    };
+    // The dispute in file a and the deposit it references in file b share a timestamp.
+    // Listed first, the dispute is applied before the deposit exists and is ignored.
+    assert_eq!(
+        process(&[
+            "resources/tied-timestamp-list-a.csv",
+            "resources/tied-timestamp-list-b.csv",
+        ]),
+        "\
+client,available,held,total,locked
+1,5.0,0.0,5.0,false
+"
+    );
+    assert_eq!(
+        process(&[
+            "resources/tied-timestamp-list-b.csv",
+            "resources/tied-timestamp-list-a.csv",
+        ]),
+        "\
+client,available,held,total,locked
+1,2.0,3.0,5.0,false
+"
+    );
+
+    // Without ties, the timestamps alone decide the order, so the order of the files does not matter.
+    assert_eq!(
+        process(&[
+            "resources/timestamped-list-a.csv",
+            "resources/timestamped-list-b.csv",
+        ]),
+        process(&[
+            "resources/timestamped-list-b.csv",
+            "resources/timestamped-list-a.csv",
+        ])
+    );
+}
+
+#[test]
+fn test_process_transaction_files_interleaves_by_timestamp() {
+    let paths = [
+        "resources/timestamped-list-a.csv",
+        "resources/timestamped-list-b.csv",
+    ];
+    let mut output = Vec::new();
+    process_transaction_files(&mut output, &paths).unwrap();
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    assert_eq!(
+        output,
+        "\
+client,available,held,total,locked
+1,0.5,0.0,0.5,false
+2,0.0,2.0,2.0,false
+"
+    );
+
+    // Applied file-then-row, the withdrawal would come before the second deposit and fail.
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    let mut result = Ok(());
+    for path in paths {
+        let mut source = CsvSource::open(path, &ProcessingConfig::default()).unwrap();
+        while let Some(next_transaction_result) = source.next() {
+            let transaction = next_transaction_result.unwrap();
+            result = result.and(update_client(
+                &mut clients,
+                transaction,
+                &ProcessingConfig::default(),
+            ));
+        }
+    }
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_process_headerless_transactions() {
+    let config = ProcessingConfig {
+        has_headers: false,
+        ..Default::default()
+    };
+    let mut headerless_output = Vec::new();
+    process_transactions_with_config(
+        &mut headerless_output,
+        "resources/headerless-transaction-list.csv",
+        &config,
+    )
+    .unwrap();
+    let mut output = Vec::new();
+    process_transactions(&mut output, "resources/transaction-list.csv").unwrap();
+    assert_eq!(headerless_output, output);
+
+    // Read as if it had a header, the first data row is taken as column names and the type column is missing.
+    assert!(
+        process_transactions(std::io::sink(), "resources/headerless-transaction-list.csv").is_err()
+    );
+}
+
+#[test]
+fn test_process_transactions_to_same_file() {
+    let input_path = "resources/transaction-list.csv";
+    let input_before = fs::read_to_string(input_path).unwrap();
+    let error = process_transactions_to_file(
+        "resources/../resources/transaction-list.csv",
+        input_path,
+        &ProcessingConfig::default(),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("same file as the input"));
+    assert_eq!(fs::read_to_string(input_path).unwrap(), input_before);
+
+    let copy_path = std::env::temp_dir().join("transaction-manager-same-path.csv");
+    let copy_path = copy_path.to_str().unwrap();
+    fs::copy(input_path, copy_path).unwrap();
+    let config = ProcessingConfig {
+        same_path_policy: SamePathPolicy::Overwrite,
+        ..Default::default()
+    };
+    process_transactions_to_file(copy_path, copy_path, &config).unwrap();
+    let mut expected = Vec::new();
+    process_transactions(&mut expected, input_path).unwrap();
+    assert_eq!(
+        fs::read_to_string(copy_path).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+    fs::remove_file(copy_path).unwrap();
+}
+
+#[test]
+fn test_process_transactions_parallel_matches_serial() {
+    let path = "resources/transaction-list.csv";
+    let mut serial_output = Vec::new();
+    process_transactions(&mut serial_output, path).unwrap();
+    for worker_count in 1..=4 {
+        let mut parallel_output = Vec::new();
+        process_transactions_parallel(
+            &mut parallel_output,
+            path,
+            worker_count,
+            &ProcessingConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(parallel_output, serial_output);
+    }
+
+    let error = process_transactions_parallel(
+        std::io::sink(),
+        "resources/timestamped-list-a.csv",
+        2,
+        &ProcessingConfig::default(),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("Insufficient funds"));
+}
+
+#[test]
+fn test_process_transactions_parallel_error_thresholds() {
+    let config = ProcessingConfig {
+        skip_invalid_transactions: true,
+        max_errors: Some(1),
+        ..Default::default()
+    };
+    // Two of the withdrawals in rejected-transaction-list.csv are rejected, for different clients.
+    let path = "resources/rejected-transaction-list.csv";
+    let serial_error =
+        process_transactions_with_config(std::io::sink(), path, &config).unwrap_err();
+    assert!(matches!(
+        serial_error.downcast_ref::<ProcessingError>(),
+        Some(ProcessingError::TooManyErrors(2, _))
+    ));
+    for worker_count in 1..=2 {
+        let mut output = Vec::new();
+        let parallel_error =
+            process_transactions_parallel(&mut output, path, worker_count, &config).unwrap_err();
+        assert!(matches!(
+            parallel_error.downcast_ref::<ProcessingError>(),
+            Some(ProcessingError::TooManyErrors(2, _))
+        ));
+        assert!(output.is_empty());
+    }
+
+    // Under the threshold, both paths write the same accounts and return the same summary.
+    let config = ProcessingConfig {
+        max_errors: Some(2),
+        metadata_header: true,
+        ..config
+    };
+    let mut serial_output = Vec::new();
+    let serial_summary =
+        process_transactions_with_config(&mut serial_output, path, &config).unwrap();
+    let mut parallel_output = Vec::new();
+    let parallel_summary =
+        process_transactions_parallel(&mut parallel_output, path, 2, &config).unwrap();
+    assert_eq!(parallel_summary, serial_summary);
+    let without_header = |output: Vec<u8>| {
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("# processed-at="));
+        output.split_once('\n').unwrap().1.to_string()
+    };
+    assert_eq!(
+        without_header(parallel_output),
+        without_header(serial_output)
+    );
+}
+
+#[test]
+fn test_output_rounding_mode() {
+    let account_states = || {
+        vec![AccountState {
+            id: 1,
+            available: 1.23459,
+            held: 0.0,
+            total: 1.23459,
+            locked: false,
+        }]
+    };
+    let config = ProcessingConfig {
+        rounding_mode: RoundingMode::Truncate,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    write_account_states(&mut output, account_states(), &config).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1.2345,0.0,1.2345,false\n"
+    );
+
+    let mut output = Vec::new();
+    write_account_states(
+        &mut output,
+        account_states(),
+        &ProcessingConfig {
+            number_format: Some(NumberFormat {
+                decimal_separator: ',',
+                grouping_separator: None,
+            }),
+            delimiter: b';',
+            ..config
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client;available;held;total;locked\n1;1,2345;0,0;1,2345;false\n"
+    );
+
+    // The default half up rounding rounds the same balance up.
+    let mut output = Vec::new();
+    write_account_states(&mut output, account_states(), &ProcessingConfig::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,1.2346,0.0,1.2346,false\n"
+    );
+}
+
+#[test]
+fn test_write_accounts() {
     let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
     clients.insert(
         0,
         Client {
             id: 1,
             available: 1.0,
-            held: 0.0,
             total: 1.0,
-            locked: false,
-            transactions: BTreeMap::new(),
+            ..Client::new(1)
         },
     );
     let mut output = Vec::new();
-    write_accounts(&mut output, clients).unwrap();
+    write_accounts(
+        &mut output,
+        clients.into_values(),
+        &ProcessingConfig::default(),
+    )
+    .unwrap();
     let output = String::from_utf8(output).expect("Not UTF-8");
     assert_eq!(
         output,
@@ -121,3 +2343,578 @@ client,available,held,total,locked
 "
     );
 }
+
+#[test]
+fn test_write_accounts_from_unsorted_iterator() {
+    let clients = [3, 1, 2].into_iter().map(|id| {
+        let mut client = Client::new(id);
+        client.available = id as f64;
+        client.total = id as f64;
+        client
+    });
+    let mut output = Vec::new();
+    write_accounts(&mut output, clients, &ProcessingConfig::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+client,available,held,total,locked
+3,3.0,0.0,3.0,false
+1,1.0,0.0,1.0,false
+2,2.0,0.0,2.0,false
+"
+    );
+}
+
+#[test]
+fn test_account_states_written_without_logs() {
+    let mut manager = TransactionManager::new(ProcessingConfig::default());
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let mut expected = Vec::new();
+    manager.write_accounts(&mut expected).unwrap();
+    let mut output = Vec::new();
+    write_account_states(
+        &mut output,
+        manager.into_account_states(),
+        &ProcessingConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_process_transactions_from_sources() {
+    let csv_input = "\
+type, client, tx, amount
+deposit, 1, 1, 3.0
+withdrawal, 1, 2, 1.0
+deposit, 2, 3, 2.0
+dispute, 2, 3,
+";
+    let json_lines_input = r#"
+{"type": "deposit", "client": 1, "tx": 1, "amount": 3.0}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": 1.0}
+{"type": "deposit", "client": 2, "tx": 3, "amount": 2.0}
+{"type": "dispute", "client": 2, "tx": 3}
+"#;
+    let config = ProcessingConfig::default();
+    let mut csv_output = Vec::new();
+    let summary = process_transactions_from_source(
+        &mut csv_output,
+        CsvSource::new(csv_input.as_bytes(), &config),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(summary.transactions_applied, 4);
+    let mut json_lines_output = Vec::new();
+    let summary = process_transactions_from_source(
+        &mut json_lines_output,
+        JsonLinesSource::new(json_lines_input.as_bytes()),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(summary.transactions_applied, 4);
+    assert_eq!(csv_output, json_lines_output);
+    assert_eq!(
+        String::from_utf8(csv_output).unwrap(),
+        "\
+client,available,held,total,locked
+1,2.0,0.0,2.0,false
+2,0.0,2.0,2.0,false
+"
+    );
+}
+
+#[test]
+fn test_merged_files_metadata_header() {
+    let mut output = Vec::new();
+    process_transaction_files_with_config(
+        &mut output,
+        &[
+            "resources/timestamped-list-a.csv",
+            "resources/timestamped-list-b.csv",
+        ],
+        &ProcessingConfig {
+            metadata_header: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    let metadata = output.lines().next().unwrap();
+    assert!(metadata.starts_with("# processed-at="));
+    assert!(metadata.contains(
+        " input=resources/timestamped-list-a.csv,resources/timestamped-list-b.csv rows="
+    ));
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let config = ProcessingConfig::default();
+    let mut transactions = Vec::new();
+    let mut source = CsvSource::open("resources/transaction-list.csv", &config).unwrap();
+    while let Some(next_transaction_result) = source.next() {
+        transactions.push(next_transaction_result.unwrap());
+    }
+    let second_half = transactions.split_off(transactions.len() / 2);
+
+    let mut manager = TransactionManager::new(config.clone());
+    for transaction in transactions.iter().cloned() {
+        manager.apply_transaction(transaction).unwrap();
+    }
+    let mut snapshot = Vec::new();
+    manager.save_snapshot(&mut snapshot).unwrap();
+    drop(manager);
+    let mut resumed =
+        TransactionManager::load_snapshot(snapshot.as_slice(), config.clone()).unwrap();
+    for transaction in second_half.iter().cloned() {
+        resumed.apply_transaction(transaction).unwrap();
+    }
+
+    let mut single_pass = TransactionManager::new(config);
+    for transaction in transactions.into_iter().chain(second_half) {
+        single_pass.apply_transaction(transaction).unwrap();
+    }
+    let mut resumed_output = Vec::new();
+    resumed.write_accounts(&mut resumed_output).unwrap();
+    let mut single_pass_output = Vec::new();
+    single_pass.write_accounts(&mut single_pass_output).unwrap();
+    assert_eq!(resumed_output, single_pass_output);
+    assert_eq!(resumed.summary(), single_pass.summary());
+    assert_eq!(resumed.stats(), single_pass.stats());
+    assert_eq!(resumed.clients, single_pass.clients);
+}
+
+#[test]
+fn test_snapshot_error_count() {
+    let mut manager = TransactionManager::new(ProcessingConfig {
+        skip_invalid_transactions: true,
+        ..Default::default()
+    });
+    manager
+        .process_file("resources/rejected-transaction-list.csv")
+        .unwrap();
+    let mut snapshot = Vec::new();
+    manager.save_snapshot(&mut snapshot).unwrap();
+    let restored =
+        TransactionManager::load_snapshot(snapshot.as_slice(), ProcessingConfig::default())
+            .unwrap();
+    assert_eq!(restored.summary().errors, 2);
+
+    // A snapshot without an error count works it out from the statistics.
+    let mut snapshot: serde_json::Value = serde_json::from_slice(&snapshot).unwrap();
+    snapshot.as_object_mut().unwrap().remove("errors");
+    let restored = TransactionManager::load_snapshot(
+        snapshot.to_string().as_bytes(),
+        ProcessingConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(restored.summary().errors, 2);
+
+    // If it has more transactions applied than attempted, it is rejected rather than underflowing.
+    snapshot["stats"]["per_client_applied"]["1"] = serde_json::json!(100);
+    let error = TransactionManager::load_snapshot(
+        snapshot.to_string().as_bytes(),
+        ProcessingConfig::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<ProcessingError>(),
+        Some(ProcessingError::InvalidSnapshot(_))
+    ));
+}
+
+#[test]
+fn test_metadata_header() {
+    let mut manager = TransactionManager::new(ProcessingConfig {
+        metadata_header: true,
+        ..Default::default()
+    });
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let mut output = Vec::new();
+    manager.write_accounts(&mut output).unwrap();
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    let metadata = output.lines().next().unwrap();
+    assert!(metadata.starts_with("# processed-at="));
+    assert!(metadata.contains(" input=resources/transaction-list.csv rows=11 version="));
+
+    let mut reader = ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(output.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec!["client", "available", "held", "total", "locked"]
+    );
+    assert_eq!(reader.records().count(), 4);
+}
+
+#[test]
+fn test_audit_output() {
+    let mut output = Vec::new();
+    process_transactions_with_config(
+        &mut output,
+        "resources/transaction-list.csv",
+        &ProcessingConfig {
+            output_format: OutputFormat::Audit,
+            metadata_header: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let output = String::from_utf8(output).expect("Not UTF-8");
+    let documents: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(documents.len(), 4);
+    assert_eq!(documents[3]["client"], 4);
+    assert_eq!(documents[3]["locked"], true);
+    assert_eq!(documents[3]["transactions"]["6"][2]["type"], "chargeback");
+}
+
+#[test]
+fn test_audit_output_is_stable() {
+    let audit = |output_format: OutputFormat| -> String {
+        let mut output = Vec::new();
+        process_transactions_with_config(
+            &mut output,
+            "resources/transaction-list.csv",
+            &ProcessingConfig {
+                output_format,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(audit(OutputFormat::Audit), audit(OutputFormat::Audit));
+    let pretty = audit(OutputFormat::PrettyAudit);
+    assert_eq!(pretty, audit(OutputFormat::PrettyAudit));
+
+    // The pretty documents hold the same data, with the log in transaction ID order and each group in applied order.
+    let documents: Vec<serde_json::Value> = serde_json::Deserializer::from_str(&pretty)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let compact: Vec<serde_json::Value> = audit(OutputFormat::Audit)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(documents, compact);
+    assert!(pretty.lines().count() > documents.len());
+    let group_types: Vec<&str> = documents[3]["transactions"]["6"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|transaction| transaction["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(group_types, ["deposit", "dispute", "chargeback"]);
+}
+
+#[test]
+fn test_write_accounts_sort_order() {
+    let mut clients: BTreeMap<u16, Client> = BTreeMap::new();
+    for (id, available, held, locked) in [
+        (1, 1.0, 4.0, false),
+        (2, 3.0, 0.0, true),
+        (3, 2.0, 0.0, false),
+    ] {
+        let mut client = Client::new(id);
+        client.available = available;
+        client.held = held;
+        client.total = available + held;
+        client.locked = locked;
+        clients.insert(id, client);
+    }
+    let client_order = |sort_order: SortOrder| -> Vec<String> {
+        let mut output = Vec::new();
+        let config = ProcessingConfig {
+            sort_order,
+            ..Default::default()
+        };
+        write_clients(&mut output, &clients, &config).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(client_order(SortOrder::ClientId), ["1", "2", "3"]);
+    assert_eq!(client_order(SortOrder::TotalDescending), ["1", "2", "3"]);
+    assert_eq!(
+        client_order(SortOrder::AvailableDescending),
+        ["2", "3", "1"]
+    );
+    assert_eq!(client_order(SortOrder::LockedFirst), ["2", "1", "3"]);
+}
+
+#[test]
+fn test_process_csv_str() {
+    // The string wrapper produces exactly what processing the same csv as a file does.
+    let input = fs::read_to_string("resources/transaction-list.csv").unwrap();
+    let mut expected = Vec::new();
+    process_transactions(&mut expected, "resources/transaction-list.csv").unwrap();
+    assert_eq!(
+        process_csv_str(&input).unwrap(),
+        String::from_utf8(expected).unwrap()
+    );
+
+    assert!(process_csv_str("type, client, tx, amount\nwithdrawal, 1, 1, 1.0\n").is_err());
+}
+
+#[test]
+fn test_output_is_sorted_for_many_clients() {
+    // Clients are created in descending id order, and must still be written in ascending id order whatever the client store is.
+    let mut input = String::from("type,client,tx,amount\n");
+    for id in 0..500 {
+        input.push_str(&format!("deposit,{},{},1.0\n", 499 - id, id));
+    }
+    let mut output = Vec::new();
+    process_transactions_from_reader(&mut output, input.as_bytes(), &ProcessingConfig::default())
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let client_ids: Vec<u32> = output
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(client_ids, (0..500).collect::<Vec<u32>>());
+}
+
+#[test]
+fn test_observer_records_locks() {
+    #[derive(Default)]
+    struct LockRecorder {
+        locks: Vec<(u16, u32)>,
+        deposits: usize,
+    }
+
+    impl LedgerObserver for LockRecorder {
+        fn on_deposit(&mut self, _client: &Client, _transaction: &Transaction) {
+            self.deposits += 1;
+        }
+
+        fn on_lock(&mut self, client: &Client, transaction: &Transaction) {
+            self.locks.push((client.id, transaction.id));
+        }
+    }
+
+    let recorder = Arc::new(Mutex::new(LockRecorder::default()));
+    let mut manager = TransactionManager::new(ProcessingConfig::default());
+    manager.add_observer(recorder.clone());
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let recorder = recorder.lock().unwrap();
+    assert_eq!(recorder.locks, vec![(4, 6)]);
+    assert_eq!(recorder.deposits, 5);
+}
+
+#[test]
+fn test_write_changed_accounts() {
+    let mut manager = TransactionManager::new(ProcessingConfig::default());
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let mut output = Vec::new();
+    manager
+        .write_changed_accounts(&mut output, "resources/baseline-account-list.csv")
+        .unwrap();
+    // Only client 2 differs from the baseline. Client 5 is only in the baseline, so it is not written.
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+client,available,held,total,locked
+2,0.0,3.3,3.3,false
+"
+    );
+}
+
+#[test]
+fn test_write_changed_accounts_reads_back_own_output() {
+    let config = ProcessingConfig {
+        delimiter: b';',
+        number_format: Some(NumberFormat {
+            decimal_separator: ',',
+            grouping_separator: Some('.'),
+        }),
+        metadata_header: true,
+        ..Default::default()
+    };
+    let mut manager = TransactionManager::new(config.clone());
+    manager
+        .process_file("resources/semicolon-transaction-list.csv")
+        .unwrap();
+    let baseline_path = std::env::temp_dir().join("transaction-manager-changed-baseline.csv");
+    let baseline_path = baseline_path.to_str().unwrap();
+    manager
+        .write_accounts(File::create(baseline_path).unwrap())
+        .unwrap();
+
+    let baseline = reporting::read_accounts(baseline_path, &config).unwrap();
+    assert_eq!(baseline.len(), 4);
+    assert_eq!(baseline[&2].held, 3.3);
+    assert!(baseline[&4].locked);
+
+    let mut output = Vec::new();
+    manager
+        .write_changed_accounts(&mut output, baseline_path)
+        .unwrap();
+    fs::remove_file(baseline_path).unwrap();
+    // Nothing changed since the baseline, so only the metadata header and the csv header are written.
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("# processed-at="));
+    assert_eq!(lines[1], "client;available;held;total;locked");
+}
+
+#[test]
+fn test_row_limit() {
+    let config = ProcessingConfig {
+        row_limit: Some(2),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let summary =
+        process_transactions_with_config(&mut output, "resources/transaction-list.csv", &config)
+            .unwrap();
+    // Only the first deposit and withdrawal are read.
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+client,available,held,total,locked
+1,1.0,0.0,1.0,false
+"
+    );
+    assert_eq!(summary.transactions_applied, 2);
+}
+
+#[test]
+fn test_empty_input() {
+    // A header-only file and a zero-byte file both produce an account list with only the output header.
+    for input in ["type, client, tx, amount\n", ""] {
+        assert_eq!(
+            process_csv_str(input).unwrap(),
+            "client,available,held,total,locked\n"
+        );
+    }
+    let mut output = Vec::new();
+    write_accounts(&mut output, Vec::new(), &ProcessingConfig::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n"
+    );
+}
+
+#[test]
+fn test_suppress_zero_accounts() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,1.0
+deposit,2,3,2.0
+dispute,2,3,
+chargeback,2,3,
+deposit,3,4,3.0
+";
+    let config = ProcessingConfig {
+        suppress_zero_accounts: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    process_transactions_from_reader(&mut output, input.as_bytes(), &config).unwrap();
+    // Client 1 is emptied and unlocked, so it is left out, while the emptied client 2 is locked and kept.
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "\
+client,available,held,total,locked
+2,0.0,0.0,0.0,true
+3,3.0,0.0,3.0,false
+"
+    );
+
+    let mut output = Vec::new();
+    process_transactions_from_reader(&mut output, input.as_bytes(), &ProcessingConfig::default())
+        .unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("1,0.0,0.0,0.0,false"));
+}
+
+#[test]
+fn test_extra_columns_in_audit_output() {
+    let input = "\
+type,client,tx,amount,merchant_id,memo
+deposit,1,1,2.0,4200,first deposit
+dispute,1,1,,,
+";
+    let config = ProcessingConfig {
+        keep_extra_columns: true,
+        output_format: OutputFormat::Audit,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    process_transactions_from_reader(&mut output, input.as_bytes(), &config).unwrap();
+    let document: serde_json::Value =
+        serde_json::from_str(String::from_utf8(output).unwrap().trim()).unwrap();
+    let logged = &document["transactions"]["1"];
+    assert_eq!(logged[0]["metadata"]["merchant_id"], "4200");
+    assert_eq!(logged[0]["metadata"]["memo"], "first deposit");
+    assert_eq!(logged[1]["metadata"]["memo"], "");
+
+    // Extra columns are still rejected by default.
+    let error = process_transactions_from_reader(
+        std::io::sink(),
+        input.as_bytes(),
+        &ProcessingConfig::default(),
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Unexpected columns: merchant_id, memo."));
+}
+
+#[test]
+fn test_simulate() {
+    let mut manager = TransactionManager::new(ProcessingConfig::default());
+    manager
+        .process_file("resources/transaction-list.csv")
+        .unwrap();
+    let mut snapshot = Vec::new();
+    manager.save_snapshot(&mut snapshot).unwrap();
+    let manager = TransactionManager::load_snapshot(
+        snapshot.as_slice(),
+        ProcessingConfig {
+            skip_invalid_transactions: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let withdrawal = |amount| Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 3,
+        id: 100,
+        amount: Some(amount),
+        ..Default::default()
+    };
+    let clients_before = manager.clients.clone();
+    let summary_before = manager.summary();
+
+    // Even though the config skips invalid transactions, the simulation reports why it would be rejected.
+    let error = manager.simulate(withdrawal(100.0)).unwrap_err();
+    assert!(matches!(
+        error.error_type,
+        TransactionErrorTypes::InsufficientFunds
+    ));
+    assert_eq!(error.client, clients_before[&3].state());
+
+    let state = manager.simulate(withdrawal(4.0)).unwrap();
+    assert_eq!((state.available, state.total), (0.0, 0.0));
+    assert_eq!(manager.clients, clients_before);
+    assert_eq!(manager.summary(), summary_before);
+}
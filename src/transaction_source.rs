@@ -0,0 +1,501 @@
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use flate2::read::GzDecoder;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read};
+
+use crate::processing_config::*;
+use crate::processing_error::*;
+use crate::transaction::*;
+
+/// A feed of transactions that can be read one at a time, such as a csv file or a JSON-lines stream.
+pub trait TransactionSource {
+    /// Read the next transaction, or None once the source is exhausted.
+    /// May produce an error if the next transaction cannot be read or parsed.
+    fn next(&mut self) -> Option<Result<Transaction, Box<dyn Error>>>;
+}
+
+/// Reads transactions from csv with a type, client, tx, amount, and optional timestamp, currency, and ref_tx column.
+/// Whitespace is trimmed from every field, and the config's header, extra column, delimiter, quote, lenient amount, and row limit settings are used.
+pub struct CsvSource<R: Read> {
+    reader: Reader<R>,
+    record: StringRecord,
+    /// How many more rows may be read, if the config limits them.
+    rows_remaining: Option<usize>,
+    parser: CsvRowParser,
+}
+
+/// Turns csv rows into transactions using the config's extra column and lenient amount settings and type aliases.
+/// Shared by CsvSource and the async csv reader, so every csv input is parsed the same way.
+pub(crate) struct CsvRowParser {
+    /// The header row, once it has been read, if the input has one.
+    headers: Option<StringRecord>,
+    /// The currency symbol to remove from amount cells, if lenient amounts are enabled.
+    lenient_currency_symbol: Option<String>,
+    /// Whether columns other than the transaction columns are kept as metadata.
+    keep_extra_columns: bool,
+    /// Other spellings of transaction types, matched ignoring ASCII case.
+    type_aliases: Vec<(String, TransactionType)>,
+    /// The column holding the transaction type, found once from the header row, or the first column if the input has none.
+    type_column: Option<usize>,
+    /// The column holding the amount, found once from the header row, or the fourth column if the input has none.
+    amount_column: Option<usize>,
+}
+
+/// The columns every transaction csv header must have.
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// The columns a transaction csv header may also have.
+const OPTIONAL_COLUMNS: [&str; 3] = ["timestamp", "currency", "ref_tx"];
+
+/// Check a transaction csv header row, in any column order, against the type, client, tx, and amount columns
+/// and the optional timestamp, currency, and ref_tx columns. An empty header row, from a zero-byte input, is accepted,
+/// so a zero-byte input is read as no transactions, just like a header-only input.
+/// Produces an InvalidHeader error listing every missing and unexpected column.
+/// ```
+/// use csv::StringRecord;
+/// use transaction_manager::transaction_source::*;
+/// assert!(validate_headers(&StringRecord::from(vec!["amount", "tx", "client", "type"])).is_ok());
+/// let error = validate_headers(&StringRecord::from(vec!["typ", "client", "tx", "amount"])).unwrap_err();
+/// assert!(error.to_string().contains("Missing columns: type."));
+/// ```
+pub fn validate_headers(headers: &StringRecord) -> Result<(), ProcessingError> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+    let missing: Vec<String> = REQUIRED_COLUMNS
+        .iter()
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .map(|column| column.to_string())
+        .collect();
+    let extra: Vec<String> = headers
+        .iter()
+        .filter(|header| !REQUIRED_COLUMNS.contains(header) && !OPTIONAL_COLUMNS.contains(header))
+        .map(|header| header.to_string())
+        .collect();
+    if missing.is_empty() && extra.is_empty() {
+        Ok(())
+    } else {
+        Err(ProcessingError::InvalidHeader(missing, extra))
+    }
+}
+
+/// Reads transactions from JSON lines, with one transaction object per line using the same field names as the csv columns.
+/// Blank lines are skipped.
+pub struct JsonLinesSource<R: BufRead> {
+    lines: Lines<R>,
+}
+
+impl CsvSource<Box<dyn Read>> {
+    /// Open a csv source over the given transaction file path.
+    /// Files that start with the gzip magic bytes are decompressed as they are read, so `.csv.gz` dumps need no separate step.
+    /// If the config says the file has no header row, columns are read in the order type, client, tx, amount, timestamp, currency, ref_tx.
+    /// May produce an error if the file cannot be opened or read.
+    pub fn open(
+        transactions_file_path: &str,
+        config: &ProcessingConfig,
+    ) -> Result<CsvSource<Box<dyn Read>>, csv::Error> {
+        let mut file = BufReader::new(File::open(transactions_file_path)?);
+        let source: Box<dyn Read> = if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        Ok(CsvSource::new(source, config))
+    }
+}
+
+impl<R: Read> CsvSource<R> {
+    /// Create a csv source that reads from the given reader.
+    /// ```
+    /// use transaction_manager::processing_config::*;
+    /// use transaction_manager::transaction_source::*;
+    /// let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+    /// let mut source = CsvSource::new(input.as_bytes(), &ProcessingConfig::default());
+    /// assert_eq!(source.next().unwrap().unwrap().amount, Some(1.0));
+    /// assert!(source.next().is_none());
+    /// ```
+    pub fn new(reader: R, config: &ProcessingConfig) -> CsvSource<R> {
+        CsvSource {
+            reader: ReaderBuilder::new()
+                .trim(Trim::All)
+                .has_headers(config.has_headers)
+                .delimiter(config.delimiter)
+                .quote(config.quote)
+                .from_reader(reader),
+            record: StringRecord::new(),
+            rows_remaining: config.row_limit,
+            parser: CsvRowParser::new(config),
+        }
+    }
+}
+
+impl CsvRowParser {
+    /// Create a parser for csv rows read with the given config. If the config says the input has no header row,
+    /// columns are read in the order type, client, tx, amount, timestamp, currency, ref_tx.
+    pub(crate) fn new(config: &ProcessingConfig) -> CsvRowParser {
+        CsvRowParser {
+            headers: None,
+            lenient_currency_symbol: if config.lenient_amounts {
+                Some(config.currency_symbol.clone())
+            } else {
+                None
+            },
+            keep_extra_columns: config.keep_extra_columns,
+            type_aliases: config
+                .type_aliases
+                .iter()
+                .map(|(alias, transaction_type)| (alias.clone(), transaction_type.clone()))
+                .collect(),
+            type_column: if config.has_headers { None } else { Some(0) },
+            amount_column: if config.has_headers { None } else { Some(3) },
+        }
+    }
+
+    /// Check the given header row and find the type and amount columns in it. The header row is kept even if it is invalid,
+    /// so it is only checked once. Extra columns are accepted if the config keeps them as metadata.
+    /// Produces an InvalidHeader error if the header row is invalid. See validate_headers.
+    pub(crate) fn read_headers(&mut self, headers: StringRecord) -> Result<(), ProcessingError> {
+        let validation = match validate_headers(&headers) {
+            Err(ProcessingError::InvalidHeader(missing, _))
+                if self.keep_extra_columns && missing.is_empty() =>
+            {
+                Ok(())
+            }
+            validation => validation,
+        };
+        self.type_column = headers.iter().position(|header| header == "type");
+        self.amount_column = headers.iter().position(|header| header == "amount");
+        self.headers = Some(headers);
+        validation
+    }
+
+    /// Parse the given csv row as a transaction, after removing amount formatting if lenient amounts are enabled
+    /// and replacing a type alias with the name of the type it stands for.
+    /// May produce an error if the row cannot be deserialized as a transaction.
+    pub(crate) fn parse(&self, record: &StringRecord) -> Result<Transaction, Box<dyn Error>> {
+        let stripped_amount = match (&self.lenient_currency_symbol, self.amount_column) {
+            (Some(currency_symbol), Some(amount_column)) => {
+                record.get(amount_column).map(|field| {
+                    (
+                        amount_column,
+                        strip_amount_formatting(field, currency_symbol),
+                    )
+                })
+            }
+            _ => None,
+        };
+        let type_name = self.type_column.and_then(|type_column| {
+            let field = record.get(type_column)?;
+            let (_, transaction_type) = self
+                .type_aliases
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(field))?;
+            Some((type_column, transaction_type.name()))
+        });
+        let replaced;
+        let record = if stripped_amount.is_some() || type_name.is_some() {
+            replaced = replace_fields(
+                record,
+                stripped_amount
+                    .as_ref()
+                    .map(|(column, amount)| (*column, amount.as_str())),
+                type_name,
+            );
+            &replaced
+        } else {
+            record
+        };
+        let mut transaction: Transaction = record.deserialize(self.headers.as_ref())?;
+        if self.keep_extra_columns {
+            if let Some(headers) = &self.headers {
+                transaction.metadata = headers
+                    .iter()
+                    .zip(record.iter())
+                    .filter(|(header, _)| {
+                        !REQUIRED_COLUMNS.contains(header) && !OPTIONAL_COLUMNS.contains(header)
+                    })
+                    .map(|(header, field)| (header.to_string(), field.to_string()))
+                    .collect();
+            }
+        }
+        Ok(transaction)
+    }
+}
+
+/// Copy the given record with the cells in the given columns replaced, keeping its position for error messages.
+fn replace_fields(
+    record: &StringRecord,
+    first: Option<(usize, &str)>,
+    second: Option<(usize, &str)>,
+) -> StringRecord {
+    let mut replaced: StringRecord = record
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            [first, second]
+                .into_iter()
+                .flatten()
+                .find(|(column, _)| *column == index)
+                .map_or(field, |(_, value)| value)
+        })
+        .collect();
+    replaced.set_position(record.position().cloned());
+    replaced
+}
+
+impl<R: Read> TransactionSource for CsvSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, Box<dyn Error>>> {
+        match &mut self.rows_remaining {
+            Some(0) => return None,
+            Some(rows_remaining) => *rows_remaining -= 1,
+            None => {}
+        }
+        if self.parser.headers.is_none() && self.reader.has_headers() {
+            let headers = match self.reader.headers() {
+                Ok(headers) => headers.clone(),
+                Err(error) => return Some(Err(error.into())),
+            };
+            if let Err(error) = self.parser.read_headers(headers) {
+                return Some(Err(error.into()));
+            }
+        }
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(error) => return Some(Err(error.into())),
+        }
+        Some(self.parser.parse(&self.record))
+    }
+}
+
+impl<R: BufRead> JsonLinesSource<R> {
+    /// Create a JSON-lines source that reads from the given reader.
+    /// ```
+    /// use transaction_manager::transaction::*;
+    /// use transaction_manager::transaction_source::*;
+    /// let input = r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": 0.5}"#;
+    /// let mut source = JsonLinesSource::new(input.as_bytes());
+    /// let transaction = source.next().unwrap().unwrap();
+    /// assert_eq!(transaction.transaction_type, TransactionType::Withdrawal);
+    /// assert_eq!(transaction.timestamp, None);
+    /// assert!(source.next().is_none());
+    /// ```
+    pub fn new(reader: R) -> JsonLinesSource<R> {
+        JsonLinesSource {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> TransactionSource for JsonLinesSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, Box<dyn Error>>> {
+        for line in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error.into())),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(|error| error.into()));
+        }
+        None
+    }
+}
+
+#[test]
+fn test_invalid_header() {
+    let read = |input: &str| {
+        let mut source = CsvSource::new(input.as_bytes(), &ProcessingConfig::default());
+        source.next().unwrap().map_err(|error| error.to_string())
+    };
+    let error = read("type, client, amount\ndeposit, 1, 1.0\n").unwrap_err();
+    assert!(error.contains("Missing columns: tx."), "{}", error);
+    assert!(!error.contains("Unexpected"), "{}", error);
+
+    let error = read("type, client, tx, amount, note\ndeposit, 1, 1, 1.0, hi\n").unwrap_err();
+    assert!(error.contains("Unexpected columns: note."), "{}", error);
+    assert!(!error.contains("Missing"), "{}", error);
+
+    let error = read("typ, client, tx, amount\ndeposit, 1, 1, 1.0\n").unwrap_err();
+    assert!(
+        error.contains("Missing columns: type. Unexpected columns: typ."),
+        "{}",
+        error
+    );
+
+    // Column order does not matter, and the optional columns are accepted.
+    let transaction =
+        read("amount, currency, tx, timestamp, client, type\n1.0, USD, 1, 5, 2, deposit\n")
+            .unwrap();
+    assert_eq!(transaction.client_id, 2);
+
+    // Empty input has no header row to check.
+    let mut source = CsvSource::new("".as_bytes(), &ProcessingConfig::default());
+    assert!(source.next().is_none());
+}
+
+#[test]
+fn test_lenient_amounts() {
+    let input = "\
+type, client, tx, amount
+deposit,1,1,\"$1,250.00\"
+deposit, 1, 2, 1250
+dispute, 1, 1,
+";
+    let config = ProcessingConfig {
+        lenient_amounts: true,
+        ..Default::default()
+    };
+    let mut source = CsvSource::new(input.as_bytes(), &config);
+    assert_eq!(source.next().unwrap().unwrap().amount, Some(1250.0));
+    assert_eq!(source.next().unwrap().unwrap().amount, Some(1250.0));
+    assert_eq!(source.next().unwrap().unwrap().amount, None);
+    assert!(source.next().is_none());
+
+    let config = ProcessingConfig {
+        lenient_amounts: true,
+        has_headers: false,
+        currency_symbol: "€".to_string(),
+        ..Default::default()
+    };
+    let mut source = CsvSource::new("withdrawal,1,3,\"€2,000.5\"\n".as_bytes(), &config);
+    assert_eq!(source.next().unwrap().unwrap().amount, Some(2000.5));
+
+    // Strict parsing is unchanged.
+    let mut source = CsvSource::new(input.as_bytes(), &ProcessingConfig::default());
+    assert!(source.next().unwrap().is_err());
+    assert_eq!(source.next().unwrap().unwrap().amount, Some(1250.0));
+}
+
+#[test]
+fn test_transaction_type_spellings() {
+    let input = "\
+type, client, tx, amount
+DEPOSIT, 1, 1, 2.0
+Withdrawal, 1, 2, 0.5
+Withdraw, 1, 3, 0.25
+dep, 1, 4, 1.0
+Open_Balance, 2, 5, 3.0
+";
+    let types = |config: &ProcessingConfig| -> Vec<Result<TransactionType, String>> {
+        let mut source = CsvSource::new(input.as_bytes(), config);
+        let mut types = Vec::new();
+        while let Some(result) = source.next() {
+            types.push(
+                result
+                    .map(|transaction| transaction.transaction_type)
+                    .map_err(|error| error.to_string()),
+            );
+        }
+        types
+    };
+    assert_eq!(
+        types(&ProcessingConfig::default()),
+        vec![
+            Ok(TransactionType::Deposit),
+            Ok(TransactionType::Withdrawal),
+            Ok(TransactionType::Withdrawal),
+            Ok(TransactionType::Deposit),
+            Ok(TransactionType::OpenBalance),
+        ]
+    );
+
+    // The alias table can be replaced, and names that are neither a type nor an alias are rejected.
+    let config = ProcessingConfig {
+        type_aliases: [("debit".to_string(), TransactionType::Withdrawal)]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let types = types(&config);
+    assert!(types[2]
+        .as_ref()
+        .unwrap_err()
+        .contains("unknown variant `Withdraw`"));
+    assert!(types[3].is_err());
+    let mut source = CsvSource::new(
+        "type,client,tx,amount
+DEBIT,1,1,1.0
+"
+        .as_bytes(),
+        &config,
+    );
+    assert_eq!(
+        source.next().unwrap().unwrap().transaction_type,
+        TransactionType::Withdrawal
+    );
+}
+
+#[test]
+fn test_blank_and_zero_amounts() {
+    use crate::client::*;
+    use crate::transaction_error::*;
+
+    let input = "\
+type, client, tx, amount
+deposit, 1, 1,
+deposit, 1, 2,    \t
+deposit, 1, 3,\"  \"
+deposit, 1, 4, 0
+withdrawal, 1, 5, 0.0
+";
+    for config in [
+        ProcessingConfig::default(),
+        ProcessingConfig {
+            lenient_amounts: true,
+            ..Default::default()
+        },
+    ] {
+        let mut source = CsvSource::new(input.as_bytes(), &config);
+        let mut error_codes = Vec::new();
+        while let Some(transaction) = source.next() {
+            let error = Client::new(1)
+                .apply_transaction_with_config(transaction.unwrap(), &config)
+                .unwrap_err();
+            error_codes.push(error.error_type.code());
+        }
+        // Blank and whitespace-only cells are missing amounts, while a zero is a present amount that is not positive.
+        assert_eq!(
+            error_codes,
+            [
+                TransactionErrorTypes::MissingRequiredAmount.code(),
+                TransactionErrorTypes::MissingRequiredAmount.code(),
+                TransactionErrorTypes::MissingRequiredAmount.code(),
+                TransactionErrorTypes::NonPositiveAmount.code(),
+                TransactionErrorTypes::NonPositiveAmount.code(),
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_json_lines_source() {
+    let input = "\
+{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 2.5, \"currency\": \"USD\"}
+
+{\"type\": \"dispute\", \"client\": 1, \"tx\": 1, \"amount\": null, \"timestamp\": 7}
+{\"type\": \"deposit\", \"client\": 70000, \"tx\": 2, \"amount\": 1.0}
+";
+    let mut source = JsonLinesSource::new(input.as_bytes());
+    assert_eq!(
+        source.next().unwrap().unwrap(),
+        Transaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            id: 1,
+            amount: Some(2.5),
+            currency: Some("USD".to_string()),
+            ..Default::default()
+        }
+    );
+    let dispute = source.next().unwrap().unwrap();
+    assert_eq!(dispute.amount, None);
+    assert_eq!(dispute.timestamp, Some(7));
+    let error = source.next().unwrap().unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("client id 70000 is out of range"));
+    assert!(source.next().is_none());
+}
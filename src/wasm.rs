@@ -0,0 +1,10 @@
+use wasm_bindgen::prelude::*;
+
+use crate::transaction_manager::*;
+
+/// Reads transactions from the given csv text with the default config, and returns the account csv as text.
+/// Any error is returned to JavaScript as a string message. Requires the `wasm` feature.
+#[wasm_bindgen]
+pub fn process_csv_string(input: &str) -> Result<String, JsValue> {
+    process_csv_str(input).map_err(|error| JsValue::from_str(&error.to_string()))
+}
@@ -0,0 +1,91 @@
+use proptest::prelude::*;
+
+use transaction_manager::client::*;
+use transaction_manager::transaction::*;
+
+/// A step in a generated transaction sequence for a single client.
+/// Reference steps pick one of the deposits or withdrawals applied so far by index, so they always reference a real transaction.
+#[derive(Clone, Debug)]
+enum Step {
+    Deposit(u32),
+    Withdrawal(u32),
+    Dispute(usize),
+    Resolve(usize),
+    Chargeback(usize),
+}
+
+/// Amounts are generated as whole cents, which shrink toward one cent and are exactly representable to four decimal places.
+fn amount_cents() -> impl Strategy<Value = u32> {
+    1u32..1_000_000
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        3 => amount_cents().prop_map(Step::Deposit),
+        2 => amount_cents().prop_map(Step::Withdrawal),
+        1 => any::<usize>().prop_map(Step::Dispute),
+        1 => any::<usize>().prop_map(Step::Resolve),
+        1 => any::<usize>().prop_map(Step::Chargeback),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn test_apply_invariants(steps in prop::collection::vec(step(), 1..60)) {
+        let mut client = Client::new(0);
+        let mut next_id: u32 = 0;
+        let mut applied_ids: Vec<u32> = Vec::new();
+        let mut locked_balances: Option<(f64, f64, f64)> = None;
+        for step in steps {
+            let referenced_id =
+                |index: usize| applied_ids.get(index % applied_ids.len().max(1)).copied();
+            let (transaction_type, id, amount) = match step {
+                Step::Deposit(cents) => {
+                    next_id += 1;
+                    (TransactionType::Deposit, next_id, Some(cents as f64 / 100.0))
+                }
+                Step::Withdrawal(cents) => {
+                    next_id += 1;
+                    (TransactionType::Withdrawal, next_id, Some(cents as f64 / 100.0))
+                }
+                Step::Dispute(index) => match referenced_id(index) {
+                    Some(id) => (TransactionType::Dispute, id, None),
+                    None => continue,
+                },
+                Step::Resolve(index) => match referenced_id(index) {
+                    Some(id) => (TransactionType::Resolve, id, None),
+                    None => continue,
+                },
+                Step::Chargeback(index) => match referenced_id(index) {
+                    Some(id) => (TransactionType::Chargeback, id, None),
+                    None => continue,
+                },
+            };
+            let creates_id = amount.is_some();
+            client = match client.clone().apply_transaction(Transaction {
+                transaction_type,
+                client_id: 0,
+                id,
+                amount,
+                ..Default::default()
+            }) {
+                Ok(client) => {
+                    if creates_id {
+                        applied_ids.push(id);
+                    }
+                    client
+                }
+                Err(_) => client,
+            };
+
+            prop_assert!(client.check_invariant(), "total != available + held: {:#?}", client);
+            prop_assert!(client.held >= 0.0, "negative held funds: {:#?}", client);
+            if let Some(balances) = locked_balances {
+                prop_assert!(client.locked);
+                prop_assert_eq!((client.available, client.held, client.total), balances);
+            } else if client.locked {
+                locked_balances = Some((client.available, client.held, client.total));
+            }
+        }
+    }
+}
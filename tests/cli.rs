@@ -0,0 +1,148 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+/// The account list produced from resources/transaction-list.csv.
+const SAMPLE_ACCOUNTS: &str = "\
+client,available,held,total,locked
+1,1.0,0.0,1.0,false
+2,0.0,3.3,3.3,false
+3,4.0,0.0,4.0,false
+4,5.0,0.0,5.0,true
+";
+
+#[test]
+fn test_output_to_file() {
+    let output_path = env::temp_dir().join("transaction-manager-cli-output.csv");
+    let status = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/transaction-list.csv")
+        .arg("--output")
+        .arg(&output_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&output_path).unwrap(), SAMPLE_ACCOUNTS);
+    fs::remove_file(&output_path).unwrap();
+}
+
+#[test]
+fn test_output_to_unopenable_file() {
+    let output_path = env::temp_dir()
+        .join("transaction-manager-missing-directory")
+        .join("accounts.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/transaction-list.csv")
+        .arg("--output")
+        .arg(&output_path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!output.stderr.is_empty());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_help() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("--help")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let usage = String::from_utf8(output.stdout).unwrap();
+    for flag in ["--input", "--output", "--format", "--strict", "--config"] {
+        assert!(usage.contains(flag), "usage is missing {}", flag);
+    }
+}
+
+#[test]
+fn test_missing_input() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("--strict")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("USAGE"));
+}
+
+#[test]
+fn test_input_flag() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("--input")
+        .arg("resources/transaction-list.csv")
+        .arg("--format")
+        .arg("csv")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), SAMPLE_ACCOUNTS);
+}
+
+#[test]
+fn test_malformed_input_exit_code() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/malformed-transaction-list.csv")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn test_summary_file() {
+    let summary_path = env::temp_dir().join("transaction-manager-cli-summary.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/transaction-list.csv")
+        .arg("--summary")
+        .arg(&summary_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), SAMPLE_ACCOUNTS);
+    assert_eq!(
+        fs::read_to_string(&summary_path).unwrap(),
+        "clients,available,held,total,locked_accounts\n4,10.0,3.3,13.3,1\n"
+    );
+    fs::remove_file(&summary_path).unwrap();
+}
+
+#[test]
+fn test_verify() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/transaction-list.csv")
+        .arg("--verify")
+        .arg("resources/expected-account-list.csv")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/transaction-list.csv")
+        .arg("--verify")
+        .arg("resources/mismatched-account-list.csv")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("accounts differ"));
+}
+
+#[test]
+fn test_max_clients() {
+    // resources/transaction-list.csv has four clients, so the second client's first deposit is rejected.
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction-manager"))
+        .arg("resources/transaction-list.csv")
+        .arg("--max-clients")
+        .arg("1")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("beyond the configured client limit"));
+    assert!(stderr.contains("client_id: 2"));
+}
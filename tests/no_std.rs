@@ -0,0 +1,20 @@
+use std::env;
+use std::process::Command;
+
+/// Build the library without the default std feature, as the README's no_std section documents,
+/// in its own target directory so it doesn't wait on the lock held by the running test build.
+#[test]
+fn test_builds_without_std() {
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--no-default-features", "--offline"])
+        .arg("--target-dir")
+        .arg(env::temp_dir().join("transaction-manager-no-std"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}